@@ -1,6 +1,6 @@
 use clap::{App, Arg, ArgMatches};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub width: usize,
     pub height: usize,
@@ -11,6 +11,19 @@ pub struct Config {
     pub interactive: bool,
     pub subcommand: Subcommand,
     pub print_forest_stats: bool,
+    pub reference_distance: f32,
+    pub env_map: Option<String>,
+    pub env_rotation: f32,
+    pub env_intensity: f32,
+    pub point_cloud: Option<String>,
+    pub point_radius: f32,
+    pub sky: bool,
+    pub sun_elevation: f32,
+    pub sun_azimuth: f32,
+    pub turbidity: f32,
+    pub light_samples: usize,
+    pub light_seed: u64,
+    pub spectral_samples: usize,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -83,6 +96,94 @@ pub fn configure_cli<'a, 'b>() -> App<'a, 'b> {
             .long("stats")
             .help("When using \"rayforest\" method, print out stats about the forest")
         )
+        .arg(
+            Arg::with_name("reference-distance")
+            .long("reference-distance")
+            .takes_value(true)
+            .default_value("1")
+            .help("The distance from each light at which the scene description reports the light's effective intensity")
+        )
+        .arg(
+            Arg::with_name("env-map")
+            .long("env-map")
+            .takes_value(true)
+            .help("Light the scene with an equirectangular Radiance .hdr or .pfm environment map")
+        )
+        .arg(
+            Arg::with_name("env-rotation")
+            .long("env-rotation")
+            .takes_value(true)
+            .default_value("0")
+            .help("Rotate the environment map around the vertical axis by this many degrees")
+        )
+        .arg(
+            Arg::with_name("env-intensity")
+            .long("env-intensity")
+            .takes_value(true)
+            .default_value("1")
+            .help("Scale the energy of the environment map")
+        )
+        .arg(
+            Arg::with_name("point-cloud")
+            .long("point-cloud")
+            .takes_value(true)
+            .help("Add the points of a .ply or .xyz point cloud to the scene")
+        )
+        .arg(
+            Arg::with_name("point-radius")
+            .long("point-radius")
+            .takes_value(true)
+            .default_value("0.02")
+            .help("Radius of each point when using \"--point-cloud\"")
+        )
+        .arg(
+            Arg::with_name("sky")
+            .long("sky")
+            .conflicts_with("env-map")
+            .help("Light the scene with a procedural daylight sky and a matching sun")
+        )
+        .arg(
+            Arg::with_name("sun-elevation")
+            .long("sun-elevation")
+            .takes_value(true)
+            .default_value("45")
+            .help("Degrees of the sun above the horizon when using \"--sky\"")
+        )
+        .arg(
+            Arg::with_name("sun-azimuth")
+            .long("sun-azimuth")
+            .takes_value(true)
+            .default_value("0")
+            .help("Degrees of the sun around the vertical axis, from +z towards +x, when using \"--sky\"")
+        )
+        .arg(
+            Arg::with_name("turbidity")
+            .long("turbidity")
+            .takes_value(true)
+            .default_value("3")
+            .help("Haziness of the sky when using \"--sky\": 2 is very clear and 10 is hazy")
+        )
+        .arg(
+            Arg::with_name("light-samples")
+            .long("light-samples")
+            .takes_value(true)
+            .default_value("0")
+            .help("Number of lights picked at each point from a light tree, 0 evaluates every light")
+        )
+        .arg(
+            Arg::with_name("light-seed")
+            .long("light-seed")
+            .takes_value(true)
+            .default_value("0")
+            .help("Seed used to pick lights when using \"--light-samples\"")
+        )
+        .arg(
+            Arg::with_name("spectral-samples")
+            .long("spectral-samples")
+            .takes_value(true)
+            .default_value("0")
+            .help("Number of wavelengths traced through each pixel in Basic mode, for dispersion. 0 renders in RGB")
+        )
         .subcommand(
             App::new("bench")
             .about("Runs benchmark tests to aid with performance testing and analysis")
@@ -146,6 +247,67 @@ pub fn parse_args(args: &ArgMatches) -> Config {
         }
     };
     let print_forest_stats = args.is_present("stats");
+    let reference_distance = args
+        .value_of("reference-distance")
+        .map(|s| {
+            s.parse::<f32>()
+                .expect("Expected number for reference distance")
+        })
+        .unwrap();
+    let env_map = args.value_of("env-map").map(String::from);
+    let env_rotation = args
+        .value_of("env-rotation")
+        .map(|s| {
+            s.parse::<f32>()
+                .expect("Expected number for environment rotation")
+        })
+        .unwrap();
+    let env_intensity = args
+        .value_of("env-intensity")
+        .map(|s| {
+            s.parse::<f32>()
+                .expect("Expected number for environment intensity")
+        })
+        .unwrap();
+    let point_cloud = args.value_of("point-cloud").map(String::from);
+    let point_radius = args
+        .value_of("point-radius")
+        .map(|s| s.parse::<f32>().expect("Expected number for point radius"))
+        .unwrap();
+    let sky = args.is_present("sky");
+    let sun_elevation = args
+        .value_of("sun-elevation")
+        .map(|s| s.parse::<f32>().expect("Expected number for sun elevation"))
+        .unwrap();
+    let sun_azimuth = args
+        .value_of("sun-azimuth")
+        .map(|s| s.parse::<f32>().expect("Expected number for sun azimuth"))
+        .unwrap();
+    let turbidity = args
+        .value_of("turbidity")
+        .map(|s| s.parse::<f32>().expect("Expected number for turbidity"))
+        .unwrap();
+    let light_samples = args
+        .value_of("light-samples")
+        .map(|s| {
+            s.parse::<usize>()
+                .expect("Expected integer for light samples")
+        })
+        .unwrap();
+    let light_seed = args
+        .value_of("light-seed")
+        .map(|s| s.parse::<u64>().expect("Expected integer for light seed"))
+        .unwrap();
+    let spectral_samples = args
+        .value_of("spectral-samples")
+        .map(|s| {
+            s.parse::<usize>()
+                .expect("Expected integer for spectral samples")
+        })
+        .unwrap();
+    if spectral_samples > 0 && method == Method::RayForest {
+        panic!("`--spectral-samples` can only be used with `--method basic`");
+    }
 
     let subcommand = args
         .subcommand_matches("bench")
@@ -171,5 +333,18 @@ pub fn parse_args(args: &ArgMatches) -> Config {
         interactive,
         subcommand,
         print_forest_stats,
+        reference_distance,
+        env_map,
+        env_rotation,
+        env_intensity,
+        point_cloud,
+        point_radius,
+        sky,
+        sun_elevation,
+        sun_azimuth,
+        turbidity,
+        light_samples,
+        light_seed,
+        spectral_samples,
     }
 }
@@ -57,7 +57,7 @@ pub mod gtk_gui {
         app.connect_activate(move |app| {
             build_gui(
                 app,
-                config,
+                config.clone(),
                 scene.clone(),
                 forest.clone(),
                 mutated_shapes.clone(),
@@ -87,18 +87,18 @@ pub mod gtk_gui {
 
         let buffer = Rc::clone(&buffer);
         let render_box =
-            build_render_view(config, Rc::clone(&scene), forest, mutated_shapes, buffer);
+            build_render_view(config.clone(), Rc::clone(&scene), forest, mutated_shapes, buffer);
         let title = "Render";
         notebook.create_tab(title, render_box.upcast());
 
-        let scene_desc = build_scene_description_view(&scene.borrow());
+        let scene_desc = build_scene_description_view(config, &scene.borrow());
         let title = "Scene";
         notebook.create_tab(title, scene_desc.upcast());
 
         window.show_all();
     }
 
-    fn build_scene_description_view(scene: &Scene) -> gtk::TextView {
+    fn build_scene_description_view(config: Config, scene: &Scene) -> gtk::TextView {
         let text = gtk::TextView::new();
         text.set_editable(false);
         match text.get_buffer() {
@@ -109,9 +109,20 @@ pub mod gtk_gui {
                 // Print Ambient Light
                 text = format!("Ambient Light: {:?}\n", scene.ambient());
 
+                // Print the background
+                if let Some(bg) = scene.get_background() {
+                    text = text + &format!("Background: {}\n", bg.to_string());
+                }
+
                 // Print lights
                 for light in scene.lights() {
-                    text = text + &format!("Light: {}\n", light.to_string());
+                    text = text
+                        + &format!(
+                            "Light: {}, Intensity at {}: {}\n",
+                            light.to_string(),
+                            config.reference_distance,
+                            light.intensity_at(config.reference_distance)
+                        );
                 }
 
                 // Print shapes
@@ -22,7 +22,10 @@ use cli::*;
 use my_scene::*;
 use render::*;
 use render_tree::RayForest;
-use scene::{Renderable, Scene};
+use scene::{
+    colors, load_point_cloud, EnvironmentLight, EnvironmentMap, LightSampling, Phong, PointCloud,
+    PreethamSky, Renderable, Scene, MAX_WAVELENGTH, MIN_WAVELENGTH,
+};
 
 fn main() {
     let cargs = configure_cli().get_matches();
@@ -32,6 +35,21 @@ fn main() {
     println!("Create Scene");
     let mut scene = Scene::new();
     create_scene(&mut scene);
+    if let Some(ref path) = config.env_map {
+        add_environment(&config, &mut scene, path);
+    }
+    if config.sky {
+        add_sky(&config, &mut scene);
+    }
+    if let Some(ref path) = config.point_cloud {
+        add_point_cloud(&config, &mut scene, path);
+    }
+    if config.light_samples > 0 {
+        scene.set_light_sampling(LightSampling::Stochastic {
+            samples: config.light_samples,
+            seed: config.light_seed,
+        });
+    }
     let scene = Rc::new(RefCell::new(scene));
     println!("Done Creating Scene");
 
@@ -42,6 +60,40 @@ fn main() {
     }
 }
 
+fn add_environment(config: &Config, scene: &mut Scene, path: &str) {
+    let mut env = EnvironmentMap::open(path).expect("Failed to load environment map");
+    env.set_rotation(config.env_rotation);
+    env.set_intensity(config.env_intensity);
+
+    let env = Rc::new(env);
+    scene.set_background(env.clone());
+    scene.add_light(Box::new(EnvironmentLight::new(env)));
+}
+
+fn add_point_cloud(config: &Config, scene: &mut Scene, path: &str) {
+    let points = load_point_cloud(path).expect("Failed to load point cloud");
+    println!("Loaded {} points", points.len());
+
+    // Points without a color of their own are white
+    let material = Rc::new(RefCell::new(Phong::new(
+        colors::BLACK,
+        colors::WHITE,
+        colors::WHITE,
+        60.,
+        0.,
+        0.,
+    )));
+    let mut cloud = PointCloud::new(points, config.point_radius, material);
+    cloud.set_name("point-cloud");
+    scene.add_shape(Box::new(cloud));
+}
+
+fn add_sky(config: &Config, scene: &mut Scene) {
+    let sky = PreethamSky::new(config.sun_elevation, config.sun_azimuth, config.turbidity);
+    scene.add_light(Box::new(sky.sun_light(1.)));
+    scene.set_background(Rc::new(sky));
+}
+
 fn handle_normal_mode(config: Config, scene: Rc<RefCell<Scene>>) {
     if config.interactive {
         enter_to_proceed();
@@ -248,7 +300,21 @@ fn render_scene_basic(config: &Config, scene: &Scene) -> RenderBuffer {
     let mut buffer = RenderBuffer::new(x_res, y_res);
 
     let start = std::time::Instant::now();
-    render::render(&camera, &scene, &mut buffer, config.depth);
+    if config.spectral_samples > 0 {
+        println!(
+            "Rendering {} wavelengths from {}nm to {}nm",
+            config.spectral_samples, MIN_WAVELENGTH, MAX_WAVELENGTH
+        );
+        render::render_spectral(
+            &camera,
+            scene,
+            &mut buffer,
+            config.depth,
+            config.spectral_samples,
+        );
+    } else {
+        render::render(&camera, &scene, &mut buffer, config.depth);
+    }
     let duration = start.elapsed();
     println!("render_scene: {}ms", duration.as_millis());
 
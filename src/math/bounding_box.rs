@@ -0,0 +1,182 @@
+use super::point::Point3;
+use super::{Matrix, Ray, Vector3};
+
+/// An axis aligned bounding box
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct BoundingBox {
+    min: Point3,
+    max: Point3,
+}
+
+impl BoundingBox {
+    pub fn new(min: &Point3, max: &Point3) -> BoundingBox {
+        BoundingBox {
+            min: Point3::new(min.x().min(max.x()), min.y().min(max.y()), min.z().min(max.z())),
+            max: Point3::new(min.x().max(max.x()), min.y().max(max.y()), min.z().max(max.z())),
+        }
+    }
+
+    /// A box which contains nothing.  Growing it by a point will create a box
+    /// which contains only that point.
+    pub fn empty() -> BoundingBox {
+        use std::f32::{INFINITY, NEG_INFINITY};
+        BoundingBox {
+            min: Point3::new(INFINITY, INFINITY, INFINITY),
+            max: Point3::new(NEG_INFINITY, NEG_INFINITY, NEG_INFINITY),
+        }
+    }
+
+    pub fn min(&self) -> Point3 {
+        self.min
+    }
+
+    pub fn max(&self) -> Point3 {
+        self.max
+    }
+
+    pub fn center(&self) -> Point3 {
+        Point3::new(
+            0.5 * (self.min.x() + self.max.x()),
+            0.5 * (self.min.y() + self.max.y()),
+            0.5 * (self.min.z() + self.max.z()),
+        )
+    }
+
+    pub fn extent(&self) -> Vector3 {
+        self.max - self.min
+    }
+
+    /// Returns the index (0 = x, 1 = y, 2 = z) of the longest side
+    pub fn longest_axis(&self) -> usize {
+        let e = self.extent();
+        if e.x() >= e.y() && e.x() >= e.z() {
+            0
+        } else if e.y() >= e.z() {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let e = self.extent();
+        2. * (e.x() * e.y() + e.y() * e.z() + e.z() * e.x())
+    }
+
+    /// Returns a box that contains both this box and `p`
+    pub fn grow(&self, p: &Point3) -> BoundingBox {
+        BoundingBox {
+            min: Point3::new(self.min.x().min(p.x()), self.min.y().min(p.y()), self.min.z().min(p.z())),
+            max: Point3::new(self.max.x().max(p.x()), self.max.y().max(p.y()), self.max.z().max(p.z())),
+        }
+    }
+
+    /// Returns a box that contains both this box and `other`
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        self.grow(&other.min).grow(&other.max)
+    }
+
+    /// Returns a box which contains the corners of this box after they are
+    /// transformed by `m`
+    pub fn transform(&self, m: &Matrix) -> BoundingBox {
+        let (lo, hi) = (self.min, self.max);
+        (0..8).fold(BoundingBox::empty(), |b, i| {
+            let corner = Point3::new(
+                if i & 1 == 0 { lo.x() } else { hi.x() },
+                if i & 2 == 0 { lo.y() } else { hi.y() },
+                if i & 4 == 0 { lo.z() } else { hi.z() },
+            );
+            b.grow(&(*m * corner))
+        })
+    }
+
+    /// Returns a box whose sides have all been moved outward by `d`
+    pub fn pad(&self, d: f32) -> BoundingBox {
+        let d = Vector3::new(d, d, d);
+        BoundingBox {
+            min: self.min + -d,
+            max: self.max + d,
+        }
+    }
+
+    /// Uses the slab method to find where `ray` enters and leaves the box.
+    /// Returns the ray parameters of the entry and exit points; the entry
+    /// parameter is negative if the ray starts inside of the box.
+    pub fn intersect(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let o = ray.origin();
+        let d = ray.direction();
+
+        let mut t_near = std::f32::NEG_INFINITY;
+        let mut t_far = std::f32::INFINITY;
+        for &(o, d, min, max) in [
+            (o.x(), d.x(), self.min.x(), self.max.x()),
+            (o.y(), d.y(), self.min.y(), self.max.y()),
+            (o.z(), d.z(), self.min.z(), self.max.z()),
+        ]
+        .iter()
+        {
+            if d.abs() < std::f32::EPSILON {
+                // Parallel to the slab: either always inside it or never
+                if o < min || o > max {
+                    return None;
+                }
+            } else {
+                let inv_d = 1. / d;
+                let t0 = (min - o) * inv_d;
+                let t1 = (max - o) * inv_d;
+                let (t0, t1) = if t0 > t1 { (t1, t0) } else { (t0, t1) };
+                t_near = t_near.max(t0);
+                t_far = t_far.min(t1);
+                if t_near > t_far {
+                    return None;
+                }
+            }
+        }
+
+        if t_far < 0. {
+            None
+        } else {
+            Some((t_near, t_far))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intersect() {
+        let bb = BoundingBox::new(&Point3::new(-1., -1., -1.), &Point3::new(1., 1., 1.));
+
+        let ray = Ray::new(&Point3::new(0., 0., -4.), &Vector3::new(0., 0., 1.));
+        assert_eq!(Some((3., 5.)), bb.intersect(&ray));
+
+        let inside = Ray::new(&Point3::new(0., 0., 0.), &Vector3::new(0., 0., 1.));
+        assert_eq!(Some((-1., 1.)), bb.intersect(&inside));
+
+        let behind = Ray::new(&Point3::new(0., 0., 4.), &Vector3::new(0., 0., 1.));
+        assert_eq!(None, bb.intersect(&behind));
+
+        let miss = Ray::new(&Point3::new(0., 2., -4.), &Vector3::new(0., 0., 1.));
+        assert_eq!(None, bb.intersect(&miss));
+    }
+
+    #[test]
+    fn grow() {
+        let bb = BoundingBox::empty()
+            .grow(&Point3::new(1., 2., 3.))
+            .grow(&Point3::new(-1., 0., 4.));
+        assert_eq!(Point3::new(-1., 0., 3.), bb.min());
+        assert_eq!(Point3::new(1., 2., 4.), bb.max());
+        assert_eq!(0, bb.longest_axis());
+    }
+    #[test]
+    fn transform() {
+        let bb = BoundingBox::new(&Point3::new(-1., -1., -1.), &Point3::new(1., 1., 1.));
+        let m = Matrix::translate(0., 4., 0.) * Matrix::scale(2., 1., 1.);
+        let moved = bb.transform(&m);
+        assert_eq!(Point3::new(-2., 3., -1.), moved.min());
+        assert_eq!(Point3::new(2., 5., 1.), moved.max());
+    }
+}
@@ -1,11 +1,17 @@
+mod bounding_box;
 mod matrix;
+mod noise;
 mod point;
+mod random;
 mod ray;
 mod vector3;
 mod vector4;
 
+pub use bounding_box::BoundingBox;
 pub use matrix::Matrix;
+pub use noise::Perlin;
 pub use point::Point3;
+pub use random::{stratified_samples, Random};
 pub use ray::Ray;
 pub use vector3::Vector3;
 pub use vector4::Vector4;
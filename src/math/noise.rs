@@ -0,0 +1,158 @@
+/// Gradient noise for procedural textures
+use super::{Point3, Random};
+
+/**
+Ken Perlin's improved gradient noise in three dimensions.  The lattice
+permutation is shuffled from a seed, so different seeds give unrelated
+patterns while the same seed always gives the same one.
+
+`noise` is smooth, zero on every point of the integer lattice and stays
+roughly within [-1, 1].  Features are about one unit across, so scale the
+point to make them smaller or larger.
+*/
+#[derive(Clone)]
+pub struct Perlin {
+    perm: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut p: Vec<u8> = (0..=255).collect();
+        let mut rng = Random::new(seed);
+        for i in (1..256).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            p.swap(i, j);
+        }
+
+        // Repeat the permutation so that lookups never need to wrap
+        let mut perm = [0; 512];
+        for (i, v) in perm.iter_mut().enumerate() {
+            *v = p[i & 255];
+        }
+        Perlin { perm }
+    }
+
+    pub fn noise(&self, p: &Point3) -> f32 {
+        let (xf, yf, zf) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let xi = (xf as i64 & 255) as usize;
+        let yi = (yf as i64 & 255) as usize;
+        let zi = (zf as i64 & 255) as usize;
+        let (x, y, z) = (p.x() - xf, p.y() - yf, p.z() - zf);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        // Hash each of the eight corners of the cell
+        let perm = &self.perm;
+        let a = perm[xi] as usize + yi;
+        let aa = perm[a] as usize + zi;
+        let ab = perm[a + 1] as usize + zi;
+        let b = perm[xi + 1] as usize + yi;
+        let ba = perm[b] as usize + zi;
+        let bb = perm[b + 1] as usize + zi;
+
+        let near = lerp(
+            v,
+            lerp(u, grad(perm[aa], x, y, z), grad(perm[ba], x - 1., y, z)),
+            lerp(u, grad(perm[ab], x, y - 1., z), grad(perm[bb], x - 1., y - 1., z)),
+        );
+        let far = lerp(
+            v,
+            lerp(
+                u,
+                grad(perm[aa + 1], x, y, z - 1.),
+                grad(perm[ba + 1], x - 1., y, z - 1.),
+            ),
+            lerp(
+                u,
+                grad(perm[ab + 1], x, y - 1., z - 1.),
+                grad(perm[bb + 1], x - 1., y - 1., z - 1.),
+            ),
+        );
+        lerp(w, near, far)
+    }
+
+    /// Fractal Brownian motion: `octaves` layers of noise, each with twice
+    /// the frequency and half the amplitude of the one before.  The sum is
+    /// normalized to stay roughly within [-1, 1].
+    pub fn fbm(&self, p: &Point3, octaves: usize) -> f32 {
+        self.octaves(p, octaves, |n| n)
+    }
+
+    /// Like `fbm` but summing the absolute value of each octave, which
+    /// gives the creases of turbulent flow.  Stays within [0, 1].
+    pub fn turbulence(&self, p: &Point3, octaves: usize) -> f32 {
+        self.octaves(p, octaves, f32::abs)
+    }
+
+    fn octaves(&self, p: &Point3, octaves: usize, f: impl Fn(f32) -> f32) -> f32 {
+        let (mut sum, mut total, mut amplitude, mut frequency) = (0., 0., 1., 1.);
+        for _ in 0..octaves.max(1) {
+            sum += amplitude * f(self.noise(&(frequency * *p)));
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.;
+        }
+        sum / total
+    }
+}
+
+// 6t^5 - 15t^4 + 10t^3, whose first and second derivatives are zero at 0
+// and 1 so that cells join smoothly
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+// The dot product of the offset to a corner with one of twelve gradients
+// pointing to the edges of a cube, picked by the hash of the corner
+fn grad(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise() {
+        let perlin = Perlin::new(1);
+        assert_eq!(0., perlin.noise(&Point3::new(3., -2., 7.)));
+
+        let mut rng = Random::new(2);
+        for _ in 0..1000 {
+            let p = Point3::new(
+                20. * rng.next_f32() - 10.,
+                20. * rng.next_f32() - 10.,
+                20. * rng.next_f32() - 10.,
+            );
+            let n = perlin.noise(&p);
+            assert!(n.abs() <= 1.1);
+
+            // Smooth: a small step gives a small change
+            let q = Point3::new(p.x() + 0.001, p.y(), p.z());
+            assert!((perlin.noise(&q) - n).abs() < 0.01);
+
+            let t = perlin.turbulence(&p, 4);
+            assert!((0. ..=1.).contains(&t));
+            assert!(perlin.fbm(&p, 4).abs() <= 1.1);
+        }
+    }
+
+    #[test]
+    fn seeds() {
+        let p = Point3::new(0.3, 1.7, -2.4);
+        assert_eq!(Perlin::new(7).noise(&p), Perlin::new(7).noise(&p));
+        assert!(Perlin::new(7).noise(&p) != Perlin::new(8).noise(&p));
+    }
+}
@@ -0,0 +1,122 @@
+use super::point::Point3;
+
+/// A small seedable pseudo random number generator (xorshift64*).  Renders
+/// need to be reproducible, so everything which samples randomly takes one
+/// of these with a known seed rather than using a global source.
+#[derive(Debug, Copy, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        // Scramble the seed so that nearby seeds give unrelated sequences,
+        // and make sure the state is never zero.
+        let state = splitmix64(seed);
+        Random {
+            state: if state == 0 {
+                0x9E37_79B9_7F4A_7C15
+            } else {
+                state
+            },
+        }
+    }
+
+    /// Seed a generator from a position, so that sampling at the same point
+    /// always gives the same sequence.
+    pub fn from_point(p: &Point3, seed: u64) -> Random {
+        let h = (p.x().to_bits() as u64)
+            ^ (p.y().to_bits() as u64).rotate_left(21)
+            ^ (p.z().to_bits() as u64).rotate_left(42);
+        Random::new(h ^ seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a number in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        // Use the top 24 bits so that every value is exactly representable
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Split the unit square into `n` cells and return a randomly jittered
+/// point inside of each cell.  The cells form a grid of the two factors of
+/// `n` closest to each other, so they always cover the whole square.
+pub fn stratified_samples(n: usize, rng: &mut Random) -> Vec<(f32, f32)> {
+    if n == 0 {
+        return vec![];
+    }
+
+    let mut rows = (n as f32).sqrt() as usize;
+    while !n.is_multiple_of(rows) {
+        rows -= 1;
+    }
+    let cols = n / rows;
+    (0..n)
+        .map(|i| {
+            let (col, row) = (i % cols, i / cols);
+            let u = (col as f32 + rng.next_f32()) / cols as f32;
+            let v = (row as f32 + rng.next_f32()) / rows as f32;
+            (u, v)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproducible() {
+        let mut a = Random::new(42);
+        let mut b = Random::new(42);
+        let mut c = Random::new(43);
+        for _ in 0..10 {
+            let x = a.next_f32();
+            assert!((0. ..1.).contains(&x));
+            assert_eq!(x, b.next_f32());
+        }
+        assert_ne!(a.next_u64(), c.next_u64());
+    }
+
+    #[test]
+    fn stratified() {
+        let mut rng = Random::new(1);
+        let samples = stratified_samples(4, &mut rng);
+        assert_eq!(4, samples.len());
+
+        // One sample in each quadrant
+        for (i, (u, v)) in samples.iter().enumerate() {
+            let (col, row) = ((i % 2) as f32, (i / 2) as f32);
+            assert!(*u >= 0.5 * col && *u < 0.5 * (col + 1.));
+            assert!(*v >= 0.5 * row && *v < 0.5 * (row + 1.));
+        }
+    }
+
+    #[test]
+    fn stratified_fills_square() {
+        let mut rng = Random::new(2);
+        for &(n, rows, cols) in &[(8, 2, 4), (5, 1, 5), (9, 3, 3), (12, 3, 4)] {
+            let samples = stratified_samples(n, &mut rng);
+            let mut cells: Vec<usize> = samples
+                .iter()
+                .map(|(u, v)| (v * rows as f32) as usize * cols + (u * cols as f32) as usize)
+                .collect();
+            cells.sort_unstable();
+            assert_eq!((0..n).collect::<Vec<_>>(), cells);
+        }
+    }
+}
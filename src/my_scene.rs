@@ -1,17 +1,19 @@
 use std::cell::RefCell;
+use std::f32::consts::PI;
 use std::rc::Rc;
 
 use super::math::{Matrix, Point3, Vector3};
 use super::scene::colors::*;
 use super::scene::Sphere;
 use super::scene::{
-    Color, Cube, Phong, Plane, PointLight, Renderable, Scene, TextureCoords, TexturePhong,
+    Attenuation, Background, Checkerboard, CloudPoint, Color, Cube, DirectionalLight, DiskLight,
+    Dispersion, Emissive, EnvironmentLight, EnvironmentMap, Gloss, GlossyLobe, Gobo, Gradient,
+    Grid, HdrImage, Heightfield, Instance, Marble, Mix, Multiply, NoiseTexture, PbrMaterial, Phong,
+    Plane, PointCloud, PointLight, PointShape, PreethamSky, RectLight, Renderable, Scene, Sdf,
+    SdfShape, SphereLight, SpotLight, Stripes, Texture, TextureCoords, TexturePhong, Triangle,
+    UvTransform, Wood,
 };
 
-fn dim_white(_: TextureCoords) -> Color {
-    0.1 * WHITE
-}
-
 const DIM_WHITE: Color = Color {
     r: 0.1,
     g: 0.1,
@@ -23,26 +25,10 @@ const DIM_BLUE: Color = Color {
     b: 0.1,
 };
 
-fn checkerboard(tx: TextureCoords) -> Color {
-    let u = (tx.0).abs() as i32;
-    let v = (tx.1).abs() as i32;
-
-    if tx.0 < 0. && tx.1 < 0. || tx.0 > 0. && tx.1 > 0. {
-        if u % 2 == v % 2 {
-            WHITE
-        } else {
-            0.5 * WHITE
-        }
-    } else {
-        if u % 2 != v % 2 {
-            WHITE
-        } else {
-            0.5 * WHITE
-        }
-    }
-}
-
 pub fn create_scene(scene: &mut Scene) {
+    let dim_white: Rc<dyn Texture> = Rc::new(DIM_WHITE);
+    let checkerboard: Rc<dyn Texture> = Rc::new(Checkerboard::new(WHITE, 0.5 * WHITE));
+
     let phong = Rc::new(RefCell::new(Phong::new(
         DIM_WHITE, RED, WHITE, 60., 0.5, 0.,
     )));
@@ -61,18 +47,37 @@ pub fn create_scene(scene: &mut Scene) {
     let sph2 = Box::new(sph2);
     scene.add_shape(sph2);
 
-    let phong = Rc::new(RefCell::new(Phong::new(
-        BLACK, WHITE, WHITE, 60., 0.7, 1.333,
-    )));
-    let mut sph4 = Sphere::new(phong);
+    // Glass which splits white light into colors in spectral renders
+    let mut glass = Phong::new(BLACK, WHITE, WHITE, 60., 0.7, 1.333);
+    glass.dispersion = Some(Dispersion::SF11);
+    let mut sph4 = Sphere::new(Rc::new(RefCell::new(glass)));
     let transform = Matrix::translate(0., -0.5, -3.) * Matrix::scale(0.6, 0.6, 0.6);
     sph4.set_transform(&transform);
     scene.add_shape(Box::new(sph4));
 
+    // The back wall is papered with diagonal stripes which fade into tiles
+    // towards the floor
+    let stripes: Rc<dyn Texture> = Rc::new(UvTransform::new(
+        Rc::new(Stripes::new(WHITE, 0.7 * WHITE)),
+        (0.5, 0.5),
+        45.,
+        (0., 0.),
+    ));
+    let tiles: Rc<dyn Texture> = Rc::new(Grid::new(0.4 * WHITE, WHITE, 0.05));
+    let fade: Rc<dyn Texture> = Rc::new(UvTransform::new(
+        Rc::new(Gradient::new(BLACK, WHITE)),
+        (5., 1.),
+        0.,
+        (-3., 0.),
+    ));
+    let wallpaper = Rc::new(Multiply::new(
+        Rc::new(Mix::new(stripes, tiles, fade)),
+        Rc::new(Color::new(0.9, 0.8, 0.6)),
+    ));
     let plane_material = Rc::new(RefCell::new(TexturePhong::new(
-        dim_white,
-        checkerboard,
-        dim_white,
+        dim_white.clone(),
+        wallpaper,
+        dim_white.clone(),
         60.,
         0.,
         0.,
@@ -85,9 +90,9 @@ pub fn create_scene(scene: &mut Scene) {
     scene.add_shape(Box::new(plane));
 
     let plane_material = Rc::new(RefCell::new(TexturePhong::new(
-        dim_white,
-        checkerboard,
-        dim_white,
+        dim_white.clone(),
+        checkerboard.clone(),
+        dim_white.clone(),
         60.,
         0.,
         0.,
@@ -99,13 +104,155 @@ pub fn create_scene(scene: &mut Scene) {
     );
     scene.add_shape(Box::new(plane));
 
-    let cube_material = Rc::new(RefCell::new(Phong::new(
-        BLACK, WHITE, WHITE, 60., 0., 1.333,
-    )));
+    // A block of frosted glass
+    let mut frosted = Phong::new(BLACK, WHITE, WHITE, 60., 0., 1.333);
+    frosted.gloss = Gloss::new(0.2, 2, GlossyLobe::Ggx);
+    let cube_material = Rc::new(RefCell::new(frosted));
     let mut cube = Cube::new(cube_material);
     let transform = Matrix::translate(-1., -1.0, -4.) * Matrix::rotate_x(-45.0);
     cube.set_transform(&transform);
     scene.add_shape(Box::new(cube));
+
+    // A row of copies of one shared sphere, each with its own material
+    let bead = Rc::new(Sphere::new(Rc::new(RefCell::new(Phong::new(
+        BLACK, WHITE, WHITE, 60., 0., 0.,
+    )))));
+    for (k, color) in [RED, GREEN, BLUE].iter().enumerate() {
+        let mut instance = Instance::new(bead.clone());
+        instance.set_material(Rc::new(RefCell::new(Phong::new(
+            BLACK, *color, WHITE, 60., 0.2, 0.,
+        ))));
+        let transform =
+            Matrix::translate(1.2 + 0.6 * k as f32, 2., 1.5) * Matrix::scale(0.25, 0.25, 0.25);
+        instance.set_transform(&transform);
+        scene.add_shape(Box::new(instance));
+    }
+
+    // A ring with a bead at its center, traced as a signed distance field
+    let sdf = Sdf::smooth_union(Sdf::torus(0.5, 0.12), Sdf::sphere(0.2), 0.1);
+    let sdf_material = Rc::new(RefCell::new(Phong::new(
+        BLACK,
+        Color::new(1., 0.8, 0.2),
+        WHITE,
+        60.,
+        0.3,
+        0.,
+    )));
+    let mut ring = SdfShape::new(sdf, sdf_material);
+    let transform = Matrix::translate(-2., 1.8, 1.) * Matrix::rotate_x(70.);
+    ring.set_transform(&transform);
+    scene.add_shape(Box::new(ring));
+
+    // Rolling hills standing on the floor
+    let (columns, rows) = (16, 16);
+    let heights: Vec<f32> = (0..rows)
+        .flat_map(|r| {
+            (0..columns).map(move |c| {
+                let (x, z) = (c as f32 / 15., r as f32 / 15.);
+                0.5 + 0.5 * (6. * x).sin() * (4. * z).cos()
+            })
+        })
+        .collect();
+    let grass = NoiseTexture::new(3, Color::new(0.3, 0.6, 0.2), Color::new(0.4, 0.3, 0.1), 3.);
+    let hill_material = Rc::new(RefCell::new(TexturePhong::new(
+        Rc::new(BLACK),
+        Rc::new(grass),
+        dim_white.clone(),
+        60.,
+        0.,
+        0.,
+    )));
+    let mut hills = Heightfield::new(heights, columns, rows, 2.5, 2.5, 0.6, hill_material);
+    hills.set_transform(&Matrix::translate(1.8, -2., 0.5));
+    scene.add_shape(Box::new(hills));
+
+    // A marble triangle hung in front of the back wall
+    let marble = Marble::new(
+        7,
+        Color::new(0.9, 0.85, 0.95),
+        Color::new(0.4, 0.1, 0.5),
+        2.,
+    );
+    let triangle_material = Rc::new(RefCell::new(TexturePhong::new(
+        Rc::new(BLACK),
+        Rc::new(marble),
+        dim_white.clone(),
+        60.,
+        0.,
+        0.,
+    )));
+    let triangle = Triangle::new(
+        &Point3::new(-3., -1.5, 1.9),
+        &Point3::new(-2.4, 0., 1.9),
+        &Point3::new(-1.8, -1.5, 1.9),
+        triangle_material,
+    );
+    scene.add_shape(Box::new(triangle));
+
+    // A wooden block on the floor to the right
+    let wood = Wood::new(
+        11,
+        Color::new(0.8, 0.6, 0.35),
+        Color::new(0.45, 0.28, 0.12),
+        8.,
+    );
+    let block_material = Rc::new(RefCell::new(TexturePhong::new(
+        Rc::new(BLACK),
+        Rc::new(wood),
+        dim_white.clone(),
+        30.,
+        0.,
+        0.,
+    )));
+    let mut block = Cube::new(block_material);
+    block.set_transform(
+        &(Matrix::translate(1.5, -1.75, -3.)
+            * Matrix::rotate_y(30.)
+            * Matrix::scale(0.5, 0.5, 0.5)),
+    );
+    scene.add_shape(Box::new(block));
+
+    // A helix of colored points, each drawn as a disk facing away from the
+    // axis of the helix
+    let points: Vec<CloudPoint> = (0..240)
+        .map(|k| {
+            let t = k as f32 * 0.1;
+            let normal = Vector3::new(t.cos(), 0., t.sin());
+            CloudPoint {
+                position: Point3::new(0.35 * t.cos(), 0.1 * t, 0.35 * t.sin()),
+                normal: Some(normal),
+                color: Some(Color::new(
+                    0.5 + 0.5 * t.cos(),
+                    0.5 + 0.5 * (t + 2.1).cos(),
+                    0.5 + 0.5 * (t + 4.2).cos(),
+                )),
+            }
+        })
+        .collect();
+    let cloud_material = Rc::new(RefCell::new(Phong::new(
+        BLACK, WHITE, DIM_WHITE, 60., 0., 0.,
+    )));
+    let mut helix = PointCloud::new(points, 0.06, cloud_material);
+    helix.set_shape(PointShape::Disk);
+    helix.set_transform(&Matrix::translate(2.3, -2., -1.5));
+    scene.add_shape(Box::new(helix));
+
+    // A glowing orange lantern sitting on the floor to the left
+    let glow = Rc::new(RefCell::new(Emissive::new(Color::new(1., 0.6, 0.2), 4.)));
+    let mut lantern = Sphere::new_with_name("lantern", glow);
+    lantern.set_transform(&(Matrix::translate(-2.3, -1.7, -1.5) * Matrix::scale(0.3, 0.3, 0.3)));
+    scene.add_emissive_shape(Box::new(lantern));
+
+    // A ball of rough copper
+    let copper = Rc::new(RefCell::new(PbrMaterial::new(
+        Color::new(0.95, 0.64, 0.54),
+        1.,
+        0.35,
+    )));
+    let mut metal = Sphere::new_with_name("copper", copper);
+    metal.set_transform(&(Matrix::translate(-2.4, -0.2, 0.8) * Matrix::scale(0.5, 0.5, 0.5)));
+    scene.add_shape(Box::new(metal));
+
     let light = PointLight::new(Point3::new(4., 4.0, 0.), Color::new(1., 0., 0.));
     scene.add_light(Box::new(light));
 
@@ -115,6 +262,101 @@ pub fn create_scene(scene: &mut Scene) {
     let light = PointLight::new(Point3::new(0., 8.0, -4.), Color::new(0., 0., 1.));
     scene.add_light(Box::new(light));
 
+    // A dim, warm sun shining down past the camera into the scene
+    let sun = DirectionalLight::new(Vector3::new(0.4, -1., 0.6), Color::new(0.3, 0.28, 0.24));
+    scene.add_light(Box::new(sun));
+
+    // A spot light projecting bars onto the floor in front of the shapes
+    let bars = |(u, _): TextureCoords| {
+        if (6. * u).fract() < 0.5 {
+            WHITE
+        } else {
+            0.2 * WHITE
+        }
+    };
+    let mut spot = SpotLight::new(
+        Point3::new(-1.5, 3., -3.),
+        Vector3::new(0., -1., 0.2),
+        WHITE,
+        15.,
+        25.,
+    );
+    spot.set_gobo(Gobo::new(Rc::new(bars)));
+    spot.set_intensity(2.);
+    spot.set_attenuation(Attenuation::Custom {
+        constant: 1.,
+        linear: 0.,
+        quadratic: 0.04,
+    });
+    scene.add_light(Box::new(spot));
+
+    // A lamp low over the hills which fades with the square of distance
+    let lamp = PointLight::new_physical(Point3::new(1.8, -1., -1.), Color::new(1., 0.8, 0.5), 2.);
+    scene.add_light(Box::new(lamp));
+
+    // Soft light from a panel overhead, a disk to the right and a small
+    // glowing ball in front of the spheres
+    let mut panel = RectLight::new(
+        Point3::new(0., 4., -1.),
+        Vector3::new(2., 0., 0.),
+        Vector3::new(0., 0., 1.5),
+        Color::new(1., 0.95, 0.9),
+    );
+    panel.set_intensity(10.);
+    panel.set_samples(8);
+    scene.add_light(Box::new(panel));
+
+    let mut disk = DiskLight::new(
+        Point3::new(3.5, 0., -1.),
+        Vector3::new(-1., 0., 0.),
+        0.5,
+        Color::new(0.8, 0.9, 1.),
+    );
+    disk.set_intensity(10.);
+    disk.set_samples(8);
+    scene.add_light(Box::new(disk));
+
+    let mut ball = SphereLight::new(Point3::new(0., 2.5, -2.), 0.3, WHITE);
+    ball.set_intensity(30.);
+    ball.set_samples(8);
+    scene.add_light(Box::new(ball));
+
+    // A clear sky, with its sun where the directional light shines from,
+    // seen behind the scene and baked into a map lighting it from all around
+    let sky = PreethamSky::new(54., -146., 3.);
+    let env = equirect(32, 16, |dir| {
+        if dir.y() > 0. {
+            sky.radiance(dir)
+        } else {
+            Color::new(0.2, 0.15, 0.1)
+        }
+    });
+    let mut env = EnvironmentMap::new(env);
+    env.set_intensity(0.3);
+    let mut env_light = EnvironmentLight::new(Rc::new(env));
+    env_light.set_samples(8);
+    scene.add_light(Box::new(env_light));
+    scene.set_background(Rc::new(sky));
+
     let ambient = Color::new(0.1, 0.1, 0.1);
     scene.set_ambient(&ambient);
 }
+
+/// Fill an equirectangular image, laid out as `EnvironmentMap` expects, with
+/// the color `f` gives for the direction through the center of each pixel.
+fn equirect(width: usize, height: usize, f: impl Fn(&Vector3) -> Color) -> HdrImage {
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let theta = PI * (y as f32 + 0.5) / height as f32;
+        for x in 0..width {
+            let phi = 2. * PI * (x as f32 + 0.5) / width as f32 - PI;
+            let dir = Vector3::new(
+                theta.sin() * phi.sin(),
+                theta.cos(),
+                theta.sin() * phi.cos(),
+            );
+            pixels.push(f(&dir));
+        }
+    }
+    HdrImage::new(width, height, pixels)
+}
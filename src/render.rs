@@ -1,6 +1,9 @@
-use super::math::{Point3, Ray, Vector3};
+use super::math::{Point3, Random, Ray, Vector3};
 use super::scene::colors::*;
-use super::scene::{Color, Intersection, Renderable, Scene};
+use super::scene::{
+    sample_wavelengths, upsample, Color, ColorMatching, Intersection, LightSampling, MediumStack,
+    RayKind, Scene,
+};
 
 pub struct RenderBuffer {
     pub w: usize,
@@ -32,76 +35,135 @@ pub fn render(camera: &Camera, scene: &Scene, buffer: &mut RenderBuffer, depth:
     for v in 0..camera.y_res {
         for u in 0..camera.x_res {
             let ray = camera.get_ray(u, v);
-            buffer.buf[u][v] = trace_ray(scene, &ray, depth);
+            let media = MediumStack::new();
+            buffer.buf[u][v] = trace_ray(scene, &ray, RayKind::Camera, depth, &media, None);
         }
     }
 }
 
-fn trace_ray(scene: &Scene, ray: &Ray, depth: usize) -> Color {
-    use std::f32::EPSILON;
+/**
+Render with `samples` wavelengths traced through each pixel, so that
+dispersive materials split white light into its colors.  The wavelengths of
+a pixel are spread evenly over the visible range and shifted together by a
+random offset which is seeded by the position of the pixel.  Neighboring
+pixels therefore see different wavelengths and the color noise does not
+line up into bands, while rendering a scene twice gives the same image.
+
+Each path carries the energy of its single wavelength, and uses the index
+of refraction for it.  The colors of lights, surfaces and media are
+upsampled to spectra and read at the wavelength where they are used, so a
+blue surface reflects little of a red wavelength.  Scenes without
+dispersion look the same as with `render`, up to the noise of the sampled
+wavelengths.
+*/
+pub fn render_spectral(
+    camera: &Camera,
+    scene: &Scene,
+    buffer: &mut RenderBuffer,
+    depth: usize,
+    samples: usize,
+) {
+    let cmf = ColorMatching::new();
+    for v in 0..camera.y_res {
+        for u in 0..camera.x_res {
+            let ray = camera.get_ray(u, v);
+            let mut rng = Random::new(((u as u64) << 32) | v as u64);
+            let wavelengths = sample_wavelengths(samples.max(1), &mut rng);
+            let total: Color = wavelengths
+                .iter()
+                .map(|&l| {
+                    let media = MediumStack::new();
+                    let c = trace_ray(scene, &ray, RayKind::Camera, depth, &media, Some(l));
+                    c.g * cmf.rgb(l)
+                })
+                .sum();
+            buffer.buf[u][v] = (1. / wavelengths.len() as f32) * total;
+        }
+    }
+}
 
+/// The energy arriving along `ray`, which travels through `media`.  In
+/// spectral rendering the ray carries a single `wavelength`, and every color
+/// is read at that wavelength, so the energy is the same in each channel.
+fn trace_ray(
+    scene: &Scene,
+    ray: &Ray,
+    kind: RayKind,
+    depth: usize,
+    media: &MediumStack,
+    wavelength: Option<f32>,
+) -> Color {
     if depth == 0 {
         return BLACK;
     }
 
-    let hit = scene.intersect(&ray);
+    let at = |c: Color| match wavelength {
+        Some(l) => upsample(&c, l) * WHITE,
+        None => c,
+    };
+    let hit = scene.intersect_for(&ray, kind);
     match hit {
-        None => BLACK,
-        Some(i) => {
-            let (n1, n2) = if i.entering {
-                (1., i.material.borrow().refraction_index())
-            } else {
-                (i.material.borrow().refraction_index(), 1.)
-            };
+        None => at(scene.background(&ray.direction())),
+        Some(mut i) => {
+            i.wavelength = wavelength;
+            let absorbed = at(media.transmittance((i.point - ray.origin()).len()));
+
+            // The surface of a shape inside of a medium with a higher
+            // priority is not there, carry on through it
+            if !media.is_boundary(&i) {
+                let ray = continue_ray(&i, ray);
+                let media = media.cross(&i);
+                return absorbed * trace_ray(scene, &ray, kind, depth, &media, wavelength);
+            }
+            i.outside_index = media.outside_index(&i);
 
-            let ambient = (i.material.borrow().ambient(i.tex_coord)) * scene.ambient();
+            let material = i.material.borrow();
+            let ambient = at(material.ambient(&i)) * at(*scene.ambient());
+            let emission = at(material.emission(i.tex_coord));
 
+            // The light and the reflectance of the surface are read at the
+            // wavelength apart, as their product has a different spectrum
             let lights: Color = get_light_energy(scene, &i)
                 .iter()
                 .map(|(ldir, lenergy)| {
-                    let fresnel = fresnel_reflection(&ldir, &i.normal, n1, n2);
-                    fresnel
-                        * i.material
-                            .borrow()
-                            .get_reflected_energy(&lenergy, &ldir, &i)
+                    at(material.get_reflected_energy(&WHITE, ldir, &i)) * at(*lenergy)
+                })
+                .sum();
+
+            // Follow the mirror reflection and refraction of the surface
+            let specular: Color = material
+                .specular_samples(&i.eye_dir, &i)
+                .iter()
+                .map(|s| {
+                    let (kind, ray) = secondary_ray(&i, &s.wi);
+                    let media = media.after(&i, kind);
+                    at(s.weight) * trace_ray(scene, &ray, kind, depth - 1, &media, wavelength)
                 })
                 .sum();
 
-            let reflected = if i.material.borrow().reflectivity() > EPSILON {
-                // compute reflection vector
-                let reflect_ray = reflect_ray(ray, &i);
-                // compute incoming energy from the direction of the reflected ray
-                let energy = trace_ray(scene, &reflect_ray, depth - 1);
-                let fresnel = fresnel_reflection(&reflect_ray.direction(), &i.normal, n1, n2);
-                fresnel
-                    * i.material.borrow().get_reflected_energy(
-                        &energy,
-                        &reflect_ray.direction(),
-                        &i,
-                    )
-            } else {
-                BLACK
-            };
-
-            let refracted = if i.material.borrow().refraction_index() > EPSILON {
-                let refract_ray = refract_ray(ray, &i, n1, n2);
-                (i.material.borrow().diffuse(i.tex_coord))
-                    * refract_ray
-                        .map(|r| {
-                            let fresnel =
-                                fresnel_refraction(&r.direction(), &i.normal.neg(), n1, n2);
-                            fresnel * trace_ray(scene, &r, depth - 1)
-                        })
-                        .unwrap_or(BLACK)
-            } else {
-                BLACK
-            };
-
-            ambient + lights + reflected + refracted
+            absorbed * (emission + ambient + lights + specular)
         }
     }
 }
 
+/// The ray carrying on in the same direction past the surface at `i`
+pub fn continue_ray(i: &Intersection, ray: &Ray) -> Ray {
+    let dir = ray.direction().norm();
+    Ray::new(&(i.point + 0.0002 * dir), &dir)
+}
+
+/// The ray continuing from the surface at `i` in direction `dir`, and
+/// whether it was reflected or refracted.
+pub fn secondary_ray(i: &Intersection, dir: &Vector3) -> (RayKind, Ray) {
+    let kind = if dir.dot(&i.normal) >= 0. {
+        RayKind::Reflection
+    } else {
+        RayKind::Refraction
+    };
+    let p = i.point + 0.0002 * *dir;
+    (kind, Ray::new(&p, dir))
+}
+
 pub fn reflect_ray(ray: &Ray, i: &Intersection) -> Ray {
     // compute reflection vector
     let reflected_dir = -ray.direction().reflect(&i.normal).norm();
@@ -124,32 +186,98 @@ pub fn refract_ray(ray: &Ray, i: &Intersection, n1: f32, n2: f32) -> Option<Ray>
     }
 }
 
-/// Use Schlick's approximation to compute the Fresnel coeffection for the amount of energy
-/// reflected off of a surface.
+/// The fraction of unpolarized light arriving along `light_dir`, from the
+/// medium with index `n1` into the one with index `n2`, which is reflected
+/// off of the surface.  `light_dir` points away from the surface on the
+/// side of `n1`.
+///
+/// This uses the exact Fresnel equations for dielectrics.  Past the critical
+/// angle all of the light is reflected, and a medium with an index of 0 is
+/// opaque and reflects everything.
 pub fn fresnel_reflection(light_dir: &Vector3, normal: &Vector3, n1: f32, n2: f32) -> f32 {
-    let m_dot_r = light_dir.dot(&normal);
-    let r0 = ((n1 - n2) / (n1 + n2)) * ((n1 - n2) / (n1 + n2));
+    if n2 <= std::f32::EPSILON {
+        return 1.;
+    }
+
+    let cos_i = light_dir.dot(&normal).abs().min(1.);
+    let eta = n1 / n2;
+    let sin_t2 = eta * eta * (1. - cos_i * cos_i);
+    if sin_t2 >= 1. {
+        // Total internal reflection
+        return 1.;
+    }
 
-    r0 + (1. - r0) * (1. - m_dot_r).powi(5)
+    let cos_t = (1. - sin_t2).sqrt();
+    let rs = (n1 * cos_i - n2 * cos_t) / (n1 * cos_i + n2 * cos_t);
+    let rp = (n2 * cos_i - n1 * cos_t) / (n2 * cos_i + n1 * cos_t);
+    0.5 * (rs * rs + rp * rp)
 }
 
-/// Use Schlick's approximation to compute the amount of energy transmitted through a material
-/// (this is the energy which is not reflected)
+/// The fraction of light which is transmitted through the surface, the
+/// energy which is not reflected
 pub fn fresnel_refraction(light_dir: &Vector3, normal: &Vector3, n1: f32, n2: f32) -> f32 {
     1. - fresnel_reflection(light_dir, normal, n1, n2)
 }
 
+/// The fraction of light arriving from air at the angle with cosine `cos_i`
+/// which is reflected by a conductor with the complex index of refraction
+/// `n + ik`, given for each channel.  Metals have a large `k`, which is why
+/// they reflect strongly even when seen head on.
+pub fn fresnel_conductor(cos_i: f32, n: &Color, k: &Color) -> Color {
+    let cos_i = cos_i.clamp(0., 1.);
+    let channel = |n: f32, k: f32| -> f32 {
+        let cos2 = cos_i * cos_i;
+        let sin2 = 1. - cos2;
+        let t0 = n * n - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4. * n * n * k * k).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2. * cos_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rs + rp)
+    };
+    Color::new(channel(n.r, k.r), channel(n.g, k.g), channel(n.b, k.b))
+}
+
 pub fn get_light_energy(scene: &Scene, i: &Intersection) -> Vec<(Vector3, Color)> {
     // Move slightly away from the surface of intersection because rounding
     // errors in floating point arithmetic can easily cause the ray to intersect
     // with its surface.  This would cause random points to be colored as if
     // they are in shadow even though they are visible to the light source.
     let p = i.point + 0.0002 * i.normal;
-    scene
-        .lights()
-        .iter()
-        .map(|l| l.get_energy(scene, &p))
-        .collect()
+    let lights = scene.lights();
+    let links = scene.light_links();
+    let energy_from = |index: usize| -> Vec<(Vector3, Color)> {
+        if links[index].illuminates(i.id) {
+            lights[index].get_energy_samples(scene, &p)
+        } else {
+            vec![]
+        }
+    };
+
+    match scene.light_sampling() {
+        LightSampling::Exhaustive => (0..lights.len()).flat_map(energy_from).collect(),
+        LightSampling::Stochastic { samples, seed } => {
+            let tree = scene.light_tree();
+            let mut energy: Vec<(Vector3, Color)> =
+                tree.unbounded().iter().flat_map(|l| energy_from(*l)).collect();
+
+            // Each light is picked with probability `pdf` out of `samples`
+            // tries, so its energy is scaled up to keep the average right.
+            let mut rng = Random::from_point(&p, seed);
+            let samples = samples.max(1);
+            for _ in 0..samples {
+                if let Some((light, pdf)) = tree.sample(&p, &mut rng) {
+                    let scale = 1. / (pdf * samples as f32);
+                    energy.extend(energy_from(light).into_iter().map(|(d, e)| (d, scale * e)));
+                }
+            }
+            energy
+        }
+    }
 }
 
 pub struct Camera {
@@ -227,7 +355,7 @@ mod benchmarks {
     use std::rc::Rc;
 
     use super::super::math::Matrix;
-    use super::super::scene::{Phong, Sphere};
+    use super::super::scene::{Phong, Renderable, Sphere};
     use super::*;
 
     #[bench]
@@ -248,3 +376,181 @@ mod benchmarks {
         b.iter(|| super::render(&camera, &scene, &mut buffer, 5));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::super::math::Matrix;
+    use super::super::scene::{
+        LightLink, LightSource, Phong, PointLight, Renderable, Sphere, Visibility, MIN_WAVELENGTH,
+    };
+    use super::*;
+
+    fn sphere(y: f32) -> Box<Sphere> {
+        let phong = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        let mut sph = Sphere::new(phong);
+        sph.set_transform(&Matrix::translate(0., y, 0.));
+        Box::new(sph)
+    }
+
+    #[test]
+    fn visibility_flags() {
+        let mut scene = Scene::new();
+        let mut hidden = sphere(0.);
+        hidden.set_visibility(Visibility {
+            camera: false,
+            shadow: false,
+            reflection: true,
+            refraction: true,
+        });
+        scene.add_shape(hidden);
+
+        let ray = Ray::new(&Point3::new(0., 0., -4.), &Vector3::new(0., 0., 1.));
+        assert_eq!(true, scene.intersect_for(&ray, RayKind::Camera).is_none());
+        assert_eq!(true, scene.intersect_for(&ray, RayKind::Reflection).is_some());
+        let media = MediumStack::new();
+        assert_eq!(BLACK, trace_ray(&scene, &ray, RayKind::Camera, 2, &media, None));
+
+        // The sphere casts no shadow on a point below it
+        let light = PointLight::new(Point3::new(0., 4., 0.), WHITE);
+        let (_, energy) = light.get_energy(&scene, &Point3::new(0., -4., 0.));
+        assert_eq!(WHITE, energy);
+    }
+
+    #[test]
+    fn light_links() {
+        let mut scene = Scene::new();
+        scene.add_shape(sphere(0.));
+        scene.add_shape(sphere(4.));
+        scene.add_light(Box::new(PointLight::new(Point3::new(0., 2., -4.), WHITE)));
+        scene.add_linked_light(
+            Box::new(PointLight::new(Point3::new(0., 2., -4.), RED)),
+            LightLink::Except(vec![1]),
+        );
+
+        let ray = Ray::new(&Point3::new(0., 0., -4.), &Vector3::new(0., 0., 1.));
+        let i = scene.intersect_for(&ray, RayKind::Camera).unwrap();
+        assert_eq!(2, get_light_energy(&scene, &i).len());
+
+        let ray = Ray::new(&Point3::new(0., 4., -4.), &Vector3::new(0., 0., 1.));
+        let i = scene.intersect_for(&ray, RayKind::Camera).unwrap();
+        assert_eq!(1, get_light_energy(&scene, &i).len());
+
+        scene.set_light_link(0, LightLink::Only(vec![0]));
+        assert_eq!(0, get_light_energy(&scene, &i).len());
+    }
+
+    #[test]
+    fn light_sampling() {
+        let mut scene = Scene::new();
+        scene.add_shape(sphere(0.));
+        for x in -8..8 {
+            let color = Color::new(1., 0.5 + x as f32 / 16., 0.25);
+            let pos = Point3::new(x as f32, 2., -4.);
+            scene.add_light(Box::new(PointLight::new(pos, color)));
+        }
+        let ray = Ray::new(&Point3::new(0., 0., -4.), &Vector3::new(0., 0., 1.));
+        let i = scene.intersect_for(&ray, RayKind::Camera).unwrap();
+        let total =
+            |energy: Vec<(Vector3, Color)>| -> Color { energy.into_iter().map(|(_, e)| e).sum() };
+        let exact = total(get_light_energy(&scene, &i));
+
+        scene.set_light_sampling(LightSampling::Stochastic {
+            samples: 4000,
+            seed: 3,
+        });
+        let estimate = total(get_light_energy(&scene, &i));
+        assert!((estimate.r - exact.r).abs() / exact.r < 0.05);
+        assert!((estimate.g - exact.g).abs() / exact.g < 0.05);
+
+        // The same seed picks the same lights
+        assert_eq!(estimate, total(get_light_energy(&scene, &i)));
+    }
+
+    #[test]
+    fn spectral() {
+        let mut scene = Scene::new();
+        scene.add_shape(sphere(0.));
+        scene.add_light(Box::new(PointLight::new(Point3::new(0., 2., -4.), WHITE)));
+        let camera = Camera::new(8, 8);
+        let mut rgb = RenderBuffer::new(8, 8);
+        let mut spectral = RenderBuffer::new(8, 8);
+        render(&camera, &scene, &mut rgb, 3);
+        render_spectral(&camera, &scene, &mut spectral, 3, 16);
+
+        // Without dispersion the colors only differ by the sampling noise
+        let mut lit = 0;
+        for u in 0..8 {
+            for v in 0..8 {
+                let (a, b) = (rgb.buf[u][v], spectral.buf[u][v]);
+                for (x, y) in &[(a.r, b.r), (a.g, b.g), (a.b, b.b)] {
+                    assert!((x - y).abs() <= 0.05 * x.max(1.));
+                }
+                if a.g > 0.1 {
+                    lit += 1;
+                }
+            }
+        }
+        assert!(lit > 0);
+
+        // Colors are read at each wavelength where they are used, so a red
+        // surface stays red and reflects almost none of a blue light
+        let red = Rc::new(RefCell::new(Phong::new(BLACK, RED, BLACK, 60., 0., 0.)));
+        let mut scene = Scene::new();
+        scene.add_shape(Box::new(Sphere::new(red)));
+        scene.add_light(Box::new(PointLight::new(Point3::new(0., 0., -4.), WHITE)));
+        let ray = Ray::new(&Point3::new(0., 0., -4.), &Vector3::new(0., 0., 1.));
+        let spectral_color = |scene: &Scene| -> Color {
+            let cmf = ColorMatching::new();
+            let media = MediumStack::new();
+            let total: Color = (0..40)
+                .map(|k| MIN_WAVELENGTH + 10. * k as f32 + 5.)
+                .map(|l| {
+                    let c = trace_ray(scene, &ray, RayKind::Camera, 3, &media, Some(l));
+                    c.g * cmf.rgb(l)
+                })
+                .sum();
+            (1. / 40.) * total
+        };
+        let lit = spectral_color(&scene);
+        assert!(lit.r > 0.5 && lit.r > 4. * lit.g && lit.r > 4. * lit.b);
+
+        let mut scene = Scene::new();
+        let red = Rc::new(RefCell::new(Phong::new(BLACK, RED, BLACK, 60., 0., 0.)));
+        scene.add_shape(Box::new(Sphere::new(red)));
+        scene.add_light(Box::new(PointLight::new(Point3::new(0., 0., -4.), BLUE)));
+        let dark = spectral_color(&scene);
+        assert!(dark.r < 0.1 * lit.r);
+    }
+
+    #[test]
+    fn fresnel() {
+        let n = Vector3::new(0., 1., 0.);
+
+        // Glass seen head on reflects 4%, and everything at a grazing angle
+        let head_on = fresnel_reflection(&n, &n, 1., 1.5);
+        assert!((head_on - 0.04).abs() < 0.0001);
+        let grazing = Vector3::new(1., 0.001, 0.).norm();
+        assert!(fresnel_reflection(&grazing, &n, 1., 1.5) > 0.99);
+
+        // The same from either side, when measured on the matching side
+        let wi = Vector3::new(0.6, 0.8, 0.);
+        let sin_t = 0.6 / 1.5;
+        let wt = Vector3::new(sin_t, (1. - sin_t * sin_t).sqrt(), 0.);
+        let outside = fresnel_reflection(&wi, &n, 1., 1.5);
+        assert!((outside - fresnel_reflection(&wt, &n, 1.5, 1.)).abs() < 0.0001);
+        assert!((outside + fresnel_refraction(&wi, &n, 1., 1.5) - 1.).abs() < 0.0001);
+
+        // Past the critical angle of glass everything is reflected
+        assert_eq!(1., fresnel_reflection(&Vector3::new(0.8, 0.6, 0.), &n, 1.5, 1.));
+
+        // A conductor without absorption is a dielectric
+        let c = fresnel_conductor(0.8, &Color::new(1.5, 1.5, 1.5), &BLACK);
+        assert!((c.r - outside).abs() < 0.0001);
+        let aluminium = fresnel_conductor(1., &Color::new(1.2, 1.2, 1.2), &Color::new(7., 7., 7.));
+        let expected = (0.2 * 0.2 + 49.) / (2.2 * 2.2 + 49.);
+        assert!((aluminium.g - expected).abs() < 0.0001);
+    }
+}
@@ -3,23 +3,31 @@ use std::collections::HashSet;
 use std::rc::Rc;
 
 use super::math::{Ray, Vector3};
-use super::render::{
-    fresnel_reflection, fresnel_refraction, get_light_energy, reflect_ray, refract_ray, Camera,
-    RenderBuffer,
+use super::render::{continue_ray, get_light_energy, secondary_ray, Camera, RenderBuffer};
+use super::scene::{
+    colors::{BLACK, WHITE},
+    Color, Intersection, MediumStack, RayKind, Scene,
 };
-use super::scene::{colors::BLACK, Color, Intersection, Renderable, Scene};
 
 #[derive(Clone)]
 enum RayTreeNode {
     None,
-    Branch(
-        Intersection,
-        Vec<(Vector3, Color)>,
-        Box<RayTreeNode>,
-        Box<RayTreeNode>,
-    ),
+    // A ray which left the scene: the energy of the background in the
+    // direction of the ray
+    Background(Color, Vector3),
+    // The energy of the node scaled by what is left after absorption in
+    // the medium the ray travelled through to get there
+    Absorbed(Color, Box<RayTreeNode>),
+    // A hit with the energy of each light arriving at it, and a child for
+    // each of the specular samples of its material with the direction and
+    // kind of the ray which was traced for it
+    Branch(Intersection, Vec<(Vector3, Color)>, Vec<(Vector3, RayKind, RayTreeNode)>),
 }
 
+// How close the direction of a specular sample must be to that of a child
+// for the child to be the ray traced for the sample
+const SAME_DIRECTION: f32 = 1e-4;
+
 #[derive(Clone)]
 struct RayTree {
     dirty: bool,
@@ -42,8 +50,14 @@ impl RayTree {
 
     fn traverse_nodes(node: &RayTreeNode) -> usize {
         match node {
-            RayTreeNode::None =>  0,
-            RayTreeNode::Branch(_, _, l, r) => 1 + RayTree::traverse_nodes(&l) + RayTree::traverse_nodes(&r),
+            RayTreeNode::None | RayTreeNode::Background(..) =>  0,
+            RayTreeNode::Absorbed(_, node) => RayTree::traverse_nodes(node),
+            RayTreeNode::Branch(_, _, children) => {
+                1 + children
+                    .iter()
+                    .map(|(_, _, child)| RayTree::traverse_nodes(child))
+                    .sum::<usize>()
+            }
         }
     }
 }
@@ -155,7 +169,14 @@ pub fn generate_ray_forest(
     for v in 0..camera.y_res {
         for u in 0..camera.x_res {
             let ray = camera.get_ray(u, v);
-            let tree = build_ray_tree(scene, &ray, depth, &mut ray_forest.forest[u][v].shapes);
+            let tree = build_ray_tree(
+                scene,
+                &ray,
+                RayKind::Camera,
+                depth,
+                &mut ray_forest.forest[u][v].shapes,
+                &MediumStack::new(),
+            );
             ray_forest.forest[u][v].root = tree;
             ray_forest.forest[u][v].dirty = true;
         }
@@ -166,47 +187,54 @@ pub fn generate_ray_forest(
 fn build_ray_tree(
     scene: &Scene,
     ray: &Ray,
+    kind: RayKind,
     depth: usize,
     shapes: &mut HashSet<i32>,
+    media: &MediumStack,
 ) -> RayTreeNode {
-    use std::f32::EPSILON;
-
     if depth == 0 {
         return RayTreeNode::None;
     }
 
-    let hit = scene.intersect(&ray);
+    let hit = scene.intersect_for(&ray, kind);
     match hit {
-        None => RayTreeNode::None,
-        Some(i) => {
+        None => {
+            let dir = ray.direction();
+            RayTreeNode::Background(scene.background(&dir), dir)
+        }
+        Some(mut i) => {
             shapes.insert(i.id);
-            let (n1, n2) = if i.entering {
-                (1., i.material.borrow().refraction_index())
-            } else {
-                (i.material.borrow().refraction_index(), 1.)
-            };
-
-            let lights = get_light_energy(scene, &i);
+            let absorbed = media.transmittance((i.point - ray.origin()).len());
 
-            let reflected = if i.material.borrow().reflectivity() > EPSILON {
-                // compute reflection vector
-                let reflect_ray = reflect_ray(ray, &i);
-                // compute incoming energy from the direction of the reflected ray
-                build_ray_tree(scene, &reflect_ray, depth - 1, shapes)
+            let node = if !media.is_boundary(&i) {
+                // The surface is inside of a medium with a higher priority
+                let ray = continue_ray(&i, ray);
+                build_ray_tree(scene, &ray, kind, depth, shapes, &media.cross(&i))
             } else {
-                RayTreeNode::None
+                i.outside_index = media.outside_index(&i);
+                let lights = get_light_energy(scene, &i);
+
+                // Trace the reflections and refractions of the surface, the
+                // material weighs them when the tree is rendered.
+                let samples = i.material.borrow().specular_samples(&i.eye_dir, &i);
+                let children = samples
+                    .iter()
+                    .map(|s| {
+                        let (kind, ray) = secondary_ray(&i, &s.wi);
+                        let media = media.after(&i, kind);
+                        let child = build_ray_tree(scene, &ray, kind, depth - 1, shapes, &media);
+                        (s.wi, kind, child)
+                    })
+                    .collect();
+
+                RayTreeNode::Branch(i, lights, children)
             };
 
-            let refracted = if i.material.borrow().refraction_index() > EPSILON {
-                let refract_ray = refract_ray(ray, &i, n1, n2);
-                refract_ray
-                    .map(|r| build_ray_tree(scene, &r, depth - 1, shapes))
-                    .unwrap_or(RayTreeNode::None)
+            if absorbed == WHITE {
+                node
             } else {
-                RayTreeNode::None
-            };
-
-            RayTreeNode::Branch(i, lights, Box::new(reflected), Box::new(refracted))
+                RayTreeNode::Absorbed(absorbed, Box::new(node))
+            }
         }
     }
 }
@@ -214,42 +242,36 @@ fn build_ray_tree(
 fn render_ray_tree(tree: &RayTreeNode, ambient: &Color) -> (Color, Vector3) {
     match tree {
         RayTreeNode::None => (BLACK, Vector3::new(0., 0., 0.)),
-        RayTreeNode::Branch(ref i, lights, reflected, refracted) => {
-            let (n1, n2) = if i.entering {
-                (1., i.material.borrow().refraction_index())
-            } else {
-                (i.material.borrow().refraction_index(), 1.)
-            };
-
+        RayTreeNode::Background(energy, dir) => (*energy, *dir),
+        RayTreeNode::Absorbed(absorbed, node) => {
+            let (energy, dir) = render_ray_tree(node, ambient);
+            (*absorbed * energy, dir)
+        }
+        RayTreeNode::Branch(ref i, lights, children) => {
+            let material = i.material.borrow();
             let lights: Color = lights
                 .iter()
-                .map(|(ldir, lenergy)| {
-                    let fresnel = fresnel_reflection(&ldir, &i.normal, n1, n2);
-                    fresnel
-                        * i.material
-                            .borrow()
-                            .get_reflected_energy(&lenergy, &ldir, &i)
-                })
+                .map(|(ldir, lenergy)| material.get_reflected_energy(lenergy, ldir, &i))
                 .sum();
 
-            let reflected = {
-                // compute incoming energy from the direction of the reflected ray
-                let (energy, dir) = render_ray_tree(reflected, ambient);
-                let fresnel = fresnel_reflection(&dir, &i.normal, n1, n2);
-                fresnel
-                    * i.material
-                        .borrow()
-                        .get_reflected_energy(&energy, &i.eye_dir, &i)
-            };
-
-            let refracted = {
-                let (energy, dir) = render_ray_tree(refracted, ambient);
-                let fresnel = fresnel_refraction(&dir, &i.normal.neg(), n1, n2);
-                fresnel * energy
-            };
+            // Pair each sample with the ray traced for it.  A material which
+            // was changed since the tree was built can have samples which
+            // were never traced, and those add nothing until it is rebuilt.
+            let specular: Color = material
+                .specular_samples(&i.eye_dir, &i)
+                .iter()
+                .filter_map(|s| {
+                    let kind = secondary_ray(i, &s.wi).0;
+                    children
+                        .iter()
+                        .find(|(wi, k, _)| *k == kind && (*wi - s.wi).len() < SAME_DIRECTION)
+                        .map(|(_, _, child)| s.weight * render_ray_tree(child, ambient).0)
+                })
+                .sum();
 
-            let ambient = (i.material.borrow().ambient(i.tex_coord)) * ambient;
-            (ambient + lights + reflected + refracted, -i.eye_dir)
+            let ambient = material.ambient(i) * ambient;
+            let emission = material.emission(i.tex_coord);
+            (emission + ambient + lights + specular, -i.eye_dir)
         }
     }
 }
@@ -260,6 +282,7 @@ mod tests {
     use super::Intersection;
     use super::super::scene::*;
     use super::super::math::*;
+    use super::super::scene::colors::RED;
 
     #[test]
     pub fn ray_tree_size() {
@@ -268,26 +291,59 @@ mod tests {
 
         assert_eq!(0, t.size());
 
-        t.root = RayTreeNode::Branch(new_int(), vec![], Box::new(RayTreeNode::None), Box::new(RayTreeNode::None));
+        t.root = RayTreeNode::Branch(new_int(), vec![], vec![]);
         assert_eq!(1, t.size());
 
-        let r = RayTreeNode::Branch(new_int(), vec![], Box::new(RayTreeNode::None), Box::new(RayTreeNode::None));
-        t.root = RayTreeNode::Branch(new_int(), vec![], Box::new(RayTreeNode::None), Box::new(r));
+        let r = RayTreeNode::Branch(new_int(), vec![], vec![]);
+        t.root = RayTreeNode::Branch(new_int(), vec![], vec![child(RayTreeNode::None), child(r)]);
         assert_eq!(2, t.size());
 
-        let l = RayTreeNode::Branch(new_int(), vec![], Box::new(RayTreeNode::None), Box::new(RayTreeNode::None));
-        let r = RayTreeNode::Branch(new_int(), vec![], Box::new(RayTreeNode::None), Box::new(RayTreeNode::None));
-        t.root = RayTreeNode::Branch(new_int(), vec![], Box::new(l), Box::new(r));
+        let l = RayTreeNode::Branch(new_int(), vec![], vec![]);
+        let r = RayTreeNode::Branch(new_int(), vec![], vec![]);
+        t.root = RayTreeNode::Branch(new_int(), vec![], vec![child(l), child(r)]);
         assert_eq!(3, t.size());
 
-        let ll = RayTreeNode::Branch(new_int(), vec![], Box::new(RayTreeNode::None), Box::new(RayTreeNode::None));
-        let lr = RayTreeNode::Branch(new_int(), vec![], Box::new(RayTreeNode::None), Box::new(RayTreeNode::None));
-        let l = RayTreeNode::Branch(new_int(), vec![], Box::new(ll), Box::new(lr));
-        let rl = RayTreeNode::Branch(new_int(), vec![], Box::new(RayTreeNode::None), Box::new(RayTreeNode::None));
-        let rr = RayTreeNode::Branch(new_int(), vec![], Box::new(RayTreeNode::None), Box::new(RayTreeNode::None));
-        let r = RayTreeNode::Branch(new_int(), vec![], Box::new(rl), Box::new(rr));
-        t.root = RayTreeNode::Branch(new_int(), vec![], Box::new(l), Box::new(r));
+        let ll = RayTreeNode::Branch(new_int(), vec![], vec![]);
+        let lr = RayTreeNode::Branch(new_int(), vec![], vec![]);
+        let l = RayTreeNode::Branch(new_int(), vec![], vec![child(ll), child(lr)]);
+        let rl = RayTreeNode::Branch(new_int(), vec![], vec![]);
+        let rr = RayTreeNode::Branch(new_int(), vec![], vec![]);
+        let r = RayTreeNode::Branch(new_int(), vec![], vec![child(rl), child(rr)]);
+        t.root = RayTreeNode::Branch(new_int(), vec![], vec![child(l), child(r)]);
         assert_eq!(7, t.size());
+
+        // A glossy surface has a branch for each of its rays
+        let glossy: Vec<_> = (0..8)
+            .map(|_| child(RayTreeNode::Branch(new_int(), vec![], vec![])))
+            .collect();
+        let absorbed = RayTreeNode::Absorbed(WHITE, Box::new(RayTreeNode::Branch(new_int(), vec![], glossy)));
+        t.root = RayTreeNode::Branch(new_int(), vec![], vec![child(absorbed), child(RayTreeNode::None)]);
+        assert_eq!(10, t.size());
+    }
+
+    #[test]
+    pub fn children_follow_their_samples() {
+        let mirror = Phong::new(BLACK, BLACK, WHITE, 60., 1., 0.);
+        let mut i = new_int();
+        i.material = Rc::new(RefCell::new(mirror));
+        i.normal = Vector3::new(0., 1., 0.);
+        i.eye_dir = Vector3::new(0.6, 0.8, 0.);
+        let samples = mirror.specular_samples(&i.eye_dir, &i);
+        assert_eq!(1, samples.len());
+
+        // The child traced for the mirror sample is weighted by it
+        let red = RayTreeNode::Background(RED, samples[0].wi);
+        let tree = RayTreeNode::Branch(i.clone(), vec![], vec![(samples[0].wi, RayKind::Reflection, red)]);
+        assert_eq!(samples[0].weight * RED, render_ray_tree(&tree, &BLACK).0);
+
+        // A child for a direction the material no longer samples adds nothing
+        let stale = RayTreeNode::Background(RED, Vector3::new(0., 1., 0.));
+        let tree = RayTreeNode::Branch(i, vec![], vec![(Vector3::new(0., 1., 0.), RayKind::Reflection, stale)]);
+        assert_eq!(BLACK, render_ray_tree(&tree, &BLACK).0);
+    }
+
+    fn child(node: RayTreeNode) -> (Vector3, RayKind, RayTreeNode) {
+        (Vector3::new(0., 1., 0.), RayKind::Reflection, node)
     }
 
     fn new_int() -> Intersection {
@@ -302,6 +358,11 @@ mod tests {
             eye_dir: Vector3::new(0., 0., 0.),
             normal: Vector3::new(0., 0., 0.),
             tex_coord: (0., 0.),
+            local_point: Point3::new(0., 0., 0.),
+            tangent: Vector3::new(1., 0., 0.),
+            outside_index: 1.,
+            wavelength: None,
+            vertex_color: None,
         }
     }
 }
@@ -0,0 +1,425 @@
+/// Lights which have an area and so cast soft shadows
+use crate::math::{stratified_samples, BoundingBox, Point3, Random, Vector3};
+
+use super::{
+    colors, combine_samples, frame, luminance, shadow_transmittance, Color, LightBounds,
+    LightSource, Scene,
+};
+
+use std::f32::consts::PI;
+
+const DEFAULT_SAMPLES: usize = 16;
+
+/// Shade `point` from each of `positions` on a light, which glow with
+/// `radiance` on the side their normal faces and were picked with the
+/// density `pdf` per unit of area.  As for a `ShapeLight`, each sample is
+/// scaled by the cosine at the light over the squared distance to it, so an
+/// area light falls off like a point light once it is far away.
+fn sample_positions(
+    scene: &Scene,
+    point: &Point3,
+    positions: &[(Point3, Vector3)],
+    pdf: f32,
+    radiance: Color,
+) -> Vec<(Vector3, Color)> {
+    let n = positions.len() as f32;
+    positions
+        .iter()
+        .map(|(pos, normal)| {
+            let to_light = pos - point;
+            let dist = to_light.len();
+            let dir_to_light = to_light.norm();
+            let cos_light = -dir_to_light.dot(normal);
+            if cos_light <= 0. {
+                return (dir_to_light, colors::BLACK);
+            }
+            let scale = cos_light / (dist * dist * pdf * n * PI);
+            let energy = scale * shadow_transmittance(scene, point, pos) * radiance;
+            (dir_to_light, energy)
+        })
+        .collect()
+}
+
+/**
+A rectangular light centered on `center` and spanned by the edges `u` and
+`v`.  It only shines from the side that `u × v` faces.
+
+Every point of the light glows with `intensity * color`.  Far from the
+light this reaches a point like a point light with an intensity of
+`intensity * area / π`, which `intensity_at` reports.
+*/
+pub struct RectLight {
+    center: Point3,
+    u: Vector3,
+    v: Vector3,
+    normal: Vector3,
+    color: Color,
+    intensity: f32,
+    samples: usize,
+}
+
+impl RectLight {
+    pub fn new(center: Point3, u: Vector3, v: Vector3, color: Color) -> RectLight {
+        RectLight {
+            center,
+            u,
+            v,
+            normal: u.cross(&v).norm(),
+            color,
+            intensity: 1.,
+            samples: DEFAULT_SAMPLES,
+        }
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    /// Set the number of shadow rays cast from each point
+    pub fn set_samples(&mut self, samples: usize) {
+        self.samples = samples.max(1);
+    }
+}
+
+impl LightSource for RectLight {
+    fn get_energy(&self, scene: &Scene, point: &Point3) -> (Vector3, Color) {
+        combine_samples(&self.get_energy_samples(scene, point))
+    }
+
+    fn get_energy_samples(&self, scene: &Scene, point: &Point3) -> Vec<(Vector3, Color)> {
+        let mut rng = Random::from_point(point, 0);
+        let positions: Vec<(Point3, Vector3)> = stratified_samples(self.samples, &mut rng)
+            .into_iter()
+            .map(|(s, t)| {
+                let pos = self.center + ((s - 0.5) * self.u + (t - 0.5) * self.v);
+                (pos, self.normal)
+            })
+            .collect();
+
+        let pdf = 1. / self.u.cross(&self.v).len();
+        sample_positions(scene, point, &positions, pdf, self.intensity * self.color)
+    }
+
+    fn intensity_at(&self, distance: f32) -> f32 {
+        let area = self.u.cross(&self.v).len();
+        self.intensity * area / (PI * distance * distance)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let corner = self.center + (-0.5 * self.u - 0.5 * self.v);
+        let bounds = [self.u, self.v, self.u + self.v]
+            .iter()
+            .fold(BoundingBox::new(&corner, &corner), |b, d| b.grow(&(corner + *d)));
+        Some(LightBounds {
+            bounds,
+            direction: self.normal,
+            cos_spread: 0.,
+            power: self.intensity_at(1.) * luminance(&self.color),
+        })
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Rect: ({}, {}, {}), Size: ({}, {}), Samples: {}, Color: ({}, {}, {}), Intensity: {}",
+            self.center.x(),
+            self.center.y(),
+            self.center.z(),
+            self.u.len(),
+            self.v.len(),
+            self.samples,
+            self.color.r,
+            self.color.g,
+            self.color.b,
+            self.intensity
+        )
+    }
+}
+
+/**
+A round light centered on `center` which shines from the side facing
+`normal`.  Like a `RectLight`, every point of it glows with
+`intensity * color`.
+*/
+pub struct DiskLight {
+    center: Point3,
+    normal: Vector3,
+    radius: f32,
+    color: Color,
+    intensity: f32,
+    samples: usize,
+
+    // axes of the disk
+    u: Vector3,
+    v: Vector3,
+}
+
+impl DiskLight {
+    pub fn new(center: Point3, normal: Vector3, radius: f32, color: Color) -> DiskLight {
+        let normal = normal.norm();
+        let (u, v) = frame(&normal);
+        DiskLight {
+            center,
+            normal,
+            radius,
+            color,
+            intensity: 1.,
+            samples: DEFAULT_SAMPLES,
+            u,
+            v,
+        }
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    /// Set the number of shadow rays cast from each point
+    pub fn set_samples(&mut self, samples: usize) {
+        self.samples = samples.max(1);
+    }
+}
+
+impl LightSource for DiskLight {
+    fn get_energy(&self, scene: &Scene, point: &Point3) -> (Vector3, Color) {
+        combine_samples(&self.get_energy_samples(scene, point))
+    }
+
+    fn get_energy_samples(&self, scene: &Scene, point: &Point3) -> Vec<(Vector3, Color)> {
+        let mut rng = Random::from_point(point, 0);
+        let positions: Vec<(Point3, Vector3)> = stratified_samples(self.samples, &mut rng)
+            .into_iter()
+            .map(|(s, t)| {
+                // The square root keeps the samples evenly spread over the area
+                let r = self.radius * s.sqrt();
+                let theta = 2. * PI * t;
+                let pos = self.center + (r * theta.cos() * self.u + r * theta.sin() * self.v);
+                (pos, self.normal)
+            })
+            .collect();
+
+        let pdf = 1. / (PI * self.radius * self.radius);
+        sample_positions(scene, point, &positions, pdf, self.intensity * self.color)
+    }
+
+    fn intensity_at(&self, distance: f32) -> f32 {
+        self.intensity * self.radius * self.radius / (distance * distance)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let r = self.radius;
+        let bounds = BoundingBox::new(
+            &(self.center + (-r * self.u - r * self.v)),
+            &(self.center + (r * self.u + r * self.v)),
+        )
+        .grow(&(self.center + (r * self.u - r * self.v)))
+        .grow(&(self.center + (r * self.v - r * self.u)));
+        Some(LightBounds {
+            bounds,
+            direction: self.normal,
+            cos_spread: 0.,
+            power: self.intensity_at(1.) * luminance(&self.color),
+        })
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Disk: ({}, {}, {}), Normal: ({}, {}, {}), Radius: {}, Samples: {}, \
+             Color: ({}, {}, {}), Intensity: {}",
+            self.center.x(),
+            self.center.y(),
+            self.center.z(),
+            self.normal.x(),
+            self.normal.y(),
+            self.normal.z(),
+            self.radius,
+            self.samples,
+            self.color.r,
+            self.color.g,
+            self.color.b,
+            self.intensity
+        )
+    }
+}
+
+/**
+A spherical light which shines equally in all directions.  Only the half
+of the sphere facing a point is sampled, since the far half could not be
+seen from it.  Every point of its surface glows with `intensity * color`.
+*/
+pub struct SphereLight {
+    center: Point3,
+    radius: f32,
+    color: Color,
+    intensity: f32,
+    samples: usize,
+}
+
+impl SphereLight {
+    pub fn new(center: Point3, radius: f32, color: Color) -> SphereLight {
+        SphereLight {
+            center,
+            radius,
+            color,
+            intensity: 1.,
+            samples: DEFAULT_SAMPLES,
+        }
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    /// Set the number of shadow rays cast from each point
+    pub fn set_samples(&mut self, samples: usize) {
+        self.samples = samples.max(1);
+    }
+}
+
+impl LightSource for SphereLight {
+    fn get_energy(&self, scene: &Scene, point: &Point3) -> (Vector3, Color) {
+        combine_samples(&self.get_energy_samples(scene, point))
+    }
+
+    fn get_energy_samples(&self, scene: &Scene, point: &Point3) -> Vec<(Vector3, Color)> {
+        let to_point = point - &self.center;
+        let mut rng = Random::from_point(point, 0);
+        let positions: Vec<(Point3, Vector3)> = stratified_samples(self.samples, &mut rng)
+            .into_iter()
+            .map(|(s, t)| {
+                let z = 1. - 2. * s;
+                let r = (1. - z * z).max(0.).sqrt();
+                let phi = 2. * PI * t;
+                let mut n = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+                if n.dot(&to_point) < 0. {
+                    n = -n;
+                }
+                (self.center + self.radius * n, n)
+            })
+            .collect();
+
+        // Only one half of the sphere is sampled
+        let pdf = 1. / (2. * PI * self.radius * self.radius);
+        sample_positions(scene, point, &positions, pdf, self.intensity * self.color)
+    }
+
+    fn intensity_at(&self, distance: f32) -> f32 {
+        self.intensity * self.radius * self.radius / (distance * distance)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        Some(LightBounds {
+            bounds: BoundingBox::new(&(self.center + (-1. * r)), &(self.center + r)),
+            direction: Vector3::new(0., 1., 0.),
+            cos_spread: -1.,
+            power: self.intensity_at(1.) * luminance(&self.color),
+        })
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Sphere: ({}, {}, {}), Radius: {}, Samples: {}, Color: ({}, {}, {}), Intensity: {}",
+            self.center.x(),
+            self.center.y(),
+            self.center.z(),
+            self.radius,
+            self.samples,
+            self.color.r,
+            self.color.g,
+            self.color.b,
+            self.intensity
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Matrix;
+    use crate::scene::color::colors::*;
+    use crate::scene::{Phong, Renderable, Sphere};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Within 5% of the energy of a point light with `intensity`
+    fn near_point_light(energy: &Color, intensity: f32) -> bool {
+        [energy.r, energy.g, energy.b]
+            .iter()
+            .all(|c| (c - intensity).abs() < 0.05 * intensity)
+    }
+
+    #[test]
+    fn unshadowed() {
+        let scene = Scene::new();
+        let mut rect = RectLight::new(
+            Point3::new(0., 4., 0.),
+            Vector3::new(1., 0., 0.),
+            Vector3::new(0., 0., 1.),
+            WHITE,
+        );
+        rect.set_samples(9);
+        rect.set_intensity(16. * PI);
+        let samples = rect.get_energy_samples(&scene, &Point3::new(0., 0., 0.));
+        assert_eq!(9, samples.len());
+
+        let (dir, energy) = rect.get_energy(&scene, &Point3::new(0., 0., 0.));
+        // The direction averages the jittered samples, which lie around the
+        // center of the light
+        assert!((Vector3::new(0., 1., 0.) - dir).len() < 0.05);
+        assert_eq!(1., rect.intensity_at(4.));
+        assert!(near_point_light(&energy, 1.));
+
+        // Twice as far away a quarter of the energy arrives
+        let (_, far) = rect.get_energy(&scene, &Point3::new(0., -4., 0.));
+        assert!(near_point_light(&far, 0.25));
+
+        // Seen at an angle less of the light faces the point
+        let (_, slanted) = rect.get_energy(&scene, &Point3::new(4., 0., 0.));
+        let expected = rect.intensity_at(32_f32.sqrt()) * 0.5_f32.sqrt();
+        assert!(near_point_light(&slanted, expected));
+
+        // The rect faces down so a point above it is not lit
+        let (_, energy) = rect.get_energy(&scene, &Point3::new(0., 5., 0.));
+        assert_eq!(BLACK, energy);
+
+        let disk = DiskLight::new(
+            Point3::new(0., 4., 0.),
+            Vector3::new(0., -1., 0.),
+            1.,
+            WHITE,
+        );
+        let (_, energy) = disk.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert!(near_point_light(&energy, disk.intensity_at(4.)));
+
+        let mut sphere = SphereLight::new(Point3::new(0., 4., 0.), 1., WHITE);
+        sphere.set_intensity(16.);
+        sphere.set_samples(64);
+        let (_, energy) = sphere.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert!(near_point_light(&energy, 1.));
+    }
+
+    #[test]
+    fn penumbra() {
+        // A small sphere between the light and the ground blocks part of the
+        // light from a point near the edge of its shadow
+        let mut scene = Scene::new();
+        let phong = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        let mut sph = Sphere::new(phong);
+        sph.set_transform(&Matrix::translate(0., 2., 0.));
+        scene.add_shape(Box::new(sph));
+
+        let mut light = SphereLight::new(Point3::new(0., 4., 0.), 1.5, WHITE);
+        light.set_samples(64);
+
+        let (_, umbra) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert_eq!(BLACK, umbra);
+
+        let (_, penumbra) = light.get_energy(&scene, &Point3::new(1.5, 0., 0.));
+        let (_, lit) = light.get_energy(&Scene::new(), &Point3::new(1.5, 0., 0.));
+        assert!(penumbra.r > 0. && penumbra.r < lit.r);
+
+        // The same point always receives the same energy
+        let (_, again) = light.get_energy(&scene, &Point3::new(1.5, 0., 0.));
+        assert_eq!(penumbra, again);
+    }
+}
@@ -0,0 +1,66 @@
+/// How the energy of a light falls off as it travels away from the light
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Attenuation {
+    /// Every point receives the full energy of the light no matter how far
+    /// away it is.
+    None,
+    /// Energy falls off with the square of the distance, as real lights do.
+    InverseSquare,
+    /// Energy is divided by `constant + linear * d + quadratic * d^2`
+    Custom {
+        constant: f32,
+        linear: f32,
+        quadratic: f32,
+    },
+}
+
+impl Attenuation {
+    /// The fraction of a light's energy which reaches a point `distance`
+    /// away from it.
+    pub fn factor(&self, distance: f32) -> f32 {
+        let denom = match *self {
+            Attenuation::None => return 1.,
+            Attenuation::InverseSquare => distance * distance,
+            Attenuation::Custom {
+                constant,
+                linear,
+                quadratic,
+            } => constant + linear * distance + quadratic * distance * distance,
+        };
+        1. / denom.max(std::f32::EPSILON)
+    }
+}
+
+impl std::fmt::Display for Attenuation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Attenuation::None => write!(f, "None"),
+            Attenuation::InverseSquare => write!(f, "Inverse Square"),
+            Attenuation::Custom {
+                constant,
+                linear,
+                quadratic,
+            } => write!(f, "Custom({}, {}, {})", constant, linear, quadratic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn factor() {
+        assert_eq!(1., Attenuation::None.factor(10.));
+        assert_eq!(0.25, Attenuation::InverseSquare.factor(2.));
+
+        let custom = Attenuation::Custom {
+            constant: 1.,
+            linear: 0.5,
+            quadratic: 0.25,
+        };
+        assert_eq!(1., custom.factor(0.));
+        assert_eq!(1. / 3., custom.factor(2.));
+        assert_eq!("Custom(1, 0.5, 0.25)", custom.to_string());
+    }
+}
@@ -0,0 +1,297 @@
+/// An axis aligned box
+use std::cell::*;
+use std::rc::Rc;
+
+use crate::math::{BoundingBox, Matrix, Point3, Ray, Vector3};
+
+use super::area_scale;
+use super::Intersection;
+use super::Material;
+use super::Renderable;
+use super::SurfaceSample;
+use super::TextureCoords;
+use super::Visibility;
+
+/**
+ * A box whose sides are aligned with the axes of its local space.
+ *
+ * Rays are intersected with the slab method, the normal is the exact normal
+ * of the face which was hit, and every face is given its own texture
+ * coordinates running from 0 to 1 across it.
+ */
+pub struct Box3 {
+    id: i32,
+    name: String,
+    bounds: BoundingBox,
+    material: Rc<RefCell<dyn Material>>,
+    transform: Matrix,
+    inv_transform: Matrix,
+    visibility: Visibility,
+}
+
+impl Box3 {
+    pub fn new(min: &Point3, max: &Point3, material: Rc<RefCell<dyn Material>>) -> Box3 {
+        Box3 {
+            id: 0,
+            name: String::from("Box"),
+            bounds: BoundingBox::new(min, max),
+            material: Rc::clone(&material),
+            transform: Matrix::identity(),
+            inv_transform: Matrix::identity(),
+            visibility: Visibility::all(),
+        }
+    }
+
+    pub fn new_with_name(
+        name: &str,
+        min: &Point3,
+        max: &Point3,
+        material: Rc<RefCell<dyn Material>>,
+    ) -> Box3 {
+        let mut b = Box3::new(min, max, material);
+        b.name = String::from(name);
+        b
+    }
+
+    /// Find the face that `p` lies on and return its outward normal and the
+    /// texture coordinates of `p` on that face.
+    fn face(&self, p: &Point3) -> (Vector3, TextureCoords) {
+        let min = self.bounds.min();
+        let size = self.bounds.extent();
+
+        // Position of the point within the box, in [0, 1] on each axis
+        let x = (p.x() - min.x()) / size.x();
+        let y = (p.y() - min.y()) / size.y();
+        let z = (p.z() - min.z()) / size.z();
+
+        // The face is the one on the axis where the point is closest to the
+        // side of the box
+        let dx = (x - 0.5).abs();
+        let dy = (y - 0.5).abs();
+        let dz = (z - 0.5).abs();
+
+        if dx >= dy && dx >= dz {
+            if x > 0.5 {
+                (Vector3::new(1., 0., 0.), (1. - z, 1. - y))
+            } else {
+                (Vector3::new(-1., 0., 0.), (z, 1. - y))
+            }
+        } else if dy >= dz {
+            if y > 0.5 {
+                (Vector3::new(0., 1., 0.), (x, z))
+            } else {
+                (Vector3::new(0., -1., 0.), (x, 1. - z))
+            }
+        } else if z > 0.5 {
+            (Vector3::new(0., 0., 1.), (x, 1. - y))
+        } else {
+            (Vector3::new(0., 0., -1.), (1. - x, 1. - y))
+        }
+    }
+
+    /// The direction of increasing u on the face with the normal `n`
+    fn face_tangent(n: &Vector3) -> Vector3 {
+        if n.x() > 0.5 {
+            Vector3::new(0., 0., -1.)
+        } else if n.x() < -0.5 {
+            Vector3::new(0., 0., 1.)
+        } else if n.z() < -0.5 {
+            Vector3::new(-1., 0., 0.)
+        } else {
+            Vector3::new(1., 0., 0.)
+        }
+    }
+}
+
+impl Renderable for Box3 {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_id(&mut self, id: i32) {
+        self.id = id;
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        // apply transformation to the ray
+        let transformed_ray = self.inv_transform * ray;
+
+        let (t0, t1) = self.bounds.intersect(&transformed_ray)?;
+        let entering = t0 > 0.;
+        let t = if entering { t0 } else { t1 };
+
+        let local_point = t * transformed_ray;
+        let (local_normal, tex_coord) = self.face(&local_point);
+        let mut normal = (self.inv_transform.transpose() * local_normal).norm();
+        if !entering {
+            normal = -normal;
+        }
+
+        Some(Intersection {
+            id: self.id,
+            t,
+            material: Rc::clone(&self.material),
+            point: t * ray,
+            eye_dir: -ray.direction().norm(),
+            normal,
+            entering,
+            tex_coord,
+            local_point,
+            tangent: self.transform * Box3::face_tangent(&local_normal),
+            outside_index: 1.,
+            wavelength: None,
+            vertex_color: None,
+        })
+    }
+
+    fn set_transform(&mut self, mat: &Matrix) {
+        self.transform = *mat;
+        self.inv_transform = self.transform.inverse();
+    }
+
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_material_mut(&mut self) -> Option<RefMut<dyn Material>> {
+        Some(self.material.borrow_mut())
+    }
+
+    fn get_material(&self) -> Option<Ref<dyn Material>> {
+        Some(self.material.borrow())
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Box(Name: {}, Material: {})",
+            self.get_name(),
+            self.material.borrow().to_string()
+        )
+    }
+
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    fn set_visibility(&mut self, visibility: Visibility) {
+        self.visibility = visibility;
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(self.bounds.transform(&self.transform))
+    }
+
+    fn sample_surface(&self, s: f32, t: f32) -> Option<SurfaceSample> {
+        let min = self.bounds.min();
+        let max = self.bounds.max();
+        let size = self.bounds.extent();
+
+        // Pick a face in proportion to its area, then a point on the face
+        let areas = [
+            size.y() * size.z(),
+            size.x() * size.z(),
+            size.x() * size.y(),
+        ];
+        let total = 2. * (areas[0] + areas[1] + areas[2]);
+        if total <= 0. {
+            return None;
+        }
+
+        let mut pick = s * total;
+        let mut face = 0;
+        while face < 5 && pick >= areas[face / 2] {
+            pick -= areas[face / 2];
+            face += 1;
+        }
+        let s = (pick / areas[face / 2]).min(1.);
+
+        let axis = face / 2;
+        let side = if face % 2 == 0 { min } else { max };
+        let lerp = |lo: f32, hi: f32, f: f32| lo + f * (hi - lo);
+        let local = match axis {
+            0 => Point3::new(side.x(), lerp(min.y(), max.y(), s), lerp(min.z(), max.z(), t)),
+            1 => Point3::new(lerp(min.x(), max.x(), s), side.y(), lerp(min.z(), max.z(), t)),
+            _ => Point3::new(lerp(min.x(), max.x(), s), lerp(min.y(), max.y(), t), side.z()),
+        };
+
+        let (n, tex_coord) = self.face(&local);
+        Some(SurfaceSample {
+            point: self.transform * local,
+            normal: (self.inv_transform.transpose() * n).norm(),
+            tex_coord,
+            pdf: 1. / (total * area_scale(&self.transform, &n)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::color::colors::WHITE;
+    use crate::scene::Phong;
+
+    fn unit_box() -> Box3 {
+        let material = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        Box3::new(
+            &Point3::new(-0.5, -0.5, -0.5),
+            &Point3::new(0.5, 0.5, 0.5),
+            material,
+        )
+    }
+
+    #[test]
+    fn intersection() {
+        let b = unit_box();
+
+        let ray = Ray::new(&Point3::new(0.25, 0., -4.), &Vector3::new(0., 0., 1.));
+        let i = b.intersect(&ray).unwrap();
+        assert_eq!(3.5, i.t);
+        assert_eq!(Vector3::new(0., 0., -1.), i.normal);
+        assert_eq!(true, i.entering);
+        assert_eq!((0.25, 0.5), i.tex_coord);
+
+        // From inside the box the normal faces back toward the ray
+        let inside = Ray::new(&Point3::new(0., 0., 0.), &Vector3::new(0., 1., 0.));
+        let i = b.intersect(&inside).unwrap();
+        assert_eq!(0.5, i.t);
+        assert_eq!(Vector3::new(0., -1., 0.), i.normal);
+        assert_eq!(false, i.entering);
+
+        let miss = Ray::new(&Point3::new(0., 1., -4.), &Vector3::new(0., 0., 1.));
+        assert_eq!(true, b.intersect(&miss).is_none());
+    }
+
+    #[test]
+    fn transformed_normals() {
+        let mut b = unit_box();
+        b.set_transform(&(Matrix::translate(0., 0., 2.) * Matrix::rotate_y(90.)));
+
+        let ray = Ray::new(&Point3::new(-4., 0., 2.), &Vector3::new(1., 0., 0.));
+        let i = b.intersect(&ray).unwrap();
+        assert_eq!(3.5, i.t);
+        assert!((i.normal - Vector3::new(-1., 0., 0.)).len() < 0.0001);
+    }
+
+    #[test]
+    fn surface_samples() {
+        let mut b = unit_box();
+        b.set_transform(&Matrix::scale(2., 1., 1.));
+
+        for i in 0..16 {
+            let sample = b.sample_surface(i as f32 / 16., 0.5).unwrap();
+            // Every sample is on the surface of the box, facing out
+            let ray = Ray::new(&(sample.point + sample.normal), &-sample.normal);
+            let hit = b.intersect(&ray).unwrap();
+            assert!((hit.point - sample.point).len() < 0.0001);
+            // Faces are picked by their area before scaling, so points on the
+            // faces stretched along x are half as dense
+            let stretched = sample.normal.x().abs() < 0.5;
+            let pdf = if stretched { 1. / 12. } else { 1. / 6. };
+            assert!((sample.pdf - pdf).abs() < 0.0001);
+        }
+    }
+}
@@ -2,112 +2,52 @@
 use std::cell::*;
 use std::rc::Rc;
 
-use crate::math::{Matrix, Point3, Ray};
+use crate::math::{BoundingBox, Matrix, Point3, Ray};
 
+use super::Box3;
 use super::Intersection;
 use super::Material;
 use super::Renderable;
-use super::Scene;
-use super::Triangle;
+use super::SurfaceSample;
+use super::Visibility;
 
+/// A unit cube centered on the origin
 pub struct Cube {
-    id: i32,
-    triangles: Scene,
-    transform: Matrix,
-    inv_transform: Matrix,
+    cube: Box3,
 }
 
 impl Cube {
     pub fn new(material: Rc<RefCell<dyn Material>>) -> Cube {
-        let v0 = Point3::new(0.5, 0.5, -0.5);
-        let v1 = Point3::new(0.5, -0.5, -0.5);
-        let v2 = Point3::new(-0.5, -0.5, -0.5);
-        let v3 = Point3::new(-0.5, 0.5, -0.5);
-
-        let v4 = Point3::new(0.5, 0.5, 0.5);
-        let v5 = Point3::new(-0.5, 0.5, 0.5);
-        let v6 = Point3::new(-0.5, -0.5, 0.5);
-        let v7 = Point3::new(0.5, -0.5, 0.5);
-
-        // front
-        let tf1 = Triangle::new(&v1, &v2, &v3, Rc::clone(&material));
-        let tf2 = Triangle::new(&v0, &v1, &v3, Rc::clone(&material));
-
-        // back
-        let tk1 = Triangle::new(&v7, &v5, &v4, Rc::clone(&material));
-        let tk2 = Triangle::new(&v5, &v7, &v6, Rc::clone(&material));
-
-        // right side
-        let tr1 = Triangle::new(&v0, &v4, &v7, Rc::clone(&material));
-        let tr2 = Triangle::new(&v7, &v1, &v0, Rc::clone(&material));
-
-        // left side
-        let tl1 = Triangle::new(&v5, &v3, &v6, Rc::clone(&material));
-        let tl2 = Triangle::new(&v6, &v3, &v2, Rc::clone(&material));
-
-        // bottom
-        let tb1 = Triangle::new(&v1, &v7, &v6, Rc::clone(&material));
-        let tb2 = Triangle::new(&v6, &v2, &v1, Rc::clone(&material));
-
-        // top
-        let tt1 = Triangle::new(&v5, &v4, &v0, Rc::clone(&material));
-        let tt2 = Triangle::new(&v0, &v3, &v5, Rc::clone(&material));
-
-        //let tris = vec![tf1, tf2, tk1, tk2, tb1, tb2, tr1, tr2, tl1, tl2, tt1, tt2];
-        let mut scene = Scene::new();
-        scene.add_shape(Box::new(tf1));
-        scene.add_shape(Box::new(tf2));
-        scene.add_shape(Box::new(tk1));
-        scene.add_shape(Box::new(tk2));
-        scene.add_shape(Box::new(tr1));
-        scene.add_shape(Box::new(tr2));
-        scene.add_shape(Box::new(tl1));
-        scene.add_shape(Box::new(tl2));
-        scene.add_shape(Box::new(tt1));
-        scene.add_shape(Box::new(tt2));
-        scene.add_shape(Box::new(tb1));
-        scene.add_shape(Box::new(tb2));
-
         Cube {
-            id: 0,
-            transform: Matrix::identity(),
-            inv_transform: Matrix::identity(),
-            triangles: scene,
+            cube: Box3::new_with_name(
+                "Cube",
+                &Point3::new(-0.5, -0.5, -0.5),
+                &Point3::new(0.5, 0.5, 0.5),
+                material,
+            ),
         }
     }
 }
 
 impl Renderable for Cube {
     fn id(&self) -> i32 {
-        self.id
+        self.cube.id()
     }
 
     fn set_id(&mut self, id: i32) {
-        self.id = id
+        self.cube.set_id(id)
     }
 
     fn intersect(&self, ray: &Ray) -> Option<Intersection> {
-        // apply transformation to the ray
-        let transformed_ray = self.inv_transform * ray;
-
-        match self.triangles.intersect(&transformed_ray) {
-            None => None,
-            Some(mut i) => {
-                i.point = i.t * ray;
-                i.eye_dir = -(ray.direction().norm());
-                i.normal = (self.inv_transform.transpose() * i.normal).norm(); // TODO: am I doing the right matrix op?
-                Some(i)
-            }
-        }
+        self.cube.intersect(ray)
     }
 
     fn set_transform(&mut self, mat: &Matrix) {
-        self.transform = *mat;
-        self.inv_transform = self.transform.inverse();
+        self.cube.set_transform(mat)
     }
 
     fn get_name(&self) -> String {
-        self.to_string()
+        self.cube.get_name()
     }
 
     fn to_string(&self) -> String {
@@ -115,14 +55,54 @@ impl Renderable for Cube {
     }
 
     fn size(&self) -> usize {
-        self.triangles.size()
+        self.cube.size()
     }
 
     fn get_material_mut(&mut self) -> Option<RefMut<dyn Material>> {
-        None
+        self.cube.get_material_mut()
     }
 
     fn get_material(&self) -> Option<Ref<dyn Material>> {
-        None
+        self.cube.get_material()
+    }
+
+    fn sample_surface(&self, s: f32, t: f32) -> Option<SurfaceSample> {
+        self.cube.sample_surface(s, t)
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        self.cube.bounding_box()
+    }
+
+    fn visibility(&self) -> Visibility {
+        self.cube.visibility()
+    }
+
+    fn set_visibility(&mut self, visibility: Visibility) {
+        self.cube.set_visibility(visibility)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vector3;
+    use crate::scene::color::colors::WHITE;
+    use crate::scene::Phong;
+
+    #[test]
+    fn texture_coords() {
+        let material = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        let cube = Cube::new(material);
+        assert_eq!(1, cube.size());
+
+        // Every face should span the full texture
+        let ray = Ray::new(&Point3::new(0.4, 0.4, -4.), &Vector3::new(0., 0., 1.));
+        let (u, v) = cube.intersect(&ray).unwrap().tex_coord;
+        assert!((u - 0.1).abs() < 0.0001 && (v - 0.1).abs() < 0.0001);
+
+        let ray = Ray::new(&Point3::new(-0.4, 4., 0.4), &Vector3::new(0., -1., 0.));
+        let (u, v) = cube.intersect(&ray).unwrap().tex_coord;
+        assert!((u - 0.1).abs() < 0.0001 && (v - 0.9).abs() < 0.0001);
     }
 }
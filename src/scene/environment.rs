@@ -0,0 +1,326 @@
+/// Light a scene with an image of its surroundings
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use crate::math::{stratified_samples, Matrix, Point3, Random, Vector3};
+
+use super::hdr::HdrImage;
+use super::{combine_samples, luminance, transmittance, Background, Color, LightSource, Scene};
+
+const DEFAULT_SAMPLES: usize = 16;
+
+/**
+An equirectangular image of the light arriving at the scene from every
+direction.  The center of the image faces +z, the top of the image is +y
+and the map can be spun around the y axis by `rotation` degrees.
+
+The map keeps a table of how bright each pixel is so that directions can be
+picked in proportion to the energy which arrives from them.
+*/
+pub struct EnvironmentMap {
+    image: HdrImage,
+    rotation: f32,
+    intensity: f32,
+    to_world: Matrix,
+    to_map: Matrix,
+
+    // Cumulative distribution of energy over the rows of the image and,
+    // for each row, over the pixels in that row
+    row_cdf: Vec<f32>,
+    col_cdf: Vec<f32>,
+    // Energy of each pixel as a fraction of the total energy
+    weights: Vec<f32>,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: HdrImage) -> EnvironmentMap {
+        let (w, h) = (image.width(), image.height());
+
+        // Rows near the poles cover less of the sphere than rows near the
+        // equator, so weight each pixel by the area it covers.
+        let mut weights = Vec::with_capacity(w * h);
+        for y in 0..h {
+            let sin_theta = (PI * (y as f32 + 0.5) / h as f32).sin();
+            for x in 0..w {
+                weights.push(luminance(&image.get(x, y)).max(0.) * sin_theta);
+            }
+        }
+        let total: f32 = weights.iter().sum();
+        if total > 0. {
+            for wt in weights.iter_mut() {
+                *wt /= total;
+            }
+        }
+
+        let mut row_cdf = Vec::with_capacity(h);
+        let mut col_cdf = Vec::with_capacity(w * h);
+        let mut rows_sum = 0.;
+        for y in 0..h {
+            let row = &weights[y * w..(y + 1) * w];
+            let row_total: f32 = row.iter().sum();
+            let mut sum = 0.;
+            for wt in row.iter() {
+                sum += wt;
+                col_cdf.push(if row_total > 0. { sum / row_total } else { 1. });
+            }
+            rows_sum += row_total;
+            row_cdf.push(rows_sum);
+        }
+
+        EnvironmentMap {
+            image,
+            rotation: 0.,
+            intensity: 1.,
+            to_world: Matrix::identity(),
+            to_map: Matrix::identity(),
+            row_cdf,
+            col_cdf,
+            weights,
+        }
+    }
+
+    pub fn open(path: &str) -> std::io::Result<EnvironmentMap> {
+        Ok(EnvironmentMap::new(HdrImage::open(path)?))
+    }
+
+    /// Spin the map around the y axis by `degrees`
+    pub fn set_rotation(&mut self, degrees: f32) {
+        self.rotation = degrees;
+        self.to_world = Matrix::rotate_y(degrees);
+        self.to_map = self.to_world.inverse();
+    }
+
+    /// Scale the energy of every pixel in the map
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    /// Pick a direction, with the chance of each direction being picked in
+    /// proportion to its energy.  Returns the direction and the probability
+    /// density of picking it.
+    fn sample(&self, s: f32, t: f32) -> Option<(Vector3, f32)> {
+        let (w, h) = (self.image.width(), self.image.height());
+        if self.row_cdf.last().is_none_or(|total| *total <= 0.) {
+            return None;
+        }
+
+        let s = s * self.row_cdf[h - 1];
+        let y = self.row_cdf.partition_point(|c| *c <= s).min(h - 1);
+        let row = &self.col_cdf[y * w..(y + 1) * w];
+        let x = row.partition_point(|c| *c <= t).min(w - 1);
+
+        // Spread the sample across the pixel it landed in
+        let mut rng = Random::new((s.to_bits() as u64) << 32 | t.to_bits() as u64);
+        let u = (x as f32 + rng.next_f32()) / w as f32;
+        let v = (y as f32 + rng.next_f32()) / h as f32;
+
+        let theta = PI * v;
+        let phi = 2. * PI * u - PI;
+        let local = Vector3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos(),
+        );
+
+        // Probability density over the image, then over the sphere
+        let pdf_image = self.weights[y * w + x] * (w * h) as f32;
+        let pdf = pdf_image / (2. * PI * PI * theta.sin().max(1e-4));
+        Some((self.to_world * local, pdf))
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn radiance(&self, dir: &Vector3) -> Color {
+        let d = (self.to_map * *dir).norm();
+        let (w, h) = (self.image.width(), self.image.height());
+        let u = (d.x().atan2(d.z()) + PI) / (2. * PI);
+        let v = d.y().clamp(-1., 1.).acos() / PI;
+        let x = ((u * w as f32) as usize).min(w - 1);
+        let y = ((v * h as f32) as usize).min(h - 1);
+        self.intensity * self.image.get(x, y)
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Environment({}x{}, Rotation: {}, Intensity: {})",
+            self.image.width(),
+            self.image.height(),
+            self.rotation,
+            self.intensity
+        )
+    }
+}
+
+/**
+Uses an `EnvironmentMap` as a light source.  Each point is lit by a number
+of directions picked from the map in proportion to their energy, which
+gives soft shadows from bright areas such as the sun or windows.
+
+The energy is scaled so that a map of uniform radiance `c` lights an
+unshadowed surface with the energy `c`.
+*/
+pub struct EnvironmentLight {
+    map: Rc<EnvironmentMap>,
+    samples: usize,
+}
+
+impl EnvironmentLight {
+    pub fn new(map: Rc<EnvironmentMap>) -> EnvironmentLight {
+        EnvironmentLight {
+            map,
+            samples: DEFAULT_SAMPLES,
+        }
+    }
+
+    /// Set the number of directions sampled from each point
+    pub fn set_samples(&mut self, samples: usize) {
+        self.samples = samples.max(1);
+    }
+}
+
+impl LightSource for EnvironmentLight {
+    fn get_energy(&self, scene: &Scene, point: &Point3) -> (Vector3, Color) {
+        combine_samples(&self.get_energy_samples(scene, point))
+    }
+
+    fn get_energy_samples(&self, scene: &Scene, point: &Point3) -> Vec<(Vector3, Color)> {
+        let mut rng = Random::from_point(point, 0);
+        let n = self.samples as f32;
+        stratified_samples(self.samples, &mut rng)
+            .into_iter()
+            .filter_map(|(s, t)| self.map.sample(s, t))
+            .map(|(dir, pdf)| {
+                let energy = (1. / (pdf * n * PI))
+                    * transmittance(scene, point, &dir, std::f32::INFINITY)
+                    * self.map.radiance(&dir);
+                (dir, energy)
+            })
+            .collect()
+    }
+
+    fn to_string(&self) -> String {
+        format!("{}, Samples: {}", self.map.to_string(), self.samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sky() -> HdrImage {
+        // The top half is bright and the bottom half is dark
+        let mut pixels = vec![];
+        for y in 0..8 {
+            for _ in 0..16 {
+                pixels.push(if y < 4 {
+                    Color::new(1., 1., 1.)
+                } else {
+                    Color::new(0., 0., 0.)
+                });
+            }
+        }
+        HdrImage::new(16, 8, pixels)
+    }
+
+    #[test]
+    fn radiance() {
+        let mut map = EnvironmentMap::new(sky());
+        assert_eq!(
+            Color::new(1., 1., 1.),
+            map.radiance(&Vector3::new(0., 1., 0.))
+        );
+        assert_eq!(
+            Color::new(0., 0., 0.),
+            map.radiance(&Vector3::new(0., -1., 0.))
+        );
+
+        map.set_intensity(2.);
+        assert_eq!(
+            Color::new(2., 2., 2.),
+            map.radiance(&Vector3::new(1., 0.1, 0.))
+        );
+    }
+
+    #[test]
+    fn importance_sampling() {
+        let map = EnvironmentMap::new(sky());
+        for i in 0..16 {
+            let (dir, pdf) = map.sample(i as f32 / 16., 0.5).unwrap();
+            // Only the bright half of the map is ever picked
+            assert!(dir.y() >= 0.);
+            assert!(pdf > 0.);
+        }
+    }
+
+    #[test]
+    fn environment_light() {
+        let scene = Scene::new();
+        let mut light = EnvironmentLight::new(Rc::new(EnvironmentMap::new(sky())));
+        light.set_samples(64);
+
+        let samples = light.get_energy_samples(&scene, &Point3::new(0., 0., 0.));
+        assert_eq!(64, samples.len());
+
+        // Uniform light over the upper hemisphere: the energy arrives from
+        // above, on average.
+        let (dir, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert!(dir.y() > 0.9);
+        assert!(energy.r > 0.);
+    }
+
+    #[test]
+    fn constant_map() {
+        let scene = Scene::new();
+        let mut map = EnvironmentMap::new(HdrImage::new(16, 8, vec![Color::new(1., 1., 1.); 128]));
+        map.set_intensity(2.);
+        let mut light = EnvironmentLight::new(Rc::new(map));
+        light.set_samples(256);
+
+        // A diffuse surface facing up with an albedo of 0.5 reflects
+        // intensity * albedo, as it would under a uniform ambient light
+        let albedo = 0.5;
+        let normal = Vector3::new(0., 1., 0.);
+        let reflected: f32 = light
+            .get_energy_samples(&scene, &Point3::new(0., 0., 0.))
+            .iter()
+            .map(|(dir, e)| albedo * e.g * dir.dot(&normal).max(0.))
+            .sum();
+        assert!((reflected - 2. * albedo).abs() < 0.05);
+    }
+
+    #[test]
+    fn rotated_texel() {
+        // A single bright texel, a little below the horizon and towards -x
+        let (x, y) = (4, 4);
+        let mut pixels = vec![Color::new(0., 0., 0.); 128];
+        pixels[y * 16 + x] = Color::new(10., 10., 10.);
+        let image = HdrImage::new(16, 8, pixels);
+
+        let theta = PI * (y as f32 + 0.5) / 8.;
+        let phi = 2. * PI * (x as f32 + 0.5) / 16. - PI;
+        let texel = Vector3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos(),
+        );
+        assert!(texel.x() < -0.9);
+
+        // Spun by 90 degrees around y, the light arrives from +z
+        let mut map = EnvironmentMap::new(image);
+        map.set_rotation(90.);
+        let rotated = Vector3::new(texel.z(), texel.y(), -texel.x());
+        assert!(rotated.z() > 0.9);
+        assert_eq!(Color::new(10., 10., 10.), map.radiance(&rotated));
+        assert_eq!(Color::new(0., 0., 0.), map.radiance(&texel));
+
+        for i in 0..8 {
+            let (dir, _) = map.sample(i as f32 / 8., 0.5).unwrap();
+            assert!(dir.dot(&rotated) > 0.95);
+        }
+
+        let light = EnvironmentLight::new(Rc::new(map));
+        let (dir, energy) = light.get_energy(&Scene::new(), &Point3::new(0., 0., 0.));
+        assert!(dir.dot(&rotated) > 0.95);
+        assert!(energy.r > 0.);
+    }
+}
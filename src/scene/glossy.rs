@@ -0,0 +1,186 @@
+/// Blurred reflections and refractions, traced as a few rays spread around
+/// the perfectly specular direction
+use std::f32::consts::PI;
+
+use crate::math::{stratified_samples, Random, Vector3};
+
+use super::{frame, BsdfSample};
+
+/// How the rays of a glossy reflection or refraction are spread out
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GlossyLobe {
+    /// A cosine lobe around the perfectly specular direction, like the
+    /// highlight of a `Phong` material
+    Phong,
+    /// Microfacet normals from the GGX distribution, which gives the long
+    /// tails of brushed metal and frosted glass
+    Ggx,
+}
+
+/**
+The roughness of a specular surface.  A `roughness` of 0 gives a perfect
+mirror or clear glass.  Otherwise each mirror reflection and refraction is
+traced as `samples` rays picked from `lobe`, which blur more as the
+roughness goes to 1.
+
+Rays are picked in proportion to the lobe, so each carries an equal share
+of the energy.  The rays of a hit are always the same, which keeps renders
+repeatable and lets a ray tree re-weight its branches.  Every glossy bounce
+multiplies the number of rays by `samples`, so keep it and the depth small:
+a ray forest keeps a node for each of these rays, so with the default of 4
+samples two glossy bounces already store 16 times as many nodes for a pixel.
+*/
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Gloss {
+    pub roughness: f32,
+    pub samples: usize,
+    pub lobe: GlossyLobe,
+}
+
+impl Default for Gloss {
+    fn default() -> Gloss {
+        Gloss {
+            roughness: 0.,
+            samples: 4,
+            lobe: GlossyLobe::Ggx,
+        }
+    }
+}
+
+impl Gloss {
+    pub fn new(roughness: f32, samples: usize, lobe: GlossyLobe) -> Gloss {
+        Gloss {
+            roughness,
+            samples,
+            lobe,
+        }
+    }
+
+    /**
+    Spread the perfectly specular `ideal` sample of a surface with the
+    normal `n` into glossy samples.  `scatter` gives the direction light
+    leaves in when the surface is turned to face a microfacet normal, such
+    as the reflection of the eye direction about it, or None if there is
+    none.  Directions which end up on the other side of the surface than
+    `ideal` are dropped along with their energy.
+    */
+    pub fn spread(
+        &self,
+        ideal: &BsdfSample,
+        n: &Vector3,
+        scatter: impl Fn(&Vector3) -> Option<Vector3>,
+        rng: &mut Random,
+    ) -> Vec<BsdfSample> {
+        if self.roughness <= 0. || self.samples == 0 {
+            return vec![*ideal];
+        }
+
+        let alpha = (self.roughness * self.roughness).max(1e-4);
+        let above = ideal.wi.dot(n) >= 0.;
+        let weight = (1. / self.samples as f32) * ideal.weight;
+        stratified_samples(self.samples, rng)
+            .into_iter()
+            .filter_map(|(u, v)| match self.lobe {
+                GlossyLobe::Phong => {
+                    // The exponent with about the same spread as GGX
+                    let exponent = 2. / (alpha * alpha) - 2.;
+                    let cos_theta = u.powf(1. / (exponent + 1.));
+                    Some(around(&ideal.wi, cos_theta, v))
+                }
+                GlossyLobe::Ggx => {
+                    let a2 = alpha * alpha;
+                    let cos_theta = ((1. - u) / (1. + (a2 - 1.) * u)).sqrt();
+                    scatter(&around(n, cos_theta, v))
+                }
+            })
+            .filter(|wi| (wi.dot(n) >= 0.) == above)
+            .map(|wi| BsdfSample { wi, weight, ..*ideal })
+            .collect()
+    }
+}
+
+/// A direction at the angle with cosine `cos_theta` from `axis`, turned
+/// around it by `v` of a full turn
+fn around(axis: &Vector3, cos_theta: f32, v: f32) -> Vector3 {
+    let (t, b) = frame(axis);
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    (sin_theta * phi.cos() * t + sin_theta * phi.sin() * b + cos_theta * *axis).norm()
+}
+
+/// Refract `wo`, pointing away from the surface, through the surface with
+/// the normal `n` on the side of `wo`.  `eta` is the ratio of the index on
+/// the side of `wo` to the one on the other side.
+pub fn refract(wo: &Vector3, n: &Vector3, eta: f32) -> Option<Vector3> {
+    let cos_i = wo.dot(n);
+    let sin_t2 = eta * eta * (1. - cos_i * cos_i);
+    if sin_t2 >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin_t2).sqrt();
+    Some(((eta * cos_i - cos_t) * *n - eta * *wo).norm())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::colors::*;
+
+    fn mirror() -> BsdfSample {
+        BsdfSample {
+            wi: Vector3::new(-0.6, 0.8, 0.),
+            weight: WHITE,
+            pdf: 1.,
+            delta: true,
+        }
+    }
+
+    #[test]
+    fn spread() {
+        let n = Vector3::new(0., 1., 0.);
+        let wo = Vector3::new(0.6, 0.8, 0.);
+        let reflect = |m: &Vector3| Some(wo.reflect(m).norm());
+        let mut rng = Random::new(1);
+
+        // A smooth surface keeps the ideal sample
+        let smooth = Gloss::default();
+        assert_eq!(1, smooth.spread(&mirror(), &n, reflect, &mut rng).len());
+
+        for lobe in &[GlossyLobe::Phong, GlossyLobe::Ggx] {
+            let glossy = Gloss::new(0.3, 16, *lobe);
+            let samples = glossy.spread(&mirror(), &n, reflect, &mut rng);
+            assert!(samples.len() > 8 && samples.len() <= 16);
+
+            // Spread around the mirror direction and above the surface
+            let mean: Vector3 = samples
+                .iter()
+                .fold(Vector3::new(0., 0., 0.), |m, s| m + s.wi);
+            assert!(mean.norm().dot(&mirror().wi) > 0.95);
+            assert!(samples.iter().all(|s| s.wi.dot(&n) >= 0.));
+            assert!(samples.iter().any(|s| s.wi.dot(&mirror().wi) < 0.999));
+
+            let total: f32 = samples.iter().map(|s| s.weight.g).sum();
+            assert!(total <= 1.0001);
+        }
+
+        // Rougher surfaces blur more
+        let spread_of = |roughness: f32| -> f32 {
+            let gloss = Gloss::new(roughness, 64, GlossyLobe::Ggx);
+            let samples = gloss.spread(&mirror(), &n, reflect, &mut Random::new(2));
+            samples.iter().map(|s| 1. - s.wi.dot(&mirror().wi)).sum::<f32>()
+                / samples.len() as f32
+        };
+        assert!(spread_of(0.6) > spread_of(0.2));
+    }
+
+    #[test]
+    fn refraction() {
+        let n = Vector3::new(0., 1., 0.);
+        let wo = Vector3::new(0.6, 0.8, 0.);
+        let wt = refract(&wo, &n, 1. / 1.5).unwrap();
+        assert!(wt.y() < 0.);
+        assert!((wt.x() + 0.6 / 1.5).abs() < 0.0001);
+
+        assert!(refract(&Vector3::new(0.8, 0.6, 0.), &n, 1.5).is_none());
+    }
+}
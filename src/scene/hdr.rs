@@ -0,0 +1,300 @@
+/// Load high dynamic range images from Radiance HDR and PFM files
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result};
+use std::path::Path;
+
+use super::Color;
+
+/// An image whose pixels are stored as floating point colors, with no
+/// limit on how bright a pixel can be.  Rows are stored from top to bottom.
+pub struct HdrImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl HdrImage {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> HdrImage {
+        assert_eq!(width * height, pixels.len());
+        HdrImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Load an image, choosing the format from the extension of the file:
+    /// `.pfm` files are read as PFM and everything else as Radiance HDR.
+    pub fn open(path: &str) -> Result<HdrImage> {
+        let is_pfm = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("pfm"));
+        let reader = BufReader::new(File::open(path)?);
+        if is_pfm {
+            read_pfm(reader)
+        } else {
+            read_hdr(reader)
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Reads a byte buffer front to back
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        let b = *self
+            .bytes
+            .get(self.pos)
+            .ok_or_else(|| invalid("Unexpected end of image"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.pos + n > self.bytes.len() {
+            return Err(invalid("Unexpected end of image"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn line(&mut self) -> Result<&'a str> {
+        let rest = &self.bytes[self.pos..];
+        let end = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(|| invalid("Unexpected end of header"))?;
+        self.pos += end + 1;
+        std::str::from_utf8(&rest[..end]).map_err(|_| invalid("Header is not valid text"))
+    }
+
+    /// Read a whitespace delimited word and the single whitespace byte
+    /// which ends it.
+    fn word(&mut self) -> Result<&'a str> {
+        while self.byte()?.is_ascii_whitespace() {}
+        let start = self.pos - 1;
+        while !self.byte()?.is_ascii_whitespace() {}
+        std::str::from_utf8(&self.bytes[start..self.pos - 1])
+            .map_err(|_| invalid("Header is not valid text"))
+    }
+}
+
+/**
+ * Read a Radiance RGBE image.
+ *
+ * Only the standard `-Y height +X width` orientation is supported.
+ * Scanlines may either be flat or use the run length encoding of newer
+ * Radiance files.
+ */
+pub fn read_hdr<R: Read>(mut reader: R) -> Result<HdrImage> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    let mut cursor = Cursor {
+        bytes: &bytes,
+        pos: 0,
+    };
+
+    let magic = cursor.line()?;
+    if !magic.starts_with("#?") {
+        return Err(invalid("Not a Radiance HDR file"));
+    }
+    loop {
+        let line = cursor.line()?.trim();
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid("Only RGBE Radiance files are supported"));
+        }
+    }
+
+    let resolution: Vec<&str> = cursor.line()?.split_whitespace().collect();
+    let (height, width) = match resolution.as_slice() {
+        ["-Y", h, "+X", w] => (parse_dim(h)?, parse_dim(w)?),
+        _ => return Err(invalid("Unsupported HDR orientation")),
+    };
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        read_scanline(&mut cursor, &mut scanline)?;
+        pixels.extend(scanline.iter().map(rgbe_to_color));
+    }
+
+    Ok(HdrImage::new(width, height, pixels))
+}
+
+fn parse_dim(s: &str) -> Result<usize> {
+    s.parse::<usize>()
+        .map_err(|_| invalid("Invalid image dimension"))
+}
+
+fn read_scanline(cursor: &mut Cursor, scanline: &mut [[u8; 4]]) -> Result<()> {
+    let width = scanline.len();
+    let is_rle = match cursor.bytes.get(cursor.pos..cursor.pos + 4) {
+        Some(head) => {
+            (8..0x8000).contains(&width)
+                && head[0] == 2
+                && head[1] == 2
+                && ((head[2] as usize) << 8 | head[3] as usize) == width
+        }
+        None => false,
+    };
+
+    if !is_rle {
+        // A flat scanline: every pixel is stored as four bytes
+        for px in scanline.iter_mut() {
+            px.copy_from_slice(cursor.take(4)?);
+        }
+        return Ok(());
+    }
+    cursor.pos += 4;
+
+    // Each channel is stored separately as a series of runs and literals
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = cursor.byte()? as usize;
+            if count > 128 {
+                let count = count - 128;
+                let value = cursor.byte()?;
+                if x + count > width {
+                    return Err(invalid("HDR run overflows scanline"));
+                }
+                for px in scanline[x..x + count].iter_mut() {
+                    px[channel] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid("Invalid HDR scanline"));
+                }
+                for (px, value) in scanline[x..x + count].iter_mut().zip(cursor.take(count)?) {
+                    px[channel] = *value;
+                }
+                x += count;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn rgbe_to_color(rgbe: &[u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::new(0., 0., 0.);
+    }
+    let f = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+    Color::new(rgbe[0] as f32 * f, rgbe[1] as f32 * f, rgbe[2] as f32 * f)
+}
+
+/**
+ * Read a Portable Float Map.  `PF` files are color and `Pf` files are
+ * grayscale.  A negative scale marks little endian data.
+ */
+pub fn read_pfm<R: Read>(mut reader: R) -> Result<HdrImage> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    let mut cursor = Cursor {
+        bytes: &bytes,
+        pos: 0,
+    };
+
+    let channels = match cursor.word()? {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid("Not a PFM file")),
+    };
+    let width = parse_dim(cursor.word()?)?;
+    let height = parse_dim(cursor.word()?)?;
+    let scale = cursor
+        .word()?
+        .parse::<f32>()
+        .map_err(|_| invalid("Invalid PFM scale"))?;
+    let little_endian = scale < 0.;
+
+    let mut pixels = vec![Color::new(0., 0., 0.); width * height];
+    let mut value = [0f32; 3];
+    // Rows are stored from the bottom of the image to the top
+    for y in (0..height).rev() {
+        for x in 0..width {
+            for v in value.iter_mut().take(channels) {
+                let mut b = [0u8; 4];
+                b.copy_from_slice(cursor.take(4)?);
+                *v = if little_endian {
+                    f32::from_le_bytes(b)
+                } else {
+                    f32::from_be_bytes(b)
+                };
+            }
+            pixels[y * width + x] = if channels == 1 {
+                Color::new(value[0], value[0], value[0])
+            } else {
+                Color::new(value[0], value[1], value[2])
+            };
+        }
+    }
+
+    Ok(HdrImage::new(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hdr() {
+        let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        // A flat scanline of 1.0 red
+        for _ in 0..8 {
+            file.extend_from_slice(&[128, 0, 0, 129]);
+        }
+        // A run length encoded scanline of 0.5 white
+        file.extend_from_slice(&[2, 2, 0, 8]);
+        for _ in 0..4 {
+            file.extend_from_slice(&[128 + 8, 128]);
+        }
+
+        let img = read_hdr(&file[..]).unwrap();
+        assert_eq!(8, img.width());
+        assert_eq!(2, img.height());
+        assert_eq!(Color::new(1., 0., 0.), img.get(3, 0));
+        assert_eq!(Color::new(0.5, 0.5, 0.5), img.get(7, 1));
+    }
+
+    #[test]
+    fn pfm() {
+        let mut file = b"PF\n1 2\n-1.0\n".to_vec();
+        for v in &[1f32, 2., 3., 4., 5., 6.] {
+            file.extend_from_slice(&v.to_le_bytes());
+        }
+
+        let img = read_pfm(&file[..]).unwrap();
+        // The first row in the file is the bottom of the image
+        assert_eq!(Color::new(4., 5., 6.), img.get(0, 0));
+        assert_eq!(Color::new(1., 2., 3.), img.get(0, 1));
+
+        assert_eq!(true, read_pfm(&b"P6\n1 1\n255\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,413 @@
+/// Terrain defined by a regular grid of heights
+use std::cell::*;
+use std::rc::Rc;
+
+use crate::math::{BoundingBox, Matrix, Point3, Ray, Vector3};
+
+use super::Intersection;
+use super::Material;
+use super::Renderable;
+use super::Visibility;
+
+/**
+ * A `Heightfield` is a terrain surface built from a grid of heights.
+ *
+ * The grid is centered on the origin and spans `width` along the X axis and
+ * `depth` along the Z axis, with each height scaled by `scale` along the Y
+ * axis.  Each grid cell is split into two triangles, but rather than turning
+ * the grid into `Triangle`s, rays walk the cells they pass over (a 2D DDA)
+ * and only test the triangles of those cells.
+ *
+ * Normals are interpolated from per vertex normals and the texture
+ * coordinates span [0, 1] across the whole terrain.
+ */
+pub struct Heightfield {
+    id: i32,
+    name: String,
+    columns: usize,
+    rows: usize,
+    heights: Vec<f32>,
+    normals: Vec<Vector3>,
+    width: f32,
+    depth: f32,
+    scale: f32,
+    bounds: BoundingBox,
+    material: Rc<RefCell<dyn Material>>,
+    transform: Matrix,
+    inv_transform: Matrix,
+    visibility: Visibility,
+}
+
+impl Heightfield {
+    /// Creates a heightfield from `heights`, which holds `rows` rows of
+    /// `columns` heights each.  Row 0 is at -Z and column 0 is at -X.
+    pub fn new(
+        heights: Vec<f32>,
+        columns: usize,
+        rows: usize,
+        width: f32,
+        depth: f32,
+        scale: f32,
+        material: Rc<RefCell<dyn Material>>,
+    ) -> Heightfield {
+        assert!(
+            columns >= 2 && rows >= 2,
+            "A heightfield needs at least 2x2 heights"
+        );
+        assert_eq!(columns * rows, heights.len(), "Heightfield is missing heights");
+
+        let mut hf = Heightfield {
+            id: 0,
+            name: String::from("Heightfield"),
+            columns,
+            rows,
+            heights,
+            normals: vec![],
+            width,
+            depth,
+            scale,
+            bounds: BoundingBox::empty(),
+            material: Rc::clone(&material),
+            transform: Matrix::identity(),
+            inv_transform: Matrix::identity(),
+            visibility: Visibility::all(),
+        };
+
+        let mut bounds = BoundingBox::empty();
+        for row in 0..rows {
+            for col in 0..columns {
+                bounds = bounds.grow(&hf.vertex(col, row));
+            }
+        }
+        // Keep the box from being flat so that rays can still enter it
+        hf.bounds = bounds.pad(0.0001);
+
+        hf.normals = (0..rows)
+            .flat_map(|row| (0..columns).map(move |col| (col, row)))
+            .map(|(col, row)| hf.vertex_normal(col, row))
+            .collect();
+
+        hf
+    }
+
+    /// Loads a grayscale image and uses the brightness of each pixel, in
+    /// [0, 1], as a height.
+    pub fn from_image(
+        path: &str,
+        width: f32,
+        depth: f32,
+        scale: f32,
+        material: Rc<RefCell<dyn Material>>,
+    ) -> image::ImageResult<Heightfield> {
+        let img = image::open(path)?.to_luma();
+        let (columns, rows) = img.dimensions();
+        let heights = img.pixels().map(|p| p[0] as f32 / 255.).collect();
+        Ok(Heightfield::new(
+            heights,
+            columns as usize,
+            rows as usize,
+            width,
+            depth,
+            scale,
+            material,
+        ))
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+
+    fn cell_width(&self) -> f32 {
+        self.width / (self.columns - 1) as f32
+    }
+
+    fn cell_depth(&self) -> f32 {
+        self.depth / (self.rows - 1) as f32
+    }
+
+    fn height(&self, col: usize, row: usize) -> f32 {
+        self.scale * self.heights[row * self.columns + col]
+    }
+
+    fn vertex(&self, col: usize, row: usize) -> Point3 {
+        Point3::new(
+            -0.5 * self.width + col as f32 * self.cell_width(),
+            self.height(col, row),
+            -0.5 * self.depth + row as f32 * self.cell_depth(),
+        )
+    }
+
+    /// Compute the normal at a vertex using the central difference of the
+    /// neighboring heights.
+    fn vertex_normal(&self, col: usize, row: usize) -> Vector3 {
+        let left = if col > 0 { col - 1 } else { col };
+        let right = if col + 1 < self.columns { col + 1 } else { col };
+        let back = if row > 0 { row - 1 } else { row };
+        let front = if row + 1 < self.rows { row + 1 } else { row };
+
+        let dx = (self.height(right, row) - self.height(left, row))
+            / ((right - left) as f32 * self.cell_width());
+        let dz = (self.height(col, front) - self.height(col, back))
+            / ((front - back) as f32 * self.cell_depth());
+        Vector3::new(-dx, 1., -dz).norm()
+    }
+
+    /// Test the two triangles covering the cell at `(col, row)`.  Returns the
+    /// ray parameter, the interpolated normal and the geometric normal of the
+    /// hit.
+    fn intersect_cell(&self, ray: &Ray, col: usize, row: usize) -> Option<(f32, Vector3, Vector3)> {
+        let corners = [(col, row), (col, row + 1), (col + 1, row), (col + 1, row + 1)];
+        let tris = [[0, 1, 2], [2, 1, 3]];
+
+        let mut nearest: Option<(f32, Vector3, Vector3)> = None;
+        for tri in tris.iter() {
+            let (c0, c1, c2) = (corners[tri[0]], corners[tri[1]], corners[tri[2]]);
+            let v0 = self.vertex(c0.0, c0.1);
+            let v1 = self.vertex(c1.0, c1.1);
+            let v2 = self.vertex(c2.0, c2.1);
+            if let Some((t, u, v)) = intersect_triangle(ray, &v0, &v1, &v2) {
+                if nearest.is_none_or(|(nt, _, _)| t < nt) {
+                    let n0 = self.normals[c0.1 * self.columns + c0.0];
+                    let n1 = self.normals[c1.1 * self.columns + c1.0];
+                    let n2 = self.normals[c2.1 * self.columns + c2.0];
+                    let normal = ((1. - u - v) * n0 + u * n1 + v * n2).norm();
+                    let geometric = (v1 - v0).cross(&(v2 - v0)).norm();
+                    nearest = Some((t, normal, geometric));
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Walk the cells of the grid which lie under the ray, in the order that
+    /// the ray passes over them, and return the first hit.
+    fn trace(&self, ray: &Ray) -> Option<(f32, Vector3, Vector3)> {
+        let (t_enter, t_exit) = self.bounds.intersect(ray)?;
+        let t_enter = t_enter.max(0.);
+
+        let o = ray.origin();
+        let d = ray.direction();
+        let start = o + t_enter * d;
+
+        let (cw, cd) = (self.cell_width(), self.cell_depth());
+        let x0 = -0.5 * self.width;
+        let z0 = -0.5 * self.depth;
+        let max_col = (self.columns - 2) as i64;
+        let max_row = (self.rows - 2) as i64;
+
+        let mut col = (((start.x() - x0) / cw).floor() as i64).max(0).min(max_col);
+        let mut row = (((start.z() - z0) / cd).floor() as i64).max(0).min(max_row);
+
+        // Setup the DDA: the ray parameter at which the ray crosses into the
+        // next column/row and how far apart those crossings are.
+        let (step_col, mut t_next_col, t_delta_col) = dda_axis(o.x(), d.x(), x0, cw, col);
+        let (step_row, mut t_next_row, t_delta_row) = dda_axis(o.z(), d.z(), z0, cd, row);
+
+        loop {
+            if let Some(hit) = self.intersect_cell(ray, col as usize, row as usize) {
+                return Some(hit);
+            }
+
+            if t_next_col < t_next_row {
+                if t_next_col > t_exit {
+                    return None;
+                }
+                col += step_col;
+                t_next_col += t_delta_col;
+            } else {
+                if t_next_row > t_exit {
+                    return None;
+                }
+                row += step_row;
+                t_next_row += t_delta_row;
+            }
+
+            if col < 0 || col > max_col || row < 0 || row > max_row {
+                return None;
+            }
+        }
+    }
+}
+
+/// Returns the step direction, the ray parameter of the first cell boundary
+/// crossing and the change in the ray parameter between crossings for one
+/// axis of the grid.
+fn dda_axis(o: f32, d: f32, min: f32, cell: f32, index: i64) -> (i64, f32, f32) {
+    use std::f32::INFINITY;
+
+    if d > 0. {
+        let boundary = min + (index + 1) as f32 * cell;
+        (1, (boundary - o) / d, cell / d)
+    } else if d < 0. {
+        let boundary = min + index as f32 * cell;
+        (-1, (boundary - o) / d, -cell / d)
+    } else {
+        (0, INFINITY, INFINITY)
+    }
+}
+
+/// Moller-Trumbore ray triangle intersection.  Returns the ray parameter and
+/// the barycentric coordinates of `v1` and `v2`.
+fn intersect_triangle(ray: &Ray, v0: &Point3, v1: &Point3, v2: &Point3) -> Option<(f32, f32, f32)> {
+    let v0v1 = v1 - v0;
+    let v0v2 = v2 - v0;
+    let pvec = ray.direction().cross(&v0v2);
+    let det = v0v1.dot(&pvec);
+
+    if det.abs() < std::f32::EPSILON {
+        return None;
+    }
+    let inv_det = 1. / det;
+
+    let tvec = ray.origin() - v0;
+    let u = tvec.dot(&pvec) * inv_det;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+
+    let qvec = tvec.cross(&v0v1);
+    let v = ray.direction().dot(&qvec) * inv_det;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+
+    let t = v0v2.dot(&qvec) * inv_det;
+    if t < 0. {
+        None
+    } else {
+        Some((t, u, v))
+    }
+}
+
+impl Renderable for Heightfield {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_id(&mut self, id: i32) {
+        self.id = id;
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let transformed_ray = self.inv_transform * ray;
+        let (t, normal, geometric) = self.trace(&transformed_ray)?;
+
+        let local = t * transformed_ray;
+        let u = (local.x() / self.width + 0.5).clamp(0., 1.);
+        let v = (local.z() / self.depth + 0.5).clamp(0., 1.);
+
+        // The terrain is open, so when it is seen from below flip the
+        // normal to face the ray.
+        let entering = geometric.dot(&transformed_ray.direction()) < 0.;
+        let mut normal = (self.inv_transform.transpose() * normal).norm();
+        if !entering {
+            normal = -normal;
+        }
+
+        Some(Intersection {
+            id: self.id,
+            t,
+            material: Rc::clone(&self.material),
+            point: t * ray,
+            eye_dir: -ray.direction().norm(),
+            normal,
+            entering,
+            tex_coord: (u, v),
+            local_point: local,
+            tangent: self.transform * Vector3::new(1., 0., 0.),
+            outside_index: 1.,
+            wavelength: None,
+            vertex_color: None,
+        })
+    }
+
+    fn set_transform(&mut self, mat: &Matrix) {
+        self.transform = *mat;
+        self.inv_transform = self.transform.inverse();
+    }
+
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_material_mut(&mut self) -> Option<RefMut<dyn Material>> {
+        Some(self.material.borrow_mut())
+    }
+
+    fn get_material(&self) -> Option<Ref<dyn Material>> {
+        Some(self.material.borrow())
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Heightfield(Name: {}, Grid: {}x{}, Material: {})",
+            self.get_name(),
+            self.columns,
+            self.rows,
+            self.material.borrow().to_string()
+        )
+    }
+
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    fn set_visibility(&mut self, visibility: Visibility) {
+        self.visibility = visibility;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::color::colors::WHITE;
+    use crate::scene::Phong;
+
+    fn assert_near(expected: f32, actual: f32) {
+        assert!(
+            (expected - actual).abs() < 0.0001,
+            "expected {} but got {}",
+            expected,
+            actual
+        );
+    }
+
+    fn ramp() -> Heightfield {
+        // Rises by 1 unit from -X to +X over a width of 4
+        let heights = vec![0., 0.5, 1., 0., 0.5, 1., 0., 0.5, 1.];
+        let material = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        Heightfield::new(heights, 3, 3, 4., 4., 1., material)
+    }
+
+    #[test]
+    fn intersect_from_above() {
+        let hf = ramp();
+
+        let ray = Ray::new(&Point3::new(1., 4., 0.5), &Vector3::new(0., -1., 0.));
+        let i = hf.intersect(&ray).unwrap();
+        assert_near(3.25, i.t);
+        assert_near(0.75, i.tex_coord.0);
+        assert_near(0.625, i.tex_coord.1);
+        assert_eq!(true, i.entering);
+        assert_eq!(Vector3::new(-0.25, 1., 0.).norm(), i.normal);
+
+        let miss = Ray::new(&Point3::new(3., 4., 0.), &Vector3::new(0., -1., 0.));
+        assert_eq!(true, hf.intersect(&miss).is_none());
+    }
+
+    #[test]
+    fn intersect_grazing() {
+        let hf = ramp();
+
+        // Travels along +X at a height of 0.75 and must cross two cells
+        // before hitting the slope.
+        let ray = Ray::new(&Point3::new(-4., 0.75, -1.), &Vector3::new(1., 0., 0.));
+        let i = hf.intersect(&ray).unwrap();
+        assert_near(5., i.t);
+        assert_near(1., i.point.x());
+    }
+}
@@ -0,0 +1,180 @@
+/// An instance of a piece of shared geometry
+use std::cell::*;
+use std::rc::Rc;
+
+use crate::math::{Matrix, Ray};
+
+use super::Intersection;
+use super::Material;
+use super::Renderable;
+use super::Visibility;
+
+/**
+ * An `Instance` places a copy of some shared geometry into the scene.
+ *
+ * The geometry is held behind an `Rc` and is never mutated by the instance,
+ * so any number of instances can point at the same mesh (or any other
+ * `Renderable`) and the cost of each copy is only its own transform, name
+ * and optional material.  Anything the geometry builds internally to speed
+ * up intersection is built once and shared by every instance.
+ */
+pub struct Instance {
+    id: i32,
+    name: String,
+    geometry: Rc<dyn Renderable>,
+    material: Option<Rc<RefCell<dyn Material>>>,
+    transform: Matrix,
+    inv_transform: Matrix,
+    visibility: Visibility,
+}
+
+impl Instance {
+    pub fn new(geometry: Rc<dyn Renderable>) -> Instance {
+        Instance {
+            id: 0,
+            name: String::from("Instance"),
+            geometry: Rc::clone(&geometry),
+            material: None,
+            transform: Matrix::identity(),
+            inv_transform: Matrix::identity(),
+            visibility: Visibility::all(),
+        }
+    }
+
+    pub fn new_with_name(name: &str, geometry: Rc<dyn Renderable>) -> Instance {
+        let mut instance = Instance::new(geometry);
+        instance.name = String::from(name);
+        instance
+    }
+
+    /// Replace the material of the shared geometry with `material` for this
+    /// instance only.
+    pub fn set_material(&mut self, material: Rc<RefCell<dyn Material>>) {
+        self.material = Some(material);
+    }
+
+    pub fn geometry(&self) -> &Rc<dyn Renderable> {
+        &self.geometry
+    }
+}
+
+impl Renderable for Instance {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_id(&mut self, id: i32) {
+        self.id = id;
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        // Move the ray into the space of the shared geometry
+        let transformed_ray = self.inv_transform * ray;
+
+        match self.geometry.intersect(&transformed_ray) {
+            None => None,
+            Some(mut i) => {
+                i.id = self.id;
+                i.point = i.t * ray;
+                i.eye_dir = -(ray.direction().norm());
+                i.normal = (self.inv_transform.transpose() * i.normal).norm();
+                i.tangent = self.transform * i.tangent;
+                if let Some(ref material) = self.material {
+                    i.material = Rc::clone(material);
+                }
+                Some(i)
+            }
+        }
+    }
+
+    fn set_transform(&mut self, mat: &Matrix) {
+        self.transform = *mat;
+        self.inv_transform = self.transform.inverse();
+    }
+
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_material_mut(&mut self) -> Option<RefMut<dyn Material>> {
+        match self.material {
+            Some(ref m) => Some(m.borrow_mut()),
+            None => None,
+        }
+    }
+
+    fn get_material(&self) -> Option<Ref<dyn Material>> {
+        match self.material {
+            Some(ref m) => Some(m.borrow()),
+            None => self.geometry.get_material(),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Instance(Name: {}, Geometry: {})",
+            self.get_name(),
+            self.geometry.to_string()
+        )
+    }
+
+    fn size(&self) -> usize {
+        self.geometry.size()
+    }
+
+    fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    fn set_visibility(&mut self, visibility: Visibility) {
+        self.visibility = visibility;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{Point3, Vector3};
+    use crate::scene::color::colors::{RED, WHITE};
+    use crate::scene::{Phong, Sphere};
+
+    #[test]
+    fn shares_geometry() {
+        let phong = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        let sphere: Rc<dyn Renderable> = Rc::new(Sphere::new(phong));
+
+        let mut a = Instance::new(Rc::clone(&sphere));
+        a.set_transform(&Matrix::translate(-2., 0., 0.));
+        let mut b = Instance::new(Rc::clone(&sphere));
+        b.set_transform(&Matrix::translate(2., 0., 0.));
+
+        assert_eq!(3, Rc::strong_count(&sphere));
+
+        let ray = Ray::new(&Point3::new(-2., 0., 4.), &Vector3::new(0., 0., -1.));
+        assert_eq!(3., a.intersect(&ray).unwrap().t);
+        assert_eq!(true, b.intersect(&ray).is_none());
+
+        let ray = Ray::new(&Point3::new(2., 0., 4.), &Vector3::new(0., 0., -1.));
+        let i = b.intersect(&ray).unwrap();
+        assert_eq!(3., i.t);
+        assert_eq!(Point3::new(2., 0., 1.), i.point);
+        assert_eq!(Vector3::new(0., 0., 1.), i.normal);
+    }
+
+    #[test]
+    fn material_override() {
+        let white = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        let red = Rc::new(RefCell::new(Phong::new(RED, RED, RED, 60., 0., 0.)));
+        let sphere: Rc<dyn Renderable> = Rc::new(Sphere::new(white));
+
+        let mut inst = Instance::new(sphere);
+        inst.set_id(7);
+        let ray = Ray::new(&Point3::new(0., 0., 4.), &Vector3::new(0., 0., -1.));
+        assert_eq!(WHITE, inst.intersect(&ray).unwrap().material.borrow().diffuse((0., 0.)));
+
+        inst.set_material(red);
+        let i = inst.intersect(&ray).unwrap();
+        assert_eq!(7, i.id);
+        assert_eq!(RED, i.material.borrow().diffuse((0., 0.)));
+    }
+}
@@ -1,7 +1,7 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use super::{Material, TextureCoords};
+use super::{frame, Color, Material, TextureCoords};
 use crate::math::{Point3, Vector3};
 
 #[derive(Clone)]
@@ -14,6 +14,39 @@ pub struct Intersection {
     pub normal: Vector3,
     pub entering: bool,
     pub tex_coord: TextureCoords,
+    /// The hit point in the space of the shape before its transform, so
+    /// that solid textures move along with the shape
+    pub local_point: Point3,
+    /// The direction in which the u texture coordinate increases.  It need
+    /// not be of unit length or perpendicular to `normal`, and is zero
+    /// where the surface has no such direction.
+    pub tangent: Vector3,
+    /// The index of refraction of the medium around the shape at the hit.
+    /// Shapes give 1 and the renderer fills in the right value when the
+    /// shape is inside of another one.
+    pub outside_index: f32,
+    /// The wavelength in nanometers carried by the ray in spectral
+    /// rendering, filled in by the renderer like `outside_index`
+    pub wavelength: Option<f32>,
+    /// A color carried by the geometry itself, such as the color of a
+    /// scanned point, which stands in for the diffuse color of the material
+    pub vertex_color: Option<Color>,
+}
+
+impl Intersection {
+    /// The tangent and bitangent of the surface: unit vectors which are
+    /// perpendicular to each other and to `normal`, with the tangent
+    /// following the u texture coordinate as closely as possible.  The
+    /// bitangent is `normal` cross tangent.
+    pub fn tangent_frame(&self) -> (Vector3, Vector3) {
+        let n = &self.normal;
+        let t = self.tangent - self.tangent.dot(n) * *n;
+        if t.len() <= 1e-6 {
+            return frame(n);
+        }
+        let t = t.norm();
+        (t, n.cross(&t))
+    }
 }
 
 impl PartialEq for Intersection {
@@ -0,0 +1,341 @@
+/// Pick lights from a scene with many lights in proportion to how much they
+/// are likely to contribute to a point
+use std::f32::consts::PI;
+
+use crate::math::{BoundingBox, Point3, Random, Vector3};
+
+use super::LightSource;
+
+/// How the lights of a scene are evaluated at each shading point
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightSampling {
+    /// Every light is evaluated, which is slow with many lights but free
+    /// of noise.  Use it for reference renders.
+    Exhaustive,
+    /// `samples` lights are picked from the light tree in proportion to
+    /// their estimated contribution.  The same `seed` gives the same image.
+    Stochastic { samples: usize, seed: u64 },
+}
+
+/**
+A conservative description of where a light is and where it shines, used
+to estimate how much energy it can send to a point without sampling it.
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct LightBounds {
+    pub bounds: BoundingBox,
+    /// The central direction the light shines in
+    pub direction: Vector3,
+    /// Cosine of the half angle of the cone around `direction` which the
+    /// light shines into, -1 for a light which shines in every direction
+    pub cos_spread: f32,
+    /// Total energy of the light, only compared between lights
+    pub power: f32,
+}
+
+impl LightBounds {
+    pub fn point(p: &Point3, power: f32) -> LightBounds {
+        LightBounds {
+            bounds: BoundingBox::new(p, p),
+            direction: Vector3::new(0., 1., 0.),
+            cos_spread: -1.,
+            power,
+        }
+    }
+
+    /// Bounds which contain the lights of both `self` and `other`
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        let (direction, cos_spread) = merge_cones(
+            (&self.direction, self.cos_spread),
+            (&other.direction, other.cos_spread),
+        );
+        LightBounds {
+            bounds: self.bounds.union(&other.bounds),
+            direction,
+            cos_spread,
+            power: self.power + other.power,
+        }
+    }
+
+    /// An estimate of how much energy the lights within these bounds send
+    /// to `p`.  It never returns zero for a point which may be lit.
+    pub fn importance(&self, p: &Point3) -> f32 {
+        let center = self.bounds.center();
+        let radius = 0.5 * self.bounds.extent().len();
+        let to_point = p - &center;
+        let dist2 = to_point.len2().max(radius * radius).max(1e-6);
+
+        if self.cos_spread <= -1. {
+            return self.power / dist2;
+        }
+
+        // Widen the cone of the light by the angle which the bounds cover
+        // as seen from the point, then check if the point is in it.
+        let dist = to_point.len();
+        if dist <= radius {
+            return self.power / dist2;
+        }
+        let theta = self
+            .direction
+            .dot(&((1. / dist) * to_point))
+            .clamp(-1., 1.)
+            .acos();
+        let theta_spread = self.cos_spread.clamp(-1., 1.).acos();
+        let theta_bounds = (radius / dist).clamp(-1., 1.).asin();
+        let theta_out = (theta - theta_spread - theta_bounds).max(0.);
+        if theta_out >= PI / 2. {
+            return 0.;
+        }
+        self.power * theta_out.cos() / dist2
+    }
+}
+
+/// The smallest cone, given as a direction and the cosine of its half
+/// angle, which holds both cones.
+fn merge_cones(a: (&Vector3, f32), b: (&Vector3, f32)) -> (Vector3, f32) {
+    if a.1 <= -1. || b.1 <= -1. {
+        return (*a.0, -1.);
+    }
+
+    let theta_a = a.1.clamp(-1., 1.).acos();
+    let theta_b = b.1.clamp(-1., 1.).acos();
+    let theta_d = a.0.dot(b.0).clamp(-1., 1.).acos();
+    if theta_a >= theta_d + theta_b {
+        return (*a.0, a.1);
+    }
+    if theta_b >= theta_d + theta_a {
+        return (*b.0, b.1);
+    }
+
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    if theta_o >= PI || theta_d <= std::f32::EPSILON {
+        return (*a.0, -1.);
+    }
+
+    // Turn the axis of `a` towards `b` until the cone covers both
+    let theta_r = theta_o - theta_a;
+    let axis = (1. / theta_d.sin()) * ((theta_d - theta_r).sin() * *a.0 + theta_r.sin() * *b.0);
+    (axis.norm(), theta_o.cos())
+}
+
+struct LightNode {
+    bounds: LightBounds,
+    // For a leaf the index of the light in the scene, for an inner node
+    // the index of the second child (the first child always directly
+    // follows its parent).
+    index: usize,
+    leaf: bool,
+}
+
+/**
+A hierarchy over the lights of a scene which have bounds.  Lights are
+grouped by position and each node of the tree holds the combined bounds,
+direction cone and power of the lights below it.
+
+To pick a light the tree is walked from the root, choosing between the two
+children of each node in proportion to their importance to the point being
+lit.
+*/
+pub struct LightTree {
+    nodes: Vec<LightNode>,
+    // Lights without bounds, which must always be evaluated
+    unbounded: Vec<usize>,
+}
+
+impl LightTree {
+    pub fn new(lights: &[Box<dyn LightSource>]) -> LightTree {
+        let mut bounded = vec![];
+        let mut unbounded = vec![];
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(b) => bounded.push((i, b)),
+                None => unbounded.push(i),
+            }
+        }
+
+        let mut tree = LightTree {
+            nodes: vec![],
+            unbounded,
+        };
+        if !bounded.is_empty() {
+            tree.build(&mut bounded);
+        }
+        tree
+    }
+
+    /// Indices of the lights which are not in the tree
+    pub fn unbounded(&self) -> &Vec<usize> {
+        &self.unbounded
+    }
+
+    fn build(&mut self, lights: &mut [(usize, LightBounds)]) -> LightBounds {
+        let index = self.nodes.len();
+        if lights.len() == 1 {
+            let (light, bounds) = lights[0];
+            self.nodes.push(LightNode {
+                bounds,
+                index: light,
+                leaf: true,
+            });
+            return bounds;
+        }
+
+        // Reserve the slot of this node, it is filled in once the children
+        // are built.
+        self.nodes.push(LightNode {
+            bounds: lights[0].1,
+            index: 0,
+            leaf: false,
+        });
+
+        let centers = lights
+            .iter()
+            .fold(BoundingBox::empty(), |b, (_, l)| b.grow(&l.bounds.center()));
+        let axis = centers.longest_axis();
+        let coord = |l: &LightBounds| {
+            let c = l.bounds.center();
+            match axis {
+                0 => c.x(),
+                1 => c.y(),
+                _ => c.z(),
+            }
+        };
+        lights.sort_by(|a, b| {
+            coord(&a.1)
+                .partial_cmp(&coord(&b.1))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mid = lights.len() / 2;
+        let (first, second) = lights.split_at_mut(mid);
+        let left = self.build(first);
+        let second_index = self.nodes.len();
+        let right = self.build(second);
+
+        let bounds = left.union(&right);
+        self.nodes[index] = LightNode {
+            bounds,
+            index: second_index,
+            leaf: false,
+        };
+        bounds
+    }
+
+    /// Pick a light for the point `p`.  Returns the index of the light in
+    /// the scene and the probability that it was picked, or None if no
+    /// light in the tree can reach the point.
+    pub fn sample(&self, p: &Point3, rng: &mut Random) -> Option<(usize, f32)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut node = 0;
+        let mut pdf = 1.;
+        loop {
+            if self.nodes[node].leaf {
+                return Some((self.nodes[node].index, pdf));
+            }
+
+            let (left, right) = (node + 1, self.nodes[node].index);
+            let wl = self.nodes[left].bounds.importance(p);
+            let wr = self.nodes[right].bounds.importance(p);
+            if wl + wr <= 0. {
+                return None;
+            }
+
+            let pl = wl / (wl + wr);
+            if rng.next_f32() < pl {
+                node = left;
+                pdf *= pl;
+            } else {
+                node = right;
+                pdf *= 1. - pl;
+            }
+        }
+    }
+
+    /// The probability that `sample` picks the light with index `light`
+    /// for the point `p`.
+    pub fn pdf(&self, p: &Point3, light: usize) -> f32 {
+        self.pdf_from(0, p, light).unwrap_or(0.)
+    }
+
+    fn pdf_from(&self, node: usize, p: &Point3, light: usize) -> Option<f32> {
+        let n = self.nodes.get(node)?;
+        if n.leaf {
+            return if n.index == light { Some(1.) } else { None };
+        }
+
+        let (left, right) = (node + 1, n.index);
+        let wl = self.nodes[left].bounds.importance(p);
+        let wr = self.nodes[right].bounds.importance(p);
+        if wl + wr <= 0. {
+            return Some(0.);
+        }
+        if let Some(pdf) = self.pdf_from(left, p, light) {
+            return Some(pdf * wl / (wl + wr));
+        }
+        self.pdf_from(right, p, light)
+            .map(|pdf| pdf * wr / (wl + wr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::color::colors::*;
+    use crate::scene::{AmbientLight, PointLight};
+
+    fn lights() -> Vec<Box<dyn LightSource>> {
+        let mut lights: Vec<Box<dyn LightSource>> = vec![];
+        for i in 0..8 {
+            lights.push(Box::new(PointLight::new(
+                Point3::new(i as f32 * 10., 0., 0.),
+                WHITE,
+            )));
+        }
+        lights.push(Box::new(AmbientLight::new(&WHITE)));
+        lights
+    }
+
+    #[test]
+    fn nearby_lights_are_picked_more() {
+        let tree = LightTree::new(&lights());
+        assert_eq!(&vec![8], tree.unbounded());
+
+        let p = Point3::new(0., 1., 0.);
+        let mut rng = Random::new(0);
+        let mut counts = [0; 8];
+        for _ in 0..1000 {
+            let (light, pdf) = tree.sample(&p, &mut rng).unwrap();
+            assert!((pdf - tree.pdf(&p, light)).abs() < 0.0001);
+            counts[light] += 1;
+        }
+        assert!(counts[0] > 500);
+        assert!((1..8).all(|l| tree.pdf(&p, l) < 0.1 * tree.pdf(&p, 0)));
+
+        // The probabilities of picking each light add up to one
+        let total: f32 = (0..8).map(|l| tree.pdf(&p, l)).sum();
+        assert!((total - 1.).abs() < 0.0001);
+    }
+
+    #[test]
+    fn cones() {
+        // A light facing up can not light a point below it
+        let up = LightBounds {
+            bounds: BoundingBox::new(&Point3::new(0., 0., 0.), &Point3::new(0., 0., 0.)),
+            direction: Vector3::new(0., 1., 0.),
+            cos_spread: 0.5,
+            power: 1.,
+        };
+        assert!(up.importance(&Point3::new(0., 2., 0.)) > 0.);
+        assert_eq!(0., up.importance(&Point3::new(0., -2., 0.)));
+
+        let mut right = up;
+        right.direction = Vector3::new(1., 0., 0.);
+        let both = up.union(&right);
+        assert!(both.cos_spread < 0.5);
+        assert!(both.importance(&Point3::new(2., 0.1, 0.)) > 0.);
+        assert_eq!(2., both.power);
+    }
+}
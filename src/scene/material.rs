@@ -1,23 +1,163 @@
-use super::*;
+use std::f32::consts::PI;
+use std::f32::EPSILON;
 
-pub type ColorFun = fn((f32, f32)) -> Color;
+use super::glossy::refract;
+use super::*;
+use crate::math::Random;
 
 pub trait ColorTrait {
     fn color(tx: TextureCoords) -> Color;
 }
 
+/**
+A direction picked by a material for light arriving at a surface.  `weight`
+is the BSDF times the cosine divided by the probability density of picking
+the direction, which is what the light arriving from `wi` is multiplied by.
+
+For perfectly specular directions, such as a mirror reflection, `delta` is
+set and `pdf` is the probability of picking that direction at all.
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct BsdfSample {
+    pub wi: Vector3,
+    pub weight: Color,
+    pub pdf: f32,
+    pub delta: bool,
+}
+
+/**
+How a surface reflects and transmits light.  In the BSDF methods `wo` is
+the direction light leaves towards and `wi` the direction it arrives from,
+both pointing away from the surface at `i`.
+
+Materials describe the smooth part of their reflection with `evaluate` and
+the perfectly specular part with `specular_samples`.  Renderers which trace
+a fixed set of rays follow every specular sample, while sampling based
+renderers use `sample` and `pdf`.  By default these pick one of the
+specular samples or a cosine weighted direction.
+*/
 pub trait Material {
+    /// The BSDF, without the perfectly specular directions
+    fn evaluate(&self, wo: &Vector3, wi: &Vector3, i: &Intersection) -> Color;
+
+    /// The mirror and refraction directions of the surface with their
+    /// weights
+    fn specular_samples(&self, _wo: &Vector3, _i: &Intersection) -> Vec<BsdfSample> {
+        vec![]
+    }
+
+    /// Pick a direction for light arriving at the surface using the random
+    /// numbers `u` and `v` in [0, 1)
+    fn sample(&self, wo: &Vector3, i: &Intersection, u: f32, v: f32) -> Option<BsdfSample> {
+        let specular = self.specular_samples(wo, i);
+        let lobes = specular.len() as f32 + 1.;
+        let lobe = (u * lobes) as usize;
+        if lobe < specular.len() {
+            let s = specular[lobe];
+            return Some(BsdfSample {
+                weight: lobes * s.weight,
+                pdf: s.pdf / lobes,
+                ..s
+            });
+        }
+
+        let u = (u * lobes - specular.len() as f32).min(1.);
+        let (t, b) = frame(&i.normal);
+        let (r, phi) = (u.sqrt(), 2. * PI * v);
+        let wi = r * phi.cos() * t + r * phi.sin() * b + (1. - u).max(0.).sqrt() * i.normal;
+        let pdf = self.pdf(wo, &wi, i);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            weight: (i.normal.dot(&wi) / pdf) * self.evaluate(wo, &wi, i),
+            pdf,
+            delta: false,
+        })
+    }
+
+    /// The probability density, per unit solid angle, that `sample` picks
+    /// `wi`
+    fn pdf(&self, wo: &Vector3, wi: &Vector3, i: &Intersection) -> f32 {
+        let cos = i.normal.dot(wi);
+        if cos <= 0. {
+            return 0.;
+        }
+        let lobes = self.specular_samples(wo, i).len() as f32 + 1.;
+        cos / (PI * lobes)
+    }
+
+    /// The energy of a light arriving from `light_dir` which is reflected
+    /// towards the eye.  The BSDF is scaled by pi, so that a white diffuse
+    /// surface lit head on reflects all of `incoming`.
     fn get_reflected_energy(
         &self,
         incoming: &Color,
         light_dir: &Vector3,
         i: &Intersection,
-    ) -> Color;
+    ) -> Color {
+        let light_dir = light_dir.norm();
+        let cos = i.normal.dot(&light_dir).max(0.);
+        (PI * cos) * (self.evaluate(&i.eye_dir, &light_dir, i) * incoming)
+    }
+
     fn diffuse(&self, tx: TextureCoords) -> Color;
+
+    /// The diffuse color at a hit, which solid textures take from the point
+    /// on the shape rather than the texture coordinates.  This is what tints
+    /// the light refracted through clear glass.
+    fn diffuse_at(&self, i: &Intersection) -> Color {
+        self.diffuse(i.tex_coord)
+    }
+
     fn set_diffuse(&mut self, c: Color);
-    fn ambient(&self, tx: TextureCoords) -> Color;
+    fn ambient(&self, i: &Intersection) -> Color;
     fn reflectivity(&self) -> f32;
     fn refraction_index(&self) -> f32;
+
+    /// The index of refraction for light of `wavelength` nanometers, or for
+    /// white light without one.  Dispersive materials bend each wavelength
+    /// by a different amount.
+    fn refraction_index_at(&self, _wavelength: Option<f32>) -> f32 {
+        self.refraction_index()
+    }
+
+    /// How much of each channel the inside of a refractive material absorbs
+    /// per unit of distance.  Light which travels `d` through it keeps
+    /// `exp(-absorption * d)` of its energy, so thick glass is darker.
+    fn absorption(&self) -> Color {
+        colors::BLACK
+    }
+
+    /// Where refractive shapes overlap, the inside belongs to the one with
+    /// the highest priority.  See `MediumStack`.
+    fn medium_priority(&self) -> i32 {
+        0
+    }
+
+    /// The color which light refracted through the surface at `i` is
+    /// filtered by.  Clear glass is tinted by its diffuse color, while glass
+    /// with an absorption takes its color from that alone.
+    fn transmission_tint(&self, i: &Intersection) -> Color {
+        if self.absorption() == colors::BLACK {
+            self.diffuse_at(i)
+        } else {
+            colors::WHITE
+        }
+    }
+
+    /// The normal to shade `i` with.  Materials with a normal or bump map
+    /// bend it away from the normal of the geometry.
+    fn shading_normal(&self, i: &Intersection) -> Vector3 {
+        i.normal
+    }
+
+    // Energy given off by the surface itself, whether or not it is lit
+    fn emission(&self, _tx: TextureCoords) -> Color {
+        colors::BLACK
+    }
+
     fn to_string(&self) -> String;
 }
 
@@ -29,6 +169,10 @@ pub struct Phong {
     pub power: f32,
     pub reflectivity: f32,
     pub refraction_index: f32,
+    pub absorption: Color,
+    pub priority: i32,
+    pub gloss: Gloss,
+    pub dispersion: Option<Dispersion>,
 }
 
 impl Phong {
@@ -47,8 +191,30 @@ impl Phong {
             power,
             reflectivity,
             refraction_index,
+            absorption: colors::BLACK,
+            priority: 0,
+            gloss: Gloss::default(),
+            dispersion: None,
         }
     }
+
+    /// Energy reflected towards `wo` from a white light in direction `wi`,
+    /// without the light which is refracted into the surface.
+    fn shade(&self, wo: &Vector3, wi: &Vector3, i: &Intersection) -> Color {
+        let (n1, n2) = indices(self.refraction_index_at(i.wavelength), i);
+        let fresnel = crate::render::fresnel_reflection(wi, &i.normal, n1, n2);
+        let surface = i.vertex_color.unwrap_or(self.diffuse);
+        let diffuse = lambert(wi, &i.normal, &colors::WHITE, &surface);
+        let specular = phong(
+            self.power,
+            wo,
+            wi,
+            &i.normal,
+            &colors::WHITE,
+            &self.specular,
+        );
+        fresnel * (diffuse + specular)
+    }
 }
 
 impl Material for Phong {
@@ -56,11 +222,15 @@ impl Material for Phong {
         self.diffuse
     }
 
+    fn diffuse_at(&self, i: &Intersection) -> Color {
+        i.vertex_color.unwrap_or(self.diffuse)
+    }
+
     fn set_diffuse(&mut self, c: Color) {
         self.diffuse = c;
     }
 
-    fn ambient(&self, _: TextureCoords) -> Color {
+    fn ambient(&self, _: &Intersection) -> Color {
         self.ambient
     }
 
@@ -68,10 +238,95 @@ impl Material for Phong {
         self.refraction_index
     }
 
+    fn refraction_index_at(&self, wavelength: Option<f32>) -> f32 {
+        dispersed_index(self.refraction_index, self.dispersion, wavelength)
+    }
+
     fn reflectivity(&self) -> f32 {
         self.reflectivity
     }
 
+    fn absorption(&self) -> Color {
+        self.absorption
+    }
+
+    fn medium_priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn evaluate(&self, wo: &Vector3, wi: &Vector3, i: &Intersection) -> Color {
+        let cos = i.normal.dot(wi);
+        if cos <= 0. {
+            return colors::BLACK;
+        }
+        (1. / (PI * cos)) * self.shade(wo, wi, i)
+    }
+
+    fn specular_samples(&self, wo: &Vector3, i: &Intersection) -> Vec<BsdfSample> {
+        let mut samples = vec![];
+        let (n1, n2) = indices(self.refraction_index_at(i.wavelength), i);
+        let mut rng = Random::from_point(&i.point, 0);
+
+        // Reflect or refract the eye direction about a microfacet normal,
+        // for spreading glossy samples
+        let reflect = |m: &Vector3| {
+            if wo.dot(m) <= 0. {
+                return None;
+            }
+            Some(wo.reflect(m).norm())
+        };
+        let transmit = |m: &Vector3| {
+            if wo.dot(m) <= 0. {
+                return None;
+            }
+            refract(wo, m, n1 / n2).or_else(|| Some(wo.reflect(m).norm()))
+        };
+
+        // Past the critical angle the light which would have been refracted
+        // is all reflected instead, and that mirror reflection is all of the
+        // energy which leaves the surface
+        let refracted = if self.refraction_index > EPSILON {
+            let ray = Ray::new(&i.point, &-*wo);
+            match crate::render::refract_ray(&ray, i, n1, n2) {
+                Some(r) => Some(r.direction()),
+                None => {
+                    let ideal = BsdfSample {
+                        wi: wo.reflect(&i.normal).norm(),
+                        weight: colors::WHITE,
+                        pdf: 1.,
+                        delta: true,
+                    };
+                    return self.gloss.spread(&ideal, &i.normal, transmit, &mut rng);
+                }
+            }
+        } else {
+            None
+        };
+
+        if self.reflectivity > EPSILON {
+            let wi = wo.reflect(&i.normal).norm();
+            let ideal = BsdfSample {
+                wi,
+                weight: self.shade(wo, &wi, i),
+                pdf: 1.,
+                delta: true,
+            };
+            samples.extend(self.gloss.spread(&ideal, &i.normal, reflect, &mut rng));
+        }
+
+        if let Some(wi) = refracted {
+            let fresnel = crate::render::fresnel_refraction(wo, &i.normal, n1, n2);
+            let ideal = BsdfSample {
+                wi,
+                weight: fresnel * self.transmission_tint(i),
+                pdf: 1.,
+                delta: true,
+            };
+            samples.extend(self.gloss.spread(&ideal, &i.normal, transmit, &mut rng));
+        }
+        samples
+    }
+
     /// Use Phong reflection model to compute the intensity of light reflected
     /// in the direction of the eye
     fn get_reflected_energy(
@@ -80,16 +335,10 @@ impl Material for Phong {
         light_dir: &Vector3,
         i: &Intersection,
     ) -> Color {
-        let diffuse = lambert(&light_dir, &i.normal, &incoming, &self.diffuse);
-        let specular = phong(
-            self.power,
-            &i.eye_dir,
-            &light_dir,
-            &i.normal,
-            &incoming,
-            &self.specular,
-        );
-        diffuse + specular
+        if i.normal.dot(light_dir) <= 0. {
+            return colors::BLACK;
+        }
+        self.shade(&i.eye_dir, light_dir, i) * incoming
     }
 
     fn to_string(&self) -> String {
@@ -100,21 +349,28 @@ impl Material for Phong {
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+/// A `Phong` material whose ambient, diffuse and specular colors come from
+/// textures.
+#[derive(Clone)]
 pub struct TexturePhong {
-    pub specular: ColorFun,
-    pub ambient: ColorFun,
-    pub diffuse: ColorFun,
+    pub specular: Rc<dyn Texture>,
+    pub ambient: Rc<dyn Texture>,
+    pub diffuse: Rc<dyn Texture>,
     pub power: f32,
     pub reflectivity: f32,
     pub refraction_index: f32,
+    pub absorption: Color,
+    pub priority: i32,
+    pub gloss: Gloss,
+    pub dispersion: Option<Dispersion>,
+    pub normal_map: Option<NormalMap>,
 }
 
 impl TexturePhong {
     pub fn new(
-        ambient: ColorFun,
-        diffuse: ColorFun,
-        specular: ColorFun,
+        ambient: Rc<dyn Texture>,
+        diffuse: Rc<dyn Texture>,
+        specular: Rc<dyn Texture>,
         power: f32,
         reflectivity: f32,
         refraction_index: f32,
@@ -126,63 +382,110 @@ impl TexturePhong {
             power,
             reflectivity,
             refraction_index,
+            absorption: colors::BLACK,
+            priority: 0,
+            gloss: Gloss::default(),
+            dispersion: None,
+            normal_map: None,
         }
     }
 
     pub fn at(&self, tx: TextureCoords) -> Phong {
-        Phong::new(
-            (self.ambient)(tx),
-            (self.diffuse)(tx),
-            (self.specular)(tx),
+        self.phong(
+            self.ambient.color(tx),
+            self.diffuse.color(tx),
+            self.specular.color(tx),
+        )
+    }
+
+    /// The material at a hit, where solid textures use the point on the
+    /// shape rather than the texture coordinates
+    fn at_hit(&self, i: &Intersection) -> Phong {
+        let (tx, p) = (i.tex_coord, &i.local_point);
+        self.phong(
+            self.ambient.color_at(tx, p),
+            self.diffuse_at(i),
+            self.specular.color_at(tx, p),
+        )
+    }
+
+    fn phong(&self, ambient: Color, diffuse: Color, specular: Color) -> Phong {
+        let mut phong = Phong::new(
+            ambient,
+            diffuse,
+            specular,
             self.power,
             self.reflectivity,
             self.refraction_index,
-        )
+        );
+        phong.absorption = self.absorption;
+        phong.priority = self.priority;
+        phong.gloss = self.gloss;
+        phong.dispersion = self.dispersion;
+        phong
     }
 }
 
 impl Material for TexturePhong {
     fn diffuse(&self, tx: TextureCoords) -> Color {
-        (self.diffuse)(tx)
+        self.diffuse.color(tx)
+    }
+
+    fn diffuse_at(&self, i: &Intersection) -> Color {
+        // A color carried by the geometry replaces the texture, as in Phong
+        i.vertex_color
+            .unwrap_or_else(|| self.diffuse.color_at(i.tex_coord, &i.local_point))
     }
 
     fn set_diffuse(&mut self, _: Color) {}
 
-    fn ambient(&self, tx: TextureCoords) -> Color {
-        (self.ambient)(tx)
+    fn ambient(&self, i: &Intersection) -> Color {
+        self.ambient.color_at(i.tex_coord, &i.local_point)
+    }
+
+    fn shading_normal(&self, i: &Intersection) -> Vector3 {
+        match self.normal_map {
+            None => i.normal,
+            Some(ref map) => map.apply(i),
+        }
     }
 
     fn refraction_index(&self) -> f32 {
         self.refraction_index
     }
 
+    fn refraction_index_at(&self, wavelength: Option<f32>) -> f32 {
+        dispersed_index(self.refraction_index, self.dispersion, wavelength)
+    }
+
+    fn absorption(&self) -> Color {
+        self.absorption
+    }
+
+    fn medium_priority(&self) -> i32 {
+        self.priority
+    }
+
     fn reflectivity(&self) -> f32 {
         self.reflectivity
     }
 
-    /// Use Phong reflection model to compute the intensity of light reflected
-    /// in the direction of the eye
+    fn evaluate(&self, wo: &Vector3, wi: &Vector3, i: &Intersection) -> Color {
+        self.at_hit(i).evaluate(wo, wi, i)
+    }
+
+    fn specular_samples(&self, wo: &Vector3, i: &Intersection) -> Vec<BsdfSample> {
+        self.at_hit(i).specular_samples(wo, i)
+    }
+
     fn get_reflected_energy(
         &self,
         incoming: &Color,
         light_dir: &Vector3,
         i: &Intersection,
     ) -> Color {
-        let diffuse = lambert(
-            &light_dir,
-            &i.normal,
-            &incoming,
-            &(self.diffuse)(i.tex_coord),
-        );
-        let specular = phong(
-            self.power,
-            &i.eye_dir,
-            &light_dir,
-            &i.normal,
-            &incoming,
-            &(self.specular)(i.tex_coord),
-        );
-        diffuse + specular
+        self.at_hit(i)
+            .get_reflected_energy(incoming, light_dir, i)
     }
 
     fn to_string(&self) -> String {
@@ -190,6 +493,81 @@ impl Material for TexturePhong {
     }
 }
 
+/// A surface which glows with the color `color` scaled by `intensity` and
+/// reflects no light.  Register a shape with this material using
+/// `Scene::add_emissive_shape` to have it light the rest of the scene.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Emissive {
+    pub color: Color,
+    pub intensity: f32,
+}
+
+impl Emissive {
+    pub fn new(color: Color, intensity: f32) -> Emissive {
+        Emissive { color, intensity }
+    }
+}
+
+impl Material for Emissive {
+    fn diffuse(&self, _: TextureCoords) -> Color {
+        colors::BLACK
+    }
+
+    fn set_diffuse(&mut self, c: Color) {
+        self.color = c;
+    }
+
+    fn ambient(&self, _: &Intersection) -> Color {
+        colors::BLACK
+    }
+
+    fn refraction_index(&self) -> f32 {
+        0.
+    }
+
+    fn reflectivity(&self) -> f32 {
+        0.
+    }
+
+    fn evaluate(&self, _: &Vector3, _: &Vector3, _: &Intersection) -> Color {
+        colors::BLACK
+    }
+
+    fn emission(&self, _: TextureCoords) -> Color {
+        self.intensity * self.color
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Emissive(Color: {}, Intensity: {})",
+            self.color, self.intensity
+        )
+    }
+}
+
+/// Indices of refraction on the side of the surface at `i` which light
+/// arrives from and on the other side.
+fn indices(refraction_index: f32, i: &Intersection) -> (f32, f32) {
+    if i.entering {
+        (i.outside_index, refraction_index)
+    } else {
+        (refraction_index, i.outside_index)
+    }
+}
+
+/// The index of a material at `wavelength`, which only changes from
+/// `refraction_index` for a refractive material with a dispersion model
+fn dispersed_index(
+    refraction_index: f32,
+    dispersion: Option<Dispersion>,
+    wavelength: Option<f32>,
+) -> f32 {
+    match (dispersion, wavelength) {
+        (Some(d), Some(w)) if refraction_index > EPSILON => d.index(w),
+        _ => refraction_index,
+    }
+}
+
 fn lambert(light_dir: &Vector3, normal: &Vector3, light: &Color, surface: &Color) -> Color {
     light_dir.dot(normal) * light * surface
 }
@@ -211,3 +589,189 @@ fn phong(
         m_dot_h.powf(power) * light * surface
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::color::colors::*;
+    use crate::scene::{Checkerboard, GlossyLobe, Wood};
+
+    fn hit(material: Phong) -> Intersection {
+        let n = Vector3::new(0., 1., 0.);
+        Intersection {
+            id: 0,
+            t: 1.,
+            material: Rc::new(RefCell::new(material)),
+            point: Point3::new(0., 0., 0.),
+            eye_dir: Vector3::new(0.6, 0.8, 0.),
+            normal: n,
+            entering: true,
+            tex_coord: (0., 0.),
+            local_point: Point3::new(0., 0., 0.),
+            tangent: Vector3::new(1., 0., 0.),
+            outside_index: 1.,
+            wavelength: None,
+            vertex_color: None,
+        }
+    }
+
+    #[test]
+    fn diffuse_at_hit() {
+        let mut i = hit(Phong::new(BLACK, WHITE, WHITE, 60., 0., 0.));
+        assert_eq!(WHITE, i.material.borrow().diffuse_at(&i));
+        i.vertex_color = Some(RED);
+        assert_eq!(RED, i.material.borrow().diffuse_at(&i));
+
+        // Solid textures are looked up at the point on the shape
+        i.vertex_color = None;
+        let wood = Rc::new(Wood::new(1, WHITE, BLACK, 4.));
+        let textured = TexturePhong::new(wood.clone(), wood.clone(), wood.clone(), 60., 0., 1.5);
+        i.local_point = Point3::new(0.3, 0.1, 0.2);
+        assert_eq!(
+            wood.color_at(i.tex_coord, &i.local_point),
+            textured.diffuse_at(&i)
+        );
+        assert_ne!(textured.diffuse(i.tex_coord), textured.diffuse_at(&i));
+        assert_eq!(textured.diffuse_at(&i), textured.transmission_tint(&i));
+    }
+
+    #[test]
+    fn texture_vertex_color() {
+        let checks = Rc::new(Checkerboard::new(WHITE, BLACK));
+        let textured =
+            TexturePhong::new(checks.clone(), checks.clone(), checks.clone(), 60., 0., 1.5);
+        let mut i = hit(Phong::new(BLACK, WHITE, WHITE, 60., 0., 0.));
+        i.material = Rc::new(RefCell::new(textured));
+        i.vertex_color = Some(RED);
+        assert_eq!(RED, i.material.borrow().diffuse_at(&i));
+
+        // The color of the geometry shades like a plain Phong of that color
+        let plain = Phong::new(BLACK, RED, checks.color(i.tex_coord), 60., 0., 1.5);
+        let light = Vector3::new(0., 1., 0.);
+        let energy = i.material.borrow().get_reflected_energy(&WHITE, &light, &i);
+        let expected = plain.get_reflected_energy(&WHITE, &light, &i);
+        assert_eq!(expected, energy);
+    }
+
+    #[test]
+    fn phong_bsdf() {
+        let glass = Phong::new(BLACK, 0.5 * WHITE, 0.5 * WHITE, 60., 1., 1.5);
+        let i = hit(glass);
+        let wo = i.eye_dir;
+
+        // The BSDF gives the same energy as shading with a light
+        let wi = Vector3::new(0., 0.6, 0.8);
+        let energy = glass.get_reflected_energy(&WHITE, &wi, &i);
+        let bsdf = (PI * 0.6) * glass.evaluate(&wo, &wi, &i);
+        assert!((energy.g - bsdf.g).abs() < 0.0001);
+
+        // A mirror reflection and a refraction into the glass
+        let specular = glass.specular_samples(&wo, &i);
+        assert_eq!(2, specular.len());
+        assert!((specular[0].wi - Vector3::new(-0.6, 0.8, 0.)).len() < 0.0001);
+        assert!(specular[1].wi.dot(&i.normal) < 0.);
+
+        // Sampling picks every lobe and agrees with `pdf`
+        let mut delta = 0;
+        for k in 0..30 {
+            let s = glass.sample(&wo, &i, k as f32 / 30., 0.3).unwrap();
+            if s.delta {
+                delta += 1;
+            } else {
+                assert!((s.pdf - glass.pdf(&wo, &s.wi, &i)).abs() < 0.0001);
+            }
+        }
+        assert_eq!(20, delta);
+    }
+
+    #[test]
+    fn total_internal_reflection() {
+        let glass = Phong::new(BLACK, 0.5 * WHITE, BLACK, 60., 0., 1.5);
+        let mut i = hit(glass);
+        i.entering = false;
+
+        // Seen from inside of the glass at a shallow angle, all of the light
+        // which would be refracted is reflected instead
+        let wo = Vector3::new(0.8, 0.6, 0.);
+        let specular = glass.specular_samples(&wo, &i);
+        assert_eq!(1, specular.len());
+        assert!((specular[0].wi - Vector3::new(-0.8, 0.6, 0.)).len() < 0.0001);
+        assert_eq!(WHITE, specular[0].weight);
+
+        // A reflective glass doesn't add its mirror lobe on top of that, so
+        // no more energy leaves the surface than arrives
+        let mirror = Phong::new(BLACK, 0.5 * WHITE, WHITE, 60., 0.8, 1.5);
+        let mut i = hit(mirror);
+        i.entering = false;
+        let specular = mirror.specular_samples(&wo, &i);
+        assert_eq!(1, specular.len());
+        let total: Color = specular.iter().map(|s| s.weight).sum();
+        assert!(total.r <= 1. && total.g <= 1. && total.b <= 1.);
+
+        // Steeper, most of it gets out, tinted by the diffuse color
+        let wo = Vector3::new(0.3, 0.9539, 0.);
+        let specular = glass.specular_samples(&wo, &i);
+        assert_eq!(1, specular.len());
+        assert!(specular[0].wi.dot(&i.normal) < 0.);
+        let weight = specular[0].weight;
+        assert!(weight.g > 0.45 && weight.g < 0.5);
+
+        // Glass with an absorption is colored by that instead
+        let mut tinted = glass;
+        tinted.absorption = Color::new(1., 0., 0.);
+        let weight = tinted.specular_samples(&wo, &i)[0].weight;
+        assert!(weight.g > 0.9 && weight.r == weight.g && weight.b == weight.g);
+    }
+
+    #[test]
+    fn dispersion() {
+        let mut prism = Phong::new(BLACK, WHITE, BLACK, 60., 0., 1.5);
+        prism.dispersion = Some(Dispersion::SF11);
+        let refracted = |wavelength: Option<f32>| -> Vector3 {
+            let mut i = hit(prism);
+            i.wavelength = wavelength;
+            prism.specular_samples(&i.eye_dir, &i)[0].wi
+        };
+
+        // Without a wavelength the index is the one for white light
+        assert_eq!(1.5, prism.refraction_index_at(None));
+        let white = refracted(None);
+        assert!((white.x() + 0.6 / 1.5).abs() < 0.0001);
+
+        // Blue is bent closer to the normal than red
+        let blue = refracted(Some(420.));
+        let red = refracted(Some(680.));
+        assert!(blue.y() < 0. && red.y() < 0.);
+        assert!(blue.x().abs() < red.x().abs());
+        assert!(prism.refraction_index_at(Some(420.)) > prism.refraction_index_at(Some(680.)));
+
+        // Opaque materials ignore their dispersion
+        let mut wall = Phong::new(BLACK, WHITE, BLACK, 60., 0., 0.);
+        wall.dispersion = Some(Dispersion::SF11);
+        assert_eq!(0., wall.refraction_index_at(Some(420.)));
+    }
+
+    #[test]
+    fn glossy_samples() {
+        let mut frosted = Phong::new(BLACK, WHITE, BLACK, 60., 1., 1.5);
+        let i = hit(frosted);
+        let wo = i.eye_dir;
+        let clear = frosted.specular_samples(&wo, &i);
+
+        frosted.gloss = Gloss::new(0.4, 8, GlossyLobe::Ggx);
+        let samples = frosted.specular_samples(&wo, &i);
+        assert!(samples.len() > 2 && samples.len() <= 16);
+
+        // Rays go to both sides of the surface, sharing the energy of the
+        // clear reflection and refraction
+        assert!(samples.iter().any(|s| s.wi.dot(&i.normal) > 0.));
+        assert!(samples.iter().any(|s| s.wi.dot(&i.normal) < 0.));
+        let total: f32 = samples.iter().map(|s| s.weight.g).sum();
+        let ideal: f32 = clear.iter().map(|s| s.weight.g).sum();
+        assert!(total <= ideal + 0.0001 && total > 0.5 * ideal);
+
+        // The same hit gives the same rays
+        let again = frosted.specular_samples(&wo, &i);
+        assert!(samples.iter().zip(again.iter()).all(|(a, b)| a.wi == b.wi));
+    }
+}
@@ -0,0 +1,218 @@
+/// The volumes a ray is travelling through, for refraction between nested
+/// transparent shapes and for absorption inside of them
+use super::{colors, Color, Intersection, RayKind};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Medium {
+    // The id of the shape the medium fills
+    id: i32,
+    index: f32,
+    absorption: Color,
+    priority: i32,
+}
+
+/**
+The refractive shapes which a ray is inside of.  Where shapes overlap the
+medium with the highest `Material::medium_priority` fills the overlap, and
+the surfaces of the other shapes within it are ignored.  This lets a liquid
+slightly overlap the walls of its glass, and the surface between them
+refracts with the indices of the glass and of the liquid rather than those
+of either against air.
+
+Outside of every shape the ray is in a vacuum with an index of 1 which
+absorbs nothing.
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediumStack {
+    media: Vec<Medium>,
+}
+
+impl MediumStack {
+    pub fn new() -> MediumStack {
+        MediumStack { media: vec![] }
+    }
+
+    // The medium with the highest priority, the most recently entered one
+    // if several share it
+    fn current(&self) -> Option<&Medium> {
+        self.media
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, m)| (m.priority, *i))
+            .map(|(_, m)| m)
+    }
+
+    pub fn index(&self) -> f32 {
+        self.current().map_or(1., |m| m.index)
+    }
+
+    pub fn absorption(&self) -> Color {
+        self.current().map_or(colors::BLACK, |m| m.absorption)
+    }
+
+    /// The fraction of energy left after travelling `distance` through the
+    /// current medium, following the Beer-Lambert law
+    pub fn transmittance(&self, distance: f32) -> Color {
+        // Channels which absorb nothing let everything through, even over
+        // the infinite distance to a directional light
+        let t = |a: f32| if a <= 0. { 1. } else { (-a * distance).exp() };
+        let a = self.absorption();
+        Color::new(t(a.r), t(a.g), t(a.b))
+    }
+
+    /// The stack after a ray crosses the surface at `i`
+    pub fn cross(&self, i: &Intersection) -> MediumStack {
+        let mut media = self.clone();
+        let material = i.material.borrow();
+        if material.refraction_index() <= std::f32::EPSILON {
+            return media;
+        }
+
+        if i.entering {
+            media.media.push(Medium {
+                id: i.id,
+                index: material.refraction_index_at(i.wavelength),
+                absorption: material.absorption(),
+                priority: material.medium_priority(),
+            });
+        } else if let Some(pos) = media.media.iter().rposition(|m| m.id == i.id) {
+            media.media.remove(pos);
+        }
+        media
+    }
+
+    /// The stack for a secondary ray of `kind` leaving the surface at `i`:
+    /// only refracted rays cross it
+    pub fn after(&self, i: &Intersection, kind: RayKind) -> MediumStack {
+        if kind == RayKind::Refraction {
+            self.cross(i)
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Whether the surface at `i` separates two different media.  It does
+    /// not when it belongs to a shape inside of a medium with a higher
+    /// priority, and the ray should carry on through it.
+    pub fn is_boundary(&self, i: &Intersection) -> bool {
+        if i.material.borrow().refraction_index() <= std::f32::EPSILON {
+            return true;
+        }
+        self.current() != self.cross(i).current()
+    }
+
+    /// The index of the medium on the side of the surface at `i` away from
+    /// the inside of the shape: the one the ray comes from when it enters
+    /// the shape and the one it goes into when it leaves
+    pub fn outside_index(&self, i: &Intersection) -> f32 {
+        if i.entering {
+            self.index()
+        } else {
+            self.cross(i).index()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{Point3, Vector3};
+    use crate::scene::{Material, Phong};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn hit(id: i32, material: Phong, entering: bool) -> Intersection {
+        let material: Rc<RefCell<dyn Material>> = Rc::new(RefCell::new(material));
+        Intersection {
+            id,
+            t: 1.,
+            material,
+            point: Point3::new(0., 0., 0.),
+            eye_dir: Vector3::new(0., 1., 0.),
+            normal: Vector3::new(0., 1., 0.),
+            entering,
+            tex_coord: (0., 0.),
+            local_point: Point3::new(0., 0., 0.),
+            tangent: Vector3::new(1., 0., 0.),
+            outside_index: 1.,
+            wavelength: None,
+            vertex_color: None,
+        }
+    }
+
+    fn dielectric(index: f32, priority: i32) -> Phong {
+        let mut m = Phong::new(colors::BLACK, colors::WHITE, colors::BLACK, 1., 0., index);
+        m.priority = priority;
+        m
+    }
+
+    #[test]
+    fn nested_media() {
+        let glass = dielectric(1.5, 0);
+        let water = dielectric(1.33, 1);
+        let air = MediumStack::new();
+
+        // Into the glass from the air
+        let into_glass = hit(1, glass, true);
+        assert!(air.is_boundary(&into_glass));
+        assert_eq!(1., air.outside_index(&into_glass));
+        let in_glass = air.cross(&into_glass);
+        assert_eq!(1.5, in_glass.index());
+
+        // The water overlapping the glass wall takes over
+        let into_water = hit(2, water, true);
+        assert!(in_glass.is_boundary(&into_water));
+        assert_eq!(1.5, in_glass.outside_index(&into_water));
+        let in_water = in_glass.cross(&into_water);
+        assert_eq!(1.33, in_water.index());
+
+        // The inside of the glass wall is hidden by the water
+        let out_of_glass = hit(1, glass, false);
+        assert!(!in_water.is_boundary(&out_of_glass));
+        let in_water = in_water.cross(&out_of_glass);
+
+        // Leaving the water goes straight into air
+        let out_of_water = hit(2, water, false);
+        assert!(in_water.is_boundary(&out_of_water));
+        assert_eq!(1., in_water.outside_index(&out_of_water));
+        assert_eq!(air, in_water.cross(&out_of_water));
+
+        // Opaque surfaces do not change the medium
+        let wall = hit(3, Phong::new(colors::WHITE, colors::WHITE, colors::WHITE, 1., 0., 0.), true);
+        assert!(in_glass.is_boundary(&wall));
+        assert_eq!(in_glass, in_glass.cross(&wall));
+    }
+
+    #[test]
+    fn equal_priority_media() {
+        let glass = dielectric(1.5, 0);
+        let water = dielectric(1.33, 0);
+
+        // Of two media with the same priority the newest one fills the
+        // overlap, whichever order they are entered in
+        let in_glass = MediumStack::new().cross(&hit(1, glass, true));
+        let in_both = in_glass.cross(&hit(2, water, true));
+        assert_eq!(1.33, in_both.index());
+        assert!(!in_both.is_boundary(&hit(1, glass, false)));
+
+        let in_water = MediumStack::new().cross(&hit(2, water, true));
+        let in_both = in_water.cross(&hit(1, glass, true));
+        assert_eq!(1.5, in_both.index());
+
+        // Leaving the newer one goes back to the older one
+        assert_eq!(1.33, in_both.cross(&hit(1, glass, false)).index());
+    }
+
+    #[test]
+    fn beer_lambert() {
+        let mut tea = dielectric(1.33, 0);
+        tea.absorption = Color::new(0.1, 0.5, 2.);
+        let inside = MediumStack::new().cross(&hit(1, tea, true));
+
+        let t = inside.transmittance(2.);
+        assert!((t.r - (-0.2f32).exp()).abs() < 0.0001);
+        assert!(t.g > t.b);
+        assert_eq!(colors::WHITE, inside.transmittance(0.));
+        assert_eq!(colors::WHITE, MediumStack::new().transmittance(100.));
+    }
+}
@@ -1,29 +1,85 @@
-use crate::math::{Matrix, Point3, Ray, Vector3};
+use crate::math::{BoundingBox, Matrix, Point3, Ray, Vector3};
 
+mod area_light;
+mod attenuation;
+mod box3;
 mod color;
 mod cube;
+mod environment;
+mod glossy;
+mod hdr;
+mod heightfield;
+mod instance;
 mod intersection;
+mod light_tree;
 mod material;
+mod medium;
+mod normal_map;
+mod pbr;
 mod plane;
+mod ply;
+mod point_cloud;
+mod sdf;
+mod shape_light;
+mod sky;
+mod solid_texture;
+mod spectrum;
 mod sphere;
+mod spot_light;
+mod texture;
 mod triangle;
+mod visibility;
 
 use std::cell::*;
+use std::rc::Rc;
 
+pub use area_light::{DiskLight, RectLight, SphereLight};
+pub use attenuation::Attenuation;
+pub use box3::Box3;
 pub use color::colors;
 pub use color::Color;
 pub use cube::Cube;
+pub use environment::{EnvironmentLight, EnvironmentMap};
+pub use glossy::{Gloss, GlossyLobe};
+pub use hdr::HdrImage;
+pub use heightfield::Heightfield;
+pub use instance::Instance;
 pub use intersection::Intersection;
-pub use material::{ColorFun, Material, Phong, TexturePhong};
+pub use light_tree::{LightBounds, LightSampling, LightTree};
+pub use material::{BsdfSample, Emissive, Material, Phong, TexturePhong};
+pub use medium::MediumStack;
+pub use normal_map::NormalMap;
+pub use pbr::PbrMaterial;
 pub use plane::Plane;
+pub use ply::{load_point_cloud, CloudPoint};
+pub use point_cloud::{PointCloud, PointShape};
+pub use sdf::{Sdf, SdfShape};
+pub use shape_light::ShapeLight;
+pub use sky::PreethamSky;
+pub use solid_texture::{Marble, NoiseTexture, Wood};
+pub use spectrum::{
+    sample_wavelengths, upsample, ColorMatching, Dispersion, MAX_WAVELENGTH, MIN_WAVELENGTH,
+};
 pub use sphere::Sphere;
+pub use spot_light::{Gobo, SpotLight};
+pub use texture::{
+    Checkerboard, Gradient, Grid, ImageTexture, Mix, Multiply, Stripes, Texture, UvTransform,
+    WrapMode,
+};
 pub use triangle::Triangle;
+pub use visibility::{LightLink, RayKind, Visibility};
 
 pub struct Scene {
     id: i32,
     ambient: Color,
     lights: Vec<Box<dyn LightSource>>,
+    // Which shapes each light illuminates, in the same order as `lights`
+    light_links: Vec<LightLink>,
+    light_sampling: LightSampling,
+    // Built from `lights` the first time it is needed
+    light_tree: RefCell<Option<Rc<LightTree>>>,
     shapes: Vec<Box<dyn Renderable>>,
+    background: Option<Rc<dyn Background>>,
 }
 
 impl Scene {
@@ -32,7 +88,11 @@ impl Scene {
             id: 0,
             ambient: colors::BLACK,
             lights: vec![],
+            light_links: vec![],
+            light_sampling: LightSampling::Exhaustive,
+            light_tree: RefCell::new(None),
             shapes: vec![],
+            background: None,
         }
     }
 
@@ -44,7 +104,50 @@ impl Scene {
     }
 
     pub fn add_light(&mut self, light: Box<dyn LightSource>) {
+        self.add_linked_light(light, LightLink::All);
+    }
+
+    /// Adds a light which only illuminates the shapes allowed by `link`
+    pub fn add_linked_light(&mut self, light: Box<dyn LightSource>, link: LightLink) {
         self.lights.push(light);
+        self.light_links.push(link);
+        self.light_tree.replace(None);
+    }
+
+    /// Change which shapes the light at `index` illuminates
+    pub fn set_light_link(&mut self, index: usize, link: LightLink) {
+        self.light_links[index] = link;
+    }
+
+    pub fn light_links(&self) -> &Vec<LightLink> {
+        &self.light_links
+    }
+
+    /// Choose whether every light is evaluated at each point or only a
+    /// few lights picked from the light tree.
+    pub fn set_light_sampling(&mut self, sampling: LightSampling) {
+        self.light_sampling = sampling;
+    }
+
+    pub fn light_sampling(&self) -> LightSampling {
+        self.light_sampling
+    }
+
+    /// The hierarchy over the lights of the scene, built the first time it
+    /// is asked for.
+    pub fn light_tree(&self) -> Rc<LightTree> {
+        let mut tree = self.light_tree.borrow_mut();
+        tree.get_or_insert_with(|| Rc::new(LightTree::new(&self.lights)))
+            .clone()
+    }
+
+    /// Adds a shape with a glowing material to the scene and registers it
+    /// as a light, so that the rest of the scene is lit by it.
+    pub fn add_emissive_shape(&mut self, shape: Box<dyn Renderable>) {
+        let id = self.shapes.len() as i32;
+        let light = ShapeLight::for_shape(id, shape.as_ref());
+        self.add_shape(shape);
+        self.add_light(Box::new(light));
     }
 
     pub fn set_ambient(&mut self, ambient: &Color) {
@@ -55,6 +158,24 @@ impl Scene {
         &self.ambient
     }
 
+    /// Set what is seen by rays which do not hit any shape
+    pub fn set_background(&mut self, background: Rc<dyn Background>) {
+        self.background = Some(background);
+    }
+
+    /// The energy arriving along a ray, traveling in `dir`, which does not
+    /// hit any shape.
+    pub fn background(&self, dir: &Vector3) -> Color {
+        match self.background {
+            None => colors::BLACK,
+            Some(ref bg) => bg.radiance(dir),
+        }
+    }
+
+    pub fn get_background(&self) -> Option<&dyn Background> {
+        self.background.as_ref().map(|bg| bg.as_ref())
+    }
+
     pub fn lights(&self) -> &Vec<Box<dyn LightSource>> {
         &self.lights
     }
@@ -73,6 +194,32 @@ impl Scene {
         None
     }
 
+    /// Find the nearest intersection with the shapes which are visible to
+    /// rays of the given kind.
+    pub fn intersect_for(&self, ray: &Ray, kind: RayKind) -> Option<Intersection> {
+        self.nearest(ray, |shape| shape.visibility().sees(kind))
+    }
+
+    // The nearest intersection with the shapes which pass `filter`
+    fn nearest(
+        &self,
+        ray: &Ray,
+        filter: impl Fn(&dyn Renderable) -> bool,
+    ) -> Option<Intersection> {
+        let mut nearest_intersection: Option<Intersection> = None;
+        for shape in self.shapes.iter().filter(|s| filter(s.as_ref())) {
+            if let Some(intersection) = shape.intersect(ray) {
+                if nearest_intersection
+                    .as_ref()
+                    .is_none_or(|n| intersection.t < n.t)
+                {
+                    nearest_intersection = Some(intersection);
+                }
+            }
+        }
+        nearest_intersection.map(with_shading_normal)
+    }
+
     pub fn find_shape(&self, name: &str) -> Option<&dyn Renderable> {
         for i in 0..self.shapes.len() {
             if self.shapes[i].get_name() == name {
@@ -96,23 +243,7 @@ impl Renderable for Scene {
     fn set_transform(&mut self, _: &Matrix) {}
 
     fn intersect(&self, ray: &Ray) -> Option<Intersection> {
-        let mut nearest = 0.;
-        let mut nearest_intersection = None;
-        for shape in self.shapes.iter() {
-            match shape.intersect(ray) {
-                None => (),
-                Some(intersection) => {
-                    if nearest_intersection.is_none() {
-                        nearest = intersection.t;
-                        nearest_intersection = Some(intersection);
-                    } else if intersection.t < nearest {
-                        nearest = intersection.t;
-                        nearest_intersection = Some(intersection);
-                    }
-                }
-            }
-        }
-        nearest_intersection
+        self.nearest(ray, |_| true)
     }
 
     fn get_name(&self) -> String {
@@ -162,57 +293,295 @@ pub trait Renderable {
     fn get_name(&self) -> String;
     fn to_string(&self) -> String;
     fn size(&self) -> usize;
+
+    // Pick a point on the surface of the object from the coordinates
+    // `(s, t)` in [0, 1).  Objects which cannot be sampled, such as
+    // infinite planes, return None and cannot be used as lights.
+    fn sample_surface(&self, _s: f32, _t: f32) -> Option<SurfaceSample> {
+        None
+    }
+
+    // A box around the object in world space, for objects of a finite size
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        None
+    }
+
+    // Which kinds of rays can hit the object.  Objects are visible to all
+    // of them unless they keep their own `Visibility`, and objects which
+    // don't ignore `set_visibility`.
+    fn visibility(&self) -> Visibility {
+        Visibility::all()
+    }
+
+    fn set_visibility(&mut self, _visibility: Visibility) {}
+}
+
+/// A point picked on the surface of a `Renderable`
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceSample {
+    pub point: Point3,
+    pub normal: Vector3,
+    pub tex_coord: TextureCoords,
+    /// Probability density of picking this point, per unit of area
+    pub pdf: f32,
+}
+
+// Shade the hit with the normal given by its material, so that lights,
+// shadows and secondary rays all see the same bumps
+fn with_shading_normal(mut i: Intersection) -> Intersection {
+    let n = i.material.borrow().shading_normal(&i);
+    i.normal = n;
+    i
+}
+
+/// Two axes perpendicular to `n` and to each other
+fn frame(n: &Vector3) -> (Vector3, Vector3) {
+    let w = if n.cross(&Vector3::new(0., 1., 0.)).len() <= std::f32::EPSILON {
+        Vector3::new(1., 0., 0.)
+    } else {
+        Vector3::new(0., 1., 0.)
+    };
+    let u = w.cross(n).norm();
+    let v = n.cross(&u).norm();
+    (u, v)
+}
+
+/// How much `m` stretches the area of a small patch of surface which has
+/// the normal `n`.
+fn area_scale(m: &Matrix, n: &Vector3) -> f32 {
+    let (u, v) = frame(n);
+    (*m * u).cross(&(*m * v)).len()
+}
+
+fn luminance(c: &Color) -> f32 {
+    0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b
+}
+
+/// Sum the samples of a light into a single direction and energy.  The
+/// direction is the average of the samples weighted by their energy.
+fn combine_samples(samples: &[(Vector3, Color)]) -> (Vector3, Color) {
+    let energy = samples.iter().map(|(_, e)| *e).sum();
+    let dir = samples
+        .iter()
+        .fold(Vector3::new(0., 0., 0.), |d, (s, e)| d + luminance(e) * s);
+    let dir = if dir.len2() > 0. { dir.norm() } else { dir };
+    (dir, energy)
 }
 
 pub type TextureCoords = (f32, f32);
 
 pub trait LightSource {
     fn get_energy(&self, scene: &Scene, point: &Point3) -> (Vector3, Color);
+
+    // Lights with an area are sampled at several points and return the
+    // direction and energy of every sample, lights which are a single
+    // point can rely on `get_energy`.
+    fn get_energy_samples(&self, scene: &Scene, point: &Point3) -> Vec<(Vector3, Color)> {
+        vec![self.get_energy(scene, point)]
+    }
+
+    // The intensity of the light reaching a point `distance` away from it.
+    // `PointLight` and `SpotLight` apply their attenuation, and the area
+    // lights fall off with the square of the distance like a point light
+    // of the same power.  Directional lights are infinitely far away and do
+    // not fall off, and shape lights only find their shape in the scene, so
+    // these and any other light without an intensity return 1.
+    fn intensity_at(&self, _distance: f32) -> f32 {
+        1.
+    }
+
+    // Where the light is and how much energy it gives off, used to pick
+    // lights in scenes with many of them.  Lights which reach the whole
+    // scene, such as the sun or the sky, have no bounds and are always
+    // evaluated.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
     fn to_string(&self) -> String;
 }
 
+/// What a ray sees when it leaves the scene without hitting anything
+pub trait Background {
+    fn radiance(&self, dir: &Vector3) -> Color;
+    fn to_string(&self) -> String;
+}
+
+// Most transparent surfaces a shadow ray will pass through
+const MAX_SHADOW_SURFACES: usize = 16;
+
+/// The fraction of the energy of the light at `light_pos` which reaches
+/// `point`.
+fn shadow_transmittance(scene: &Scene, point: &Point3, light_pos: &Point3) -> Color {
+    let to_light = light_pos - point;
+    transmittance(scene, point, &to_light.norm(), to_light.len())
+}
+
+/**
+ * The fraction of energy which travels from `point` along `dir` for
+ * `distance` without being blocked.
+ *
+ * Opaque shapes block all of the energy.  Shapes with a refractive material
+ * let through the energy which is not reflected at their surface, filtered
+ * by `Material::transmission_tint` as the camera rays which refract into them
+ * are.  Inside of them the energy is absorbed with distance, so either kind
+ * of colored glass casts a colored shadow.  Shadow rays start outside of
+ * every medium.
+ */
+fn transmittance(scene: &Scene, point: &Point3, dir: &Vector3, distance: f32) -> Color {
+    let mut energy = colors::WHITE;
+    let mut origin = *point;
+    let mut remaining = distance;
+    let mut media = MediumStack::new();
+
+    for _ in 0..MAX_SHADOW_SURFACES {
+        let ray = Ray::new(&origin, dir);
+        let i = match scene.intersect_for(&ray, RayKind::Shadow) {
+            // If there is an intersection: make sure it happens between the light and the
+            // surface point.
+            Some(i) if (i.point - origin).len() < remaining => i,
+            _ => return energy * media.transmittance(remaining),
+        };
+        let step = (i.point - origin).len();
+        energy = energy * media.transmittance(step);
+
+        let material = i.material.borrow();
+        let index = material.refraction_index();
+        if index <= std::f32::EPSILON {
+            return colors::BLACK;
+        }
+        if media.is_boundary(&i) {
+            let outside = media.outside_index(&i);
+            let (n1, n2) = if i.entering { (outside, index) } else { (index, outside) };
+            let fresnel = crate::render::fresnel_refraction(&-*dir, &i.normal, n1, n2);
+            energy = energy * (fresnel * material.transmission_tint(&i));
+        }
+        media = media.cross(&i);
+
+        // Step past the surface and keep going towards the light
+        remaining -= step;
+        origin = i.point + 0.0002 * *dir;
+    }
+
+    colors::BLACK
+}
+
 /**
 A single point light in the scene: it radiates energy with the
 intensity of `Color` equally in all directions.
+
+The energy reaching a point is `intensity * color` scaled by the light's
+`Attenuation` at that distance.
 */
 pub struct PointLight {
     pos: Point3,
     color: Color,
+    intensity: f32,
+    attenuation: Attenuation,
 }
 
 impl PointLight {
+    /// Create a light which does not fall off with distance
     pub fn new(pos: Point3, color: Color) -> PointLight {
-        PointLight { pos, color }
+        PointLight {
+            pos,
+            color,
+            intensity: 1.,
+            attenuation: Attenuation::None,
+        }
+    }
+
+    /// Create a light with the given intensity whose energy falls off
+    /// with the square of the distance.
+    pub fn new_physical(pos: Point3, color: Color, intensity: f32) -> PointLight {
+        PointLight {
+            pos,
+            color,
+            intensity,
+            attenuation: Attenuation::InverseSquare,
+        }
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    pub fn set_attenuation(&mut self, attenuation: Attenuation) {
+        self.attenuation = attenuation;
     }
 }
 
 impl LightSource for PointLight {
     fn get_energy(&self, scene: &Scene, point: &Point3) -> (Vector3, Color) {
-        let dir_to_light = (self.pos - point).norm();
-        let ray = Ray::new(&point, &dir_to_light);
-        let total_energy = match scene.intersect(&ray) {
-            // If there is an intersection: make sure it happens between the light and the
-            // surface point.
-            Some(i) => {
-                if (i.point - point).len2() < (self.pos - point).len2() {
-                    colors::BLACK
-                } else {
-                    self.color
-                }
-            }
-            None => self.color,
-        };
+        let to_light = self.pos - point;
+        let dir_to_light = to_light.norm();
+        let total_energy = self.intensity_at(to_light.len())
+            * shadow_transmittance(scene, point, &self.pos)
+            * self.color;
         (dir_to_light, total_energy)
     }
 
+    fn intensity_at(&self, distance: f32) -> f32 {
+        self.intensity * self.attenuation.factor(distance)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds::point(&self.pos, self.intensity * luminance(&self.color)))
+    }
+
     fn to_string(&self) -> String {
         format!(
-            "Position: ({}, {}, {}), Color: ({}, {}, {})",
+            "Position: ({}, {}, {}), Color: ({}, {}, {}), Intensity: {}, Attenuation: {}",
             self.pos.x(),
             self.pos.y(),
             self.pos.z(),
             self.color.r,
             self.color.g,
+            self.color.b,
+            self.intensity,
+            self.attenuation
+        )
+    }
+}
+
+/**
+A light source which is infinitely far away, such as the sun: every point
+in the scene receives energy with the intensity of `Color` from the same
+direction.
+*/
+pub struct DirectionalLight {
+    direction: Vector3,
+    color: Color,
+}
+
+impl DirectionalLight {
+    /// Create a light whose rays travel along `direction`.
+    pub fn new(direction: Vector3, color: Color) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.norm(),
+            color,
+        }
+    }
+}
+
+impl LightSource for DirectionalLight {
+    fn get_energy(&self, scene: &Scene, point: &Point3) -> (Vector3, Color) {
+        let dir_to_light = -self.direction;
+        // The light is infinitely far away, so anything along the ray
+        // will cast a shadow on the point.
+        let total_energy =
+            transmittance(scene, point, &dir_to_light, std::f32::INFINITY) * self.color;
+        (dir_to_light, total_energy)
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Direction: ({}, {}, {}), Color: ({}, {}, {})",
+            self.direction.x(),
+            self.direction.y(),
+            self.direction.z(),
+            self.color.r,
+            self.color.g,
             self.color.b
         )
     }
@@ -244,3 +613,102 @@ impl LightSource for AmbientLight {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::color::colors::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn directional_light() {
+        let mut scene = Scene::new();
+        let light = DirectionalLight::new(Vector3::new(0., -2., 0.), WHITE);
+
+        let (dir, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert_eq!(Vector3::new(0., 1., 0.), dir);
+        assert_eq!(WHITE, energy);
+
+        // A shape anywhere above the point blocks the light
+        let phong = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        let mut sph = Sphere::new(phong);
+        sph.set_transform(&Matrix::translate(0., 100., 0.));
+        scene.add_shape(Box::new(sph));
+
+        let (_, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert_eq!(BLACK, energy);
+        let (_, energy) = light.get_energy(&scene, &Point3::new(2., 0., 0.));
+        assert_eq!(WHITE, energy);
+    }
+
+    #[test]
+    fn point_light_falloff() {
+        let scene = Scene::new();
+        let light = PointLight::new(Point3::new(0., 2., 0.), WHITE);
+        let (_, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert_eq!(WHITE, energy);
+
+        let light = PointLight::new_physical(Point3::new(0., 2., 0.), WHITE, 8.);
+        let (_, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert_eq!(Color::new(2., 2., 2.), energy);
+        assert_eq!(0.5, light.intensity_at(4.));
+    }
+
+    #[test]
+    fn transparent_shadows() {
+        let mut scene = Scene::new();
+        let light = PointLight::new(Point3::new(0., 4., 0.), WHITE);
+
+        // A glass sphere which absorbs red and green between the point and
+        // the light, which goes through 2 units of it
+        let mut glass = Phong::new(WHITE, WHITE, WHITE, 60., 0., 1.333);
+        glass.absorption = Color::new(0.5, 0.5, 0.);
+        let mut sph = Sphere::new(Rc::new(RefCell::new(glass)));
+        sph.set_transform(&Matrix::translate(0., 2., 0.));
+        scene.add_shape(Box::new(sph));
+
+        let (_, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert!(energy.b > 0.9 && energy.b < 1.);
+        assert!(energy.r > 0.2 && energy.r < 0.5);
+
+        // Clear glass tinted blue by its diffuse color, once going in and
+        // once coming out
+        let mut scene = Scene::new();
+        let blue = Phong::new(WHITE, Color::new(0.5, 0.5, 1.), WHITE, 60., 0., 1.333);
+        let mut sph = Sphere::new(Rc::new(RefCell::new(blue)));
+        sph.set_transform(&Matrix::translate(0., 2., 0.));
+        scene.add_shape(Box::new(sph));
+
+        let (_, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert!(energy.b > 0.9 && energy.b < 1.);
+        assert!(energy.r > 0.2 && energy.r < 0.25);
+
+        // An opaque sphere blocks all of the light
+        let opaque = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        let mut sph = Sphere::new(opaque);
+        sph.set_transform(&(Matrix::translate(0., 1., 0.) * Matrix::scale(0.5, 0.5, 0.5)));
+        scene.add_shape(Box::new(sph));
+
+        let (_, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert_eq!(BLACK, energy);
+    }
+
+    #[test]
+    fn absorbing_shadows() {
+        let mut scene = Scene::new();
+        let light = PointLight::new(Point3::new(0., 4., 0.), WHITE);
+
+        // Clear glass which absorbs red, the light goes through 2 units of
+        // it on the way to the point
+        let mut phong = Phong::new(WHITE, WHITE, WHITE, 60., 0., 1.5);
+        phong.absorption = Color::new(1., 0., 0.);
+        let mut sph = Sphere::new(Rc::new(RefCell::new(phong)));
+        sph.set_transform(&Matrix::translate(0., 2., 0.));
+        scene.add_shape(Box::new(sph));
+
+        let (_, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        let fresnel = energy.g;
+        assert!(fresnel > 0.8 && fresnel < 1.);
+        assert!((energy.r - fresnel * (-2f32).exp()).abs() < 0.001);
+    }
+}
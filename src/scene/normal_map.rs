@@ -0,0 +1,169 @@
+/// Detail which bends the shading normal away from the normal of the
+/// geometry, so flat surfaces can look rough, embossed or engraved
+use std::rc::Rc;
+
+use crate::math::Vector3;
+
+use super::{Intersection, Texture, TextureCoords};
+
+// Step in texture coordinates used to find the slope of a bump function
+const BUMP_DELTA: f32 = 1e-3;
+
+/**
+How a material perturbs the normal of the surface it is on.  The result is
+used for all shading at the hit, including lights, shadows, reflections and
+refractions, so a bumpy mirror also reflects a bumpy image.
+*/
+#[derive(Clone)]
+pub enum NormalMap {
+    /// A tangent-space normal map, usually an image loaded with
+    /// `ImageTexture::open_linear`.  The red, green and blue channels hold
+    /// the normal along the tangent, the bitangent and the normal of the
+    /// surface, mapped from [-1, 1] to [0, 1], so (0.5, 0.5, 1) leaves the
+    /// surface as it is.
+    Tangent(Rc<dyn Texture>),
+    /// A height over the surface for each texture coordinate.  The normal
+    /// tilts away from the slope of the height, scaled by `strength`.
+    Bump {
+        height: Rc<dyn Fn(TextureCoords) -> f32>,
+        strength: f32,
+    },
+}
+
+impl NormalMap {
+    pub fn apply(&self, i: &Intersection) -> Vector3 {
+        let n = i.normal;
+        let (t, b) = i.tangent_frame();
+        let perturbed = match self {
+            NormalMap::Tangent(texture) => {
+                let c = texture.color_at(i.tex_coord, &i.local_point);
+                (2. * c.r - 1.) * t + (2. * c.g - 1.) * b + (2. * c.b - 1.) * n
+            }
+            NormalMap::Bump { height, strength } => {
+                let (u, v) = i.tex_coord;
+                let h = height((u, v));
+                let dh_du = (height((u + BUMP_DELTA, v)) - h) / BUMP_DELTA;
+                let dh_dv = (height((u, v + BUMP_DELTA)) - h) / BUMP_DELTA;
+                n - *strength * (dh_du * t + dh_dv * b)
+            }
+        };
+
+        // A normal which turns away from the viewer would show the back of
+        // the surface, keep the normal of the geometry instead.
+        if perturbed.len() <= 1e-6 || perturbed.dot(&i.eye_dir) <= 0. {
+            return n;
+        }
+        perturbed.norm()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{Point3, Ray};
+    use crate::scene::colors::*;
+    use crate::scene::{Color, Plane, Renderable, Scene, TexturePhong};
+    use std::cell::RefCell;
+
+    fn hit() -> Intersection {
+        let material = TexturePhong::new(
+            Rc::new(BLACK),
+            Rc::new(WHITE),
+            Rc::new(BLACK),
+            1.,
+            0.,
+            0.,
+        );
+        Intersection {
+            id: 0,
+            t: 1.,
+            material: Rc::new(RefCell::new(material)),
+            point: Point3::new(0., 0., 0.),
+            eye_dir: Vector3::new(0., 1., 0.),
+            normal: Vector3::new(0., 1., 0.),
+            entering: true,
+            tex_coord: (0.5, 0.5),
+            local_point: Point3::new(0., 0., 0.),
+            // Not perpendicular to the normal, the frame corrects that
+            tangent: Vector3::new(2., 1., 0.),
+            outside_index: 1.,
+            wavelength: None,
+            vertex_color: None,
+        }
+    }
+
+    #[test]
+    fn tangent_frame() {
+        let i = hit();
+        let (t, b) = i.tangent_frame();
+        assert!((t.x() - 1.).abs() < 0.0001);
+        assert!(t.dot(&i.normal).abs() < 0.0001 && b.dot(&i.normal).abs() < 0.0001);
+        assert!(t.dot(&b).abs() < 0.0001 && (b.len() - 1.).abs() < 0.0001);
+
+        // Without a tangent any frame will do
+        let mut i = hit();
+        i.tangent = Vector3::new(0., 0., 0.);
+        let (t, b) = i.tangent_frame();
+        assert!((t.len() - 1.).abs() < 0.0001 && t.dot(&b).abs() < 0.0001);
+    }
+
+    #[test]
+    fn maps() {
+        let i = hit();
+        let (t, _) = i.tangent_frame();
+
+        let flat = NormalMap::Tangent(Rc::new(Color::new(0.5, 0.5, 1.)));
+        assert!((flat.apply(&i) - i.normal).len() < 0.0001);
+
+        // Tilted towards the tangent
+        let tilted = NormalMap::Tangent(Rc::new(Color::new(1., 0.5, 1.)));
+        let n = tilted.apply(&i);
+        assert!((n.len() - 1.).abs() < 0.0001);
+        assert!((n.dot(&t) - 0.5f32.sqrt()).abs() < 0.001);
+
+        // A height which rises along u tilts the normal back along -u
+        let ramp = NormalMap::Bump {
+            height: Rc::new(|tx: TextureCoords| tx.0),
+            strength: 1.,
+        };
+        let n = ramp.apply(&i);
+        assert!((n.dot(&t) + 0.5f32.sqrt()).abs() < 0.001);
+
+        let level = NormalMap::Bump {
+            height: Rc::new(|_| 3.),
+            strength: 1.,
+        };
+        assert!((level.apply(&i) - i.normal).len() < 0.0001);
+
+        // Normals turned away from the viewer are ignored
+        let under = NormalMap::Tangent(Rc::new(Color::new(1., 0.5, 0.)));
+        let mut grazing = hit();
+        grazing.eye_dir = Vector3::new(-0.9, 0.1, 0.).norm();
+        assert!((under.apply(&grazing) - grazing.normal).len() < 0.0001);
+    }
+
+    #[test]
+    fn scene_uses_shading_normal() {
+        let mut material = TexturePhong::new(
+            Rc::new(BLACK),
+            Rc::new(WHITE),
+            Rc::new(BLACK),
+            1.,
+            0.,
+            0.,
+        );
+        material.normal_map = Some(NormalMap::Tangent(Rc::new(Color::new(1., 0.5, 1.))));
+        let plane = Plane::new(
+            &Point3::new(0., 0., 0.),
+            &Vector3::new(0., 1., 0.),
+            Rc::new(RefCell::new(material)),
+        );
+        let mut scene = Scene::new();
+        scene.add_shape(Box::new(plane));
+
+        let ray = Ray::new(&Point3::new(0., 1., 0.), &Vector3::new(0., -1., 0.));
+        let i = scene.intersect(&ray).unwrap();
+        assert!(i.normal.y() < 0.99);
+        assert!((i.normal.len() - 1.).abs() < 0.0001);
+    }
+}
@@ -0,0 +1,358 @@
+/// A physically based material built on a microfacet model
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use crate::math::Vector3;
+use crate::render::fresnel_conductor;
+
+use super::{
+    colors, frame, luminance, BsdfSample, Color, Intersection, Material, NormalMap, TextureCoords,
+    Texture,
+};
+
+// Below this the highlight of a point light becomes too small to be seen
+const MIN_ROUGHNESS: f32 = 0.02;
+
+/**
+A material using the metallic-roughness model of glTF and most modern
+renderers.  Reflected light is split into a specular part, which uses the
+Cook-Torrance model with a GGX distribution of microfacet normals, the
+Smith masking-shadowing term and Schlick's Fresnel approximation, and a
+diffuse part which only gets the energy that is not reflected specularly.
+Unlike `Phong` the surface never reflects more energy than it receives.
+
+`metallic` blends between a dielectric, whose highlights are white and
+reflect `0.08 * specular` of the light at normal incidence (0.5 gives the
+4% of most plastics), and a metal, which has no diffuse part and
+highlights tinted by `base_color`.
+
+When set, `base_color_map` and `metallic_roughness_map` are multiplied
+with the constant parameters.  As in glTF, the green channel of the
+metallic-roughness map holds the roughness and the blue channel holds the
+metallic value.  `normal_map` adds surface detail.
+
+Setting `conductor` to the complex index of refraction `(n, k)` of a metal
+replaces Schlick's approximation for the metallic part with the exact
+Fresnel equations of a conductor, which also gives the metal its color.
+*/
+#[derive(Clone)]
+pub struct PbrMaterial {
+    pub base_color: Color,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub specular: f32,
+    pub base_color_map: Option<Rc<dyn Texture>>,
+    pub metallic_roughness_map: Option<Rc<dyn Texture>>,
+    pub normal_map: Option<NormalMap>,
+    pub conductor: Option<(Color, Color)>,
+}
+
+/// The parameters of a `PbrMaterial` at one point of the surface
+struct Params {
+    diffuse: Color,
+    f0: Color,
+    metallic: f32,
+    alpha: f32,
+}
+
+impl PbrMaterial {
+    pub fn new(base_color: Color, metallic: f32, roughness: f32) -> PbrMaterial {
+        PbrMaterial {
+            base_color,
+            metallic,
+            roughness,
+            specular: 0.5,
+            base_color_map: None,
+            metallic_roughness_map: None,
+            normal_map: None,
+            conductor: None,
+        }
+    }
+
+    fn params(&self, i: &Intersection) -> Params {
+        let (tx, p) = (i.tex_coord, &i.local_point);
+        let base = match (i.vertex_color, &self.base_color_map) {
+            (Some(c), _) => c,
+            (None, None) => self.base_color,
+            (None, Some(map)) => self.base_color * map.color_at(tx, p),
+        };
+        let (metallic, roughness) = match self.metallic_roughness_map {
+            None => (self.metallic, self.roughness),
+            Some(ref map) => {
+                let c = map.color_at(tx, p);
+                (self.metallic * c.b, self.roughness * c.g)
+            }
+        };
+        let metallic = metallic.clamp(0., 1.);
+        let roughness = roughness.clamp(MIN_ROUGHNESS, 1.);
+
+        let metal = match self.conductor {
+            None => base,
+            Some((ref n, ref k)) => fresnel_conductor(1., n, k),
+        };
+        Params {
+            diffuse: (1. - metallic) * base,
+            f0: (1. - metallic) * self.dielectric_f0() + metallic * metal,
+            metallic,
+            alpha: roughness * roughness,
+        }
+    }
+
+    fn dielectric_f0(&self) -> Color {
+        let f0 = 0.08 * self.specular;
+        Color::new(f0, f0, f0)
+    }
+
+    /// The fraction of light reflected by microfacets seen at the angle
+    /// with cosine `cos`
+    fn fresnel(&self, p: &Params, cos: f32) -> Color {
+        match self.conductor {
+            None => schlick(&p.f0, cos),
+            Some((ref n, ref k)) => {
+                (1. - p.metallic) * schlick(&self.dielectric_f0(), cos)
+                    + p.metallic * fresnel_conductor(cos, n, k)
+            }
+        }
+    }
+}
+
+impl Material for PbrMaterial {
+    fn evaluate(&self, wo: &Vector3, wi: &Vector3, i: &Intersection) -> Color {
+        let n = &i.normal;
+        let (n_dot_v, n_dot_l) = (n.dot(wo), n.dot(wi));
+        if n_dot_v <= 0. || n_dot_l <= 0. {
+            return colors::BLACK;
+        }
+
+        let p = self.params(i);
+        let h = (*wo + *wi).norm();
+        let f = self.fresnel(&p, wo.dot(&h));
+        let specular = (ggx(p.alpha, n.dot(&h)) * smith(p.alpha, n_dot_v, n_dot_l)
+            / (4. * n_dot_v * n_dot_l))
+            * f;
+        let diffuse = (1. / PI) * ((colors::WHITE - f) * p.diffuse);
+        diffuse + specular
+    }
+
+    /// Pick either a microfacet normal in proportion to its share of the
+    /// surface, and reflect `wo` about it, or a cosine weighted direction
+    /// for the diffuse part.
+    fn sample(&self, wo: &Vector3, i: &Intersection, u: f32, v: f32) -> Option<BsdfSample> {
+        let n = &i.normal;
+        if n.dot(wo) <= 0. {
+            return None;
+        }
+
+        let p = self.params(i);
+        let ps = specular_probability(&p);
+        let (t, b) = frame(n);
+        let phi = 2. * PI * v;
+        let wi = if u < ps {
+            let u = u / ps;
+            let a2 = p.alpha * p.alpha;
+            let cos_theta = ((1. - u) / (1. + (a2 - 1.) * u)).sqrt();
+            let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+            let h = sin_theta * phi.cos() * t + sin_theta * phi.sin() * b + cos_theta * *n;
+            2. * wo.dot(&h) * h - *wo
+        } else {
+            let u = (u - ps) / (1. - ps);
+            let r = u.sqrt();
+            r * phi.cos() * t + r * phi.sin() * b + (1. - u).max(0.).sqrt() * *n
+        };
+
+        let pdf = self.pdf(wo, &wi, i);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            weight: (n.dot(&wi) / pdf) * self.evaluate(wo, &wi, i),
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3, i: &Intersection) -> f32 {
+        let n = &i.normal;
+        let (n_dot_v, n_dot_l) = (n.dot(wo), n.dot(wi));
+        if n_dot_v <= 0. || n_dot_l <= 0. {
+            return 0.;
+        }
+
+        let p = self.params(i);
+        let h = (*wo + *wi).norm();
+        let specular = ggx(p.alpha, n.dot(&h)) * n.dot(&h) / (4. * wo.dot(&h).max(1e-6));
+        let diffuse = n_dot_l / PI;
+        let ps = specular_probability(&p);
+        ps * specular + (1. - ps) * diffuse
+    }
+
+    fn diffuse(&self, tx: TextureCoords) -> Color {
+        match self.base_color_map {
+            None => self.base_color,
+            Some(ref map) => self.base_color * map.color(tx),
+        }
+    }
+
+    fn set_diffuse(&mut self, c: Color) {
+        self.base_color = c;
+    }
+
+    fn ambient(&self, i: &Intersection) -> Color {
+        self.params(i).diffuse
+    }
+
+    fn shading_normal(&self, i: &Intersection) -> Vector3 {
+        match self.normal_map {
+            None => i.normal,
+            Some(ref map) => map.apply(i),
+        }
+    }
+
+    fn refraction_index(&self) -> f32 {
+        0.
+    }
+
+    // Reflections are part of the BRDF rather than a perfect mirror
+    fn reflectivity(&self) -> f32 {
+        0.
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "PBR(Base Color: {}, Metallic: {}, Roughness: {}, Specular: {})",
+            self.base_color, self.metallic, self.roughness, self.specular
+        )
+    }
+}
+
+/// GGX (Trowbridge-Reitz) distribution of microfacet normals
+fn ggx(alpha: f32, n_dot_h: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+/// Smith masking-shadowing term for GGX
+fn smith(alpha: f32, n_dot_v: f32, n_dot_l: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let g1 = |x: f32| 2. * x / (x + (a2 + (1. - a2) * x * x).sqrt());
+    g1(n_dot_v) * g1(n_dot_l)
+}
+
+fn schlick(f0: &Color, cos: f32) -> Color {
+    let k = (1. - cos.clamp(0., 1.)).powi(5);
+    *f0 + k * (colors::WHITE - *f0)
+}
+
+/// The chance of sampling the specular part rather than the diffuse part
+fn specular_probability(p: &Params) -> f32 {
+    let specular = luminance(&p.f0);
+    let diffuse = luminance(&p.diffuse) * (1. - specular);
+    if specular + diffuse <= 0. {
+        1.
+    } else {
+        specular / (specular + diffuse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{stratified_samples, Point3, Random};
+    use crate::scene::color::colors::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A hit on a surface facing up, seen from above
+    fn hit(material: PbrMaterial) -> Intersection {
+        let n = Vector3::new(0., 1., 0.);
+        Intersection {
+            id: 0,
+            t: 1.,
+            material: Rc::new(RefCell::new(material)),
+            point: Point3::new(0., 0., 0.),
+            eye_dir: n,
+            normal: n,
+            entering: true,
+            tex_coord: (0., 0.),
+            local_point: Point3::new(0., 0., 0.),
+            tangent: Vector3::new(1., 0., 0.),
+            outside_index: 1.,
+            wavelength: None,
+            vertex_color: None,
+        }
+    }
+
+    #[test]
+    fn energy_is_conserved() {
+        let wo = Vector3::new(0.6, 0.8, 0.);
+        let mut rng = Random::new(5);
+        for (metallic, roughness) in &[(0., 0.3), (1., 0.3), (0.5, 0.8)] {
+            let m = PbrMaterial::new(WHITE, *metallic, *roughness);
+            let i = hit(m.clone());
+
+            // The average sample weight is the fraction of the energy which
+            // is reflected.  Rough surfaces lose some of it, since light
+            // bouncing between microfacets is not modelled.
+            let samples = stratified_samples(4096, &mut rng);
+            let total: Color = samples
+                .iter()
+                .filter_map(|(u, v)| m.sample(&wo, &i, *u, *v))
+                .map(|s| {
+                    assert!((s.pdf - m.pdf(&wo, &s.wi, &i)).abs() < 0.001 * s.pdf);
+                    s.weight
+                })
+                .sum();
+            let reflected = total.g / samples.len() as f32;
+            assert!(reflected > 0.5 && reflected < 1.01);
+        }
+    }
+
+    #[test]
+    fn diffuse_and_metal() {
+        let n = Vector3::new(0., 1., 0.);
+
+        // Without specular reflection a white surface lit head on reflects
+        // all of the light, like a white `Phong` surface.
+        let mut plastic = PbrMaterial::new(WHITE, 0., 1.);
+        plastic.specular = 0.;
+        let energy = plastic.get_reflected_energy(&WHITE, &n, &hit(plastic.clone()));
+        assert!((energy.r - 1.).abs() < 0.001);
+
+        // A metal has no diffuse part, so a black metal reflects nothing
+        let metal = PbrMaterial::new(BLACK, 1., 0.5);
+        assert_eq!(BLACK, metal.get_reflected_energy(&WHITE, &n, &hit(metal.clone())));
+
+        // A smooth metal has a sharp highlight in the mirror direction
+        let gold = PbrMaterial::new(Color::new(1., 0.8, 0.3), 1., 0.1);
+        let i = hit(gold.clone());
+        let wo = Vector3::new(0.6, 0.8, 0.);
+        let mirror = Vector3::new(-0.6, 0.8, 0.);
+        let off = Vector3::new(0., 0.8, 0.6);
+        let at_mirror = gold.evaluate(&wo, &mirror, &i);
+        assert!(at_mirror.r > 100. * gold.evaluate(&wo, &off, &i).r);
+        assert!(at_mirror.b < at_mirror.r);
+    }
+
+    #[test]
+    fn conductor() {
+        // The complex index of refraction of gold for red, green and blue
+        let n = Color::new(0.143, 0.374, 1.442);
+        let k = Color::new(3.983, 2.385, 1.603);
+        let mut gold = PbrMaterial::new(WHITE, 1., 0.1);
+        gold.conductor = Some((n, k));
+
+        let i = hit(gold.clone());
+        let wo = Vector3::new(0.6, 0.8, 0.);
+        let mirror = Vector3::new(-0.6, 0.8, 0.);
+        let at_mirror = gold.evaluate(&wo, &mirror, &i);
+        assert!(at_mirror.r > at_mirror.g && at_mirror.g > at_mirror.b);
+
+        // The white base color does not matter, the color comes from the
+        // index of refraction
+        let f0 = fresnel_conductor(1., &n, &k);
+        let p = gold.params(&i);
+        assert!((p.f0.b - f0.b).abs() < 0.0001);
+    }
+}
@@ -2,7 +2,7 @@
 use std::cell::*;
 use std::rc::Rc;
 
-use super::{Intersection, Material, Renderable};
+use super::{Intersection, Material, Renderable, Visibility};
 use crate::math::{Matrix, Point3, Ray, Vector3};
 
 pub struct Plane {
@@ -12,6 +12,7 @@ pub struct Plane {
     material: Rc<RefCell<dyn Material>>,
     transform: Matrix,
     inv_transform: Matrix,
+    visibility: Visibility,
 
     // axes for the texture coordinates
     u: Vector3,
@@ -36,6 +37,7 @@ impl Plane {
             material: Rc::clone(&material),
             transform: Matrix::identity(),
             inv_transform: Matrix::identity(),
+            visibility: Visibility::all(),
             u,
             v,
         }
@@ -75,6 +77,11 @@ impl Renderable for Plane {
                 normal: (self.transform * self.normal),
                 material: Rc::clone(&self.material),
                 tex_coord: (u, v),
+                local_point: t * transformed_ray,
+                tangent: self.u,
+                outside_index: 1.,
+                wavelength: None,
+                vertex_color: None,
             };
             Some(i)
         } else {
@@ -102,26 +109,32 @@ impl Renderable for Plane {
     fn size(&self) -> usize {
         1
     }
+
+    fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    fn set_visibility(&mut self, visibility: Visibility) {
+        self.visibility = visibility;
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::math::Vector3;
-    use crate::scene::{
-        color::{colors::WHITE, Color},
-        material::TexturePhong,
-        TextureCoords,
-    };
-
-    fn white(_: TextureCoords) -> Color {
-        WHITE
-    }
+    use crate::scene::{color::colors::WHITE, material::TexturePhong, Texture};
 
     #[test]
     fn texture_coords() {
+        let white: Rc<dyn Texture> = Rc::new(WHITE);
         let phong = Rc::new(RefCell::new(TexturePhong::new(
-            white, white, white, 60., 0., 0.,
+            white.clone(),
+            white.clone(),
+            white,
+            60.,
+            0.,
+            0.,
         )));
         let normal = Vector3::new(0., 1., 0.);
         let plane = Plane::new(&Point3::new(0., 0., 0.), &normal, phong);
@@ -0,0 +1,394 @@
+/// Load point clouds from PLY and XYZ files
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result};
+use std::path::Path;
+
+use super::Color;
+use crate::math::{Point3, Vector3};
+
+/// A single point read from a point cloud file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CloudPoint {
+    pub position: Point3,
+    pub normal: Option<Vector3>,
+    pub color: Option<Color>,
+}
+
+/// Load a point cloud, choosing the format from the extension of the file:
+/// `.ply` files are read as PLY and everything else is read as XYZ.
+pub fn load_point_cloud(path: &str) -> Result<Vec<CloudPoint>> {
+    let is_ply = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ply"));
+    let reader = BufReader::new(File::open(path)?);
+    if is_ply {
+        read_ply(reader)
+    } else {
+        read_xyz(reader)
+    }
+}
+
+/**
+ * Read the vertices of an ASCII or binary little endian PLY file.
+ *
+ * Only the `vertex` element is kept: its `x`, `y` and `z` properties give
+ * the position, `nx`, `ny` and `nz` the normal, and `red`, `green` and
+ * `blue` the color.  Integer colors are assumed to be in [0, 255] and
+ * floating point colors in [0, 1].  Any other elements (e.g. faces) are
+ * skipped.
+ */
+pub fn read_ply<R: Read>(mut reader: R) -> Result<Vec<CloudPoint>> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+
+    let (header, body) = split_header(&bytes)?;
+    let mut body = match header.format {
+        PlyFormat::Ascii => {
+            let text = std::str::from_utf8(body).map_err(|_| invalid("PLY body is not valid text"))?;
+            PlySource::Ascii(text.split_whitespace())
+        }
+        PlyFormat::BinaryLittleEndian => PlySource::Binary(body),
+    };
+
+    let mut points = vec![];
+    for element in header.elements.iter() {
+        let is_vertex = element.name == "vertex";
+        let mut values = vec![0.; element.properties.len()];
+        for _ in 0..element.count {
+            for (i, prop) in element.properties.iter().enumerate() {
+                match prop.list {
+                    None => values[i] = body.read(prop.ty)?,
+                    Some(count_ty) => {
+                        let n = body.read(count_ty)? as usize;
+                        for _ in 0..n {
+                            body.read(prop.ty)?;
+                        }
+                    }
+                }
+            }
+
+            if is_vertex {
+                points.push(element.to_point(&values)?);
+            }
+        }
+    }
+
+    Ok(points)
+}
+
+/**
+ * Read an XYZ file: one point per line, written as `x y z`, optionally
+ * followed by a color `r g b` and then a normal `nx ny nz`.  Colors may be
+ * given in either [0, 1] or [0, 255].  Blank lines and lines starting with
+ * `#` are ignored.
+ */
+pub fn read_xyz<R: Read>(mut reader: R) -> Result<Vec<CloudPoint>> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    let mut points = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let values = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<f32>())
+            .collect::<std::result::Result<Vec<f32>, _>>()
+            .map_err(|_| invalid(&format!("Invalid number on line {} of XYZ file", n + 1)))?;
+
+        if values.len() < 3 {
+            return Err(invalid(&format!("Expected x y z on line {} of XYZ file", n + 1)));
+        }
+
+        let position = Point3::new(values[0], values[1], values[2]);
+        let color = if values.len() >= 6 {
+            let scale = if values[3..6].iter().any(|&c| c > 1.) { 1. / 255. } else { 1. };
+            Some(scale * Color::new(values[3], values[4], values[5]))
+        } else {
+            None
+        };
+        let normal = if values.len() >= 9 {
+            unit_normal(values[6], values[7], values[8])
+        } else {
+            None
+        };
+
+        points.push(CloudPoint { position, normal, color });
+    }
+
+    Ok(points)
+}
+
+// Files often write a zero normal for points which have none
+fn unit_normal(x: f32, y: f32, z: f32) -> Option<Vector3> {
+    let n = Vector3::new(x, y, z);
+    if n.len2() > 0. {
+        Some(n.norm())
+    } else {
+        None
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Result<PlyType> {
+        match name {
+            "char" | "int8" => Ok(PlyType::I8),
+            "uchar" | "uint8" => Ok(PlyType::U8),
+            "short" | "int16" => Ok(PlyType::I16),
+            "ushort" | "uint16" => Ok(PlyType::U16),
+            "int" | "int32" => Ok(PlyType::I32),
+            "uint" | "uint32" => Ok(PlyType::U32),
+            "float" | "float32" => Ok(PlyType::F32),
+            "double" | "float64" => Ok(PlyType::F64),
+            _ => Err(invalid(&format!("Unknown PLY property type: {}", name))),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        *self == PlyType::F32 || *self == PlyType::F64
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    ty: PlyType,
+    // The type of the element count if this property is a list
+    list: Option<PlyType>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+impl PlyElement {
+    fn index_of(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|p| p.name == name && p.list.is_none())
+    }
+
+    fn to_point(&self, values: &[f64]) -> Result<CloudPoint> {
+        let get = |name: &str| self.index_of(name).map(|i| values[i] as f32);
+
+        let position = match (get("x"), get("y"), get("z")) {
+            (Some(x), Some(y), Some(z)) => Point3::new(x, y, z),
+            _ => return Err(invalid("PLY vertex is missing x, y or z")),
+        };
+
+        let normal = match (get("nx"), get("ny"), get("nz")) {
+            (Some(x), Some(y), Some(z)) => unit_normal(x, y, z),
+            _ => None,
+        };
+
+        let color = match (get("red"), get("green"), get("blue")) {
+            (Some(r), Some(g), Some(b)) => {
+                let is_float = self.properties[self.index_of("red").unwrap()].ty.is_float();
+                let scale = if is_float { 1. } else { 1. / 255. };
+                Some(scale * Color::new(r, g, b))
+            }
+            _ => None,
+        };
+
+        Ok(CloudPoint { position, normal, color })
+    }
+}
+
+struct PlyHeader {
+    format: PlyFormat,
+    elements: Vec<PlyElement>,
+}
+
+/// Parse the header at the start of `bytes` and return it along with the
+/// bytes of the body which follows it.
+fn split_header(bytes: &[u8]) -> Result<(PlyHeader, &[u8])> {
+    const END: &[u8] = b"end_header";
+    let end = bytes
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or_else(|| invalid("PLY file has no end_header"))?;
+
+    // The body starts after the newline which ends the header
+    let mut body_start = end + END.len();
+    while body_start < bytes.len() && bytes[body_start] != b'\n' {
+        body_start += 1;
+    }
+    body_start = (body_start + 1).min(bytes.len());
+
+    let text = std::str::from_utf8(&bytes[..end]).map_err(|_| invalid("PLY header is not valid text"))?;
+    let mut lines = text.lines().map(|l| l.trim());
+    if lines.next() != Some("ply") {
+        return Err(invalid("Not a PLY file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", other, _] => {
+                return Err(invalid(&format!("Unsupported PLY format: {}", other)))
+            }
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid("Invalid PLY element count"))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_ty, ty, name] => {
+                let element = elements.last_mut().ok_or_else(|| invalid("PLY property before element"))?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    ty: PlyType::parse(ty)?,
+                    list: Some(PlyType::parse(count_ty)?),
+                });
+            }
+            ["property", ty, name] => {
+                let element = elements.last_mut().ok_or_else(|| invalid("PLY property before element"))?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    ty: PlyType::parse(ty)?,
+                    list: None,
+                });
+            }
+            _ => (), // comments, obj_info and blank lines
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid("PLY header has no format"))?;
+    Ok((PlyHeader { format, elements }, &bytes[body_start..]))
+}
+
+enum PlySource<'a> {
+    Ascii(std::str::SplitWhitespace<'a>),
+    Binary(&'a [u8]),
+}
+
+impl<'a> PlySource<'a> {
+    fn read(&mut self, ty: PlyType) -> Result<f64> {
+        match self {
+            PlySource::Ascii(tokens) => tokens
+                .next()
+                .ok_or_else(|| invalid("PLY file ended early"))?
+                .parse::<f64>()
+                .map_err(|_| invalid("Invalid number in PLY file")),
+            PlySource::Binary(bytes) => {
+                let size = ty.size();
+                if bytes.len() < size {
+                    return Err(invalid("PLY file ended early"));
+                }
+                let (b, rest) = bytes.split_at(size);
+                *bytes = rest;
+                let v = match ty {
+                    PlyType::I8 => b[0] as i8 as f64,
+                    PlyType::U8 => b[0] as f64,
+                    PlyType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    PlyType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    PlyType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    PlyType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    PlyType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    PlyType::F64 => {
+                        f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+                    }
+                };
+                Ok(v)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_ply() {
+        let ply = "ply\n\
+                   format ascii 1.0\n\
+                   comment made by hand\n\
+                   element vertex 2\n\
+                   property float x\n\
+                   property float y\n\
+                   property float z\n\
+                   property uchar red\n\
+                   property uchar green\n\
+                   property uchar blue\n\
+                   element face 1\n\
+                   property list uchar int vertex_indices\n\
+                   end_header\n\
+                   0 1 2 255 0 0\n\
+                   -1 0.5 3 0 255 0\n\
+                   3 0 1 1\n";
+
+        let points = read_ply(ply.as_bytes()).unwrap();
+        assert_eq!(2, points.len());
+        assert_eq!(Point3::new(-1., 0.5, 3.), points[1].position);
+        assert_eq!(Some(Color::new(1., 0., 0.)), points[0].color);
+        assert_eq!(None, points[0].normal);
+    }
+
+    #[test]
+    fn binary_ply() {
+        let mut ply = b"ply\nformat binary_little_endian 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nproperty float nx\nproperty float ny\nproperty float nz\nend_header\n".to_vec();
+        for v in [1f32, 2., 3., 0., 0., 2.].iter() {
+            ply.extend_from_slice(&v.to_le_bytes());
+        }
+
+        let points = read_ply(&ply[..]).unwrap();
+        assert_eq!(1, points.len());
+        assert_eq!(Point3::new(1., 2., 3.), points[0].position);
+        assert_eq!(Some(Vector3::new(0., 0., 1.)), points[0].normal);
+        assert_eq!(None, points[0].color);
+    }
+
+    #[test]
+    fn xyz() {
+        let xyz = "# a comment\n1 2 3\n\n4 5 6 255 255 0\n";
+        let points = read_xyz(xyz.as_bytes()).unwrap();
+        assert_eq!(2, points.len());
+        assert_eq!(Point3::new(4., 5., 6.), points[1].position);
+        assert_eq!(Some(Color::new(1., 1., 0.)), points[1].color);
+
+        let normals = "0 0 0 1 1 1 0 0 2\n0 0 0 1 1 1 0 0 0\n";
+        let points = read_xyz(normals.as_bytes()).unwrap();
+        assert_eq!(Some(Vector3::new(0., 0., 1.)), points[0].normal);
+        assert_eq!(None, points[1].normal);
+
+        assert_eq!(true, read_xyz("1 2\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,362 @@
+/// Render a cloud of points as small spheres or disks
+use std::cell::*;
+use std::rc::Rc;
+
+use crate::math::{BoundingBox, Matrix, Ray, Vector3};
+
+use super::ply::{load_point_cloud, CloudPoint};
+use super::Intersection;
+use super::Material;
+use super::Renderable;
+use super::TextureCoords;
+use super::Visibility;
+
+/// How each point in a `PointCloud` is drawn
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PointShape {
+    Sphere,
+    /// A disk facing along the point's normal.  Points without a normal are
+    /// drawn as spheres.
+    Disk,
+}
+
+// Most points that will be tested by a leaf of the BVH
+const LEAF_SIZE: usize = 4;
+
+struct BvhNode {
+    bounds: BoundingBox,
+    // For a leaf the range of points in the node, for an inner node
+    // `start` is the index of the second child (the first child always
+    // directly follows its parent) and `count` is 0.
+    start: usize,
+    count: usize,
+}
+
+/**
+ * A `PointCloud` renders every point of a scan as a small sphere or disk of
+ * the same radius.
+ *
+ * The points are kept in a bounding volume hierarchy so that only the points
+ * near a ray are tested.  Points which carry a color pass it on through
+ * `Intersection::vertex_color`, where it replaces the diffuse color of the
+ * cloud's material.
+ */
+pub struct PointCloud {
+    id: i32,
+    name: String,
+    points: Vec<CloudPoint>,
+    nodes: Vec<BvhNode>,
+    radius: f32,
+    shape: PointShape,
+    material: Rc<RefCell<dyn Material>>,
+    transform: Matrix,
+    inv_transform: Matrix,
+    visibility: Visibility,
+}
+
+impl PointCloud {
+    pub fn new(
+        points: Vec<CloudPoint>,
+        radius: f32,
+        material: Rc<RefCell<dyn Material>>,
+    ) -> PointCloud {
+        let mut cloud = PointCloud {
+            id: 0,
+            name: String::from("PointCloud"),
+            points,
+            nodes: vec![],
+            radius,
+            shape: PointShape::Sphere,
+            material: Rc::clone(&material),
+            transform: Matrix::identity(),
+            inv_transform: Matrix::identity(),
+            visibility: Visibility::all(),
+        };
+
+        if !cloud.points.is_empty() {
+            let n = cloud.points.len();
+            cloud.build(0, n);
+        }
+
+        cloud
+    }
+
+    /// Load a point cloud from a PLY or XYZ file
+    pub fn from_file(
+        path: &str,
+        radius: f32,
+        material: Rc<RefCell<dyn Material>>,
+    ) -> std::io::Result<PointCloud> {
+        let points = load_point_cloud(path)?;
+        Ok(PointCloud::new(points, radius, material))
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+
+    pub fn set_shape(&mut self, shape: PointShape) {
+        self.shape = shape;
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Recursively build the BVH over `points[start..end]`, splitting at the
+    /// median along the longest axis of the centers.
+    fn build(&mut self, start: usize, end: usize) {
+        let bounds = self.points[start..end]
+            .iter()
+            .fold(BoundingBox::empty(), |b, p| b.grow(&p.position))
+            .pad(self.radius);
+
+        let index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds,
+            start,
+            count: end - start,
+        });
+
+        if end - start <= LEAF_SIZE {
+            return;
+        }
+
+        let axis = bounds.longest_axis();
+        let coord = |p: &CloudPoint| match axis {
+            0 => p.position.x(),
+            1 => p.position.y(),
+            _ => p.position.z(),
+        };
+        self.points[start..end].sort_by(|a, b| {
+            coord(a)
+                .partial_cmp(&coord(b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mid = start + (end - start) / 2;
+        self.build(start, mid);
+        let second = self.nodes.len();
+        self.build(mid, end);
+
+        self.nodes[index].start = second;
+        self.nodes[index].count = 0;
+    }
+
+    /// Intersect a single point.  Returns the ray parameter, the local normal
+    /// and whether the ray is entering the point.
+    fn intersect_point(&self, ray: &Ray, point: &CloudPoint) -> Option<(f32, Vector3, bool)> {
+        match (self.shape, point.normal) {
+            (PointShape::Disk, Some(n)) => {
+                let denom = n.dot(&ray.direction());
+                if denom.abs() < std::f32::EPSILON {
+                    return None;
+                }
+                let t = (point.position - ray.origin()).dot(&n) / denom;
+                if t < 0. || ((t * ray) - point.position).len2() > self.radius * self.radius {
+                    return None;
+                }
+                // Disks are two sided
+                let normal = if denom > 0. { -n } else { n };
+                Some((t, normal, true))
+            }
+            _ => {
+                let l = ray.origin() - point.position;
+                let a = ray.direction().len2();
+                let b = 2. * ray.direction().dot(&l);
+                let c = l.len2() - self.radius * self.radius;
+                let discr = b * b - 4. * a * c;
+                if discr < 0. {
+                    return None;
+                }
+                let root = discr.sqrt();
+                let t0 = (-b - root) / (2. * a);
+                let t1 = (-b + root) / (2. * a);
+                if t1 < 0. {
+                    return None;
+                }
+                let entering = t0 > 0.;
+                let t = if entering { t0 } else { t1 };
+                let mut normal = ((t * ray) - point.position).norm();
+                if !entering {
+                    normal = -normal;
+                }
+                Some((t, normal, entering))
+            }
+        }
+    }
+
+    fn get_texture_coord(n: &Vector3) -> TextureCoords {
+        use std::f32::consts::PI;
+        let u = (1. + n.z().atan2(n.x()) / PI) * 0.5;
+        let v = n.y().acos() / PI;
+        (u, v)
+    }
+}
+
+impl Renderable for PointCloud {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_id(&mut self, id: i32) {
+        self.id = id;
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        // apply transformation to the ray
+        let transformed_ray = self.inv_transform * ray;
+
+        let mut nearest: Option<(f32, Vector3, bool, usize)> = None;
+        let mut stack = vec![0];
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            match node.bounds.intersect(&transformed_ray) {
+                None => continue,
+                Some((t_enter, _)) => {
+                    if nearest.is_some_and(|(t, _, _, _)| t_enter > t) {
+                        continue;
+                    }
+                }
+            }
+
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(idx + 1);
+            } else {
+                for p in node.start..node.start + node.count {
+                    if let Some((t, n, e)) = self.intersect_point(&transformed_ray, &self.points[p]) {
+                        if nearest.is_none_or(|(nt, _, _, _)| t < nt) {
+                            nearest = Some((t, n, e, p));
+                        }
+                    }
+                }
+            }
+        }
+
+        let (t, local_normal, entering, p) = nearest?;
+        let normal = (self.inv_transform.transpose() * local_normal).norm();
+
+        Some(Intersection {
+            id: self.id,
+            t,
+            material: Rc::clone(&self.material),
+            point: t * ray,
+            eye_dir: -ray.direction().norm(),
+            normal,
+            entering,
+            tex_coord: PointCloud::get_texture_coord(&local_normal),
+            local_point: t * transformed_ray,
+            tangent: self.transform * Vector3::new(-local_normal.z(), 0., local_normal.x()),
+            outside_index: 1.,
+            wavelength: None,
+            vertex_color: self.points[p].color,
+        })
+    }
+
+    fn set_transform(&mut self, mat: &Matrix) {
+        self.transform = *mat;
+        self.inv_transform = self.transform.inverse();
+    }
+
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_material_mut(&mut self) -> Option<RefMut<dyn Material>> {
+        Some(self.material.borrow_mut())
+    }
+
+    fn get_material(&self) -> Option<Ref<dyn Material>> {
+        Some(self.material.borrow())
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "PointCloud(Name: {}, Points: {}, Material: {})",
+            self.get_name(),
+            self.points.len(),
+            self.material.borrow().to_string()
+        )
+    }
+
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    fn set_visibility(&mut self, visibility: Visibility) {
+        self.visibility = visibility;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Point3;
+    use crate::scene::color::{colors::*, Color};
+    use crate::scene::Phong;
+
+    fn cloud(points: Vec<CloudPoint>) -> PointCloud {
+        let material = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        PointCloud::new(points, 0.1, material)
+    }
+
+    fn point(x: f32, y: f32, z: f32) -> CloudPoint {
+        CloudPoint {
+            position: Point3::new(x, y, z),
+            normal: None,
+            color: None,
+        }
+    }
+
+    #[test]
+    fn nearest_point() {
+        // A line of points running away from the ray origin
+        let points = (0..100).map(|i| point(0., 0., i as f32)).collect();
+        let c = cloud(points);
+        assert_eq!(100, c.len());
+
+        let ray = Ray::new(&Point3::new(0., 0., -5.), &Vector3::new(0., 0., 1.));
+        let i = c.intersect(&ray).unwrap();
+        assert!((i.t - 4.9).abs() < 0.0001);
+        assert_eq!(Vector3::new(0., 0., -1.), i.normal);
+
+        let ray = Ray::new(&Point3::new(0., 0., 50.5), &Vector3::new(0., 0., 1.));
+        let i = c.intersect(&ray).unwrap();
+        assert!((i.t - 0.4).abs() < 0.0001);
+
+        let miss = Ray::new(&Point3::new(0., 1., -5.), &Vector3::new(0., 0., 1.));
+        assert_eq!(true, c.intersect(&miss).is_none());
+    }
+
+    #[test]
+    fn point_color_and_disks() {
+        let mut p = point(0., 0., 0.);
+        p.color = Some(Color::new(0., 0.5, 1.));
+        p.normal = Some(Vector3::new(0., 0., 1.));
+        let mut c = cloud(vec![p]);
+
+        let ray = Ray::new(&Point3::new(0.05, 0., -5.), &Vector3::new(0., 0., 1.));
+        let i = c.intersect(&ray).unwrap();
+        assert_eq!(Some(Color::new(0., 0.5, 1.)), i.vertex_color);
+        assert_eq!(true, i.t < 5.);
+
+        // The color of the point is used in place of the diffuse color
+        let light = Vector3::new(0., 0., -1.);
+        let energy = i.material.borrow().get_reflected_energy(&WHITE, &light, &i);
+        assert!(energy.r < energy.g && energy.g < energy.b);
+
+        c.set_shape(PointShape::Disk);
+        let i = c.intersect(&ray).unwrap();
+        assert_eq!(5., i.t);
+        assert_eq!(Vector3::new(0., 0., -1.), i.normal);
+    }
+}
@@ -0,0 +1,433 @@
+/// Shapes defined by signed distance functions and rendered by sphere tracing
+use std::cell::*;
+use std::rc::Rc;
+
+use crate::math::{Matrix, Ray, Vector3};
+
+use super::Intersection;
+use super::Material;
+use super::Renderable;
+use super::TextureCoords;
+use super::Visibility;
+
+/**
+ * A tree of signed distance functions.
+ *
+ * Leaves are primitives centered on the origin, inner nodes either combine
+ * the distances of their children (unions, intersections, ...) or warp the
+ * space the child is evaluated in (repetition, twisting, bending).  Distances
+ * are negative inside of a shape and positive outside.
+ */
+#[derive(Debug, Clone)]
+pub enum Sdf {
+    Sphere(f32),
+    Box(Vector3),
+    RoundBox(Vector3, f32),
+    Torus(f32, f32),
+    Cylinder(f32, f32),
+    Union(Box<Sdf>, Box<Sdf>),
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f32),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    Subtraction(Box<Sdf>, Box<Sdf>),
+    Translate(Box<Sdf>, Vector3),
+    Repeat(Box<Sdf>, Vector3),
+    Twist(Box<Sdf>, f32),
+    Bend(Box<Sdf>, f32),
+}
+
+impl Sdf {
+    pub fn sphere(radius: f32) -> Sdf {
+        Sdf::Sphere(radius)
+    }
+
+    /// A box with the given half widths along each axis
+    pub fn cuboid(half: Vector3) -> Sdf {
+        Sdf::Box(half)
+    }
+
+    /// A box whose edges and corners are rounded off with `radius`
+    pub fn round_box(half: Vector3, radius: f32) -> Sdf {
+        Sdf::RoundBox(half, radius)
+    }
+
+    /// A torus lying in the XZ plane
+    pub fn torus(major: f32, minor: f32) -> Sdf {
+        Sdf::Torus(major, minor)
+    }
+
+    /// A capped cylinder running along the Y axis
+    pub fn cylinder(radius: f32, half_height: f32) -> Sdf {
+        Sdf::Cylinder(radius, half_height)
+    }
+
+    pub fn union(a: Sdf, b: Sdf) -> Sdf {
+        Sdf::Union(Box::new(a), Box::new(b))
+    }
+
+    /// Union of `a` and `b` which blends the seam between the two over a
+    /// distance of roughly `k`
+    pub fn smooth_union(a: Sdf, b: Sdf, k: f32) -> Sdf {
+        Sdf::SmoothUnion(Box::new(a), Box::new(b), k)
+    }
+
+    pub fn intersection(a: Sdf, b: Sdf) -> Sdf {
+        Sdf::Intersection(Box::new(a), Box::new(b))
+    }
+
+    /// Carve `b` out of `a`
+    pub fn subtraction(a: Sdf, b: Sdf) -> Sdf {
+        Sdf::Subtraction(Box::new(a), Box::new(b))
+    }
+
+    pub fn translate(a: Sdf, offset: Vector3) -> Sdf {
+        Sdf::Translate(Box::new(a), offset)
+    }
+
+    /// Repeat `a` infinitely with the given period along each axis.  A period
+    /// of zero disables repetition along that axis.
+    pub fn repeat(a: Sdf, period: Vector3) -> Sdf {
+        Sdf::Repeat(Box::new(a), period)
+    }
+
+    /// Twist `a` around the Y axis by `k` radians per unit of height
+    pub fn twist(a: Sdf, k: f32) -> Sdf {
+        Sdf::Twist(Box::new(a), k)
+    }
+
+    /// Bend `a` around the Z axis by `k` radians per unit along X
+    pub fn bend(a: Sdf, k: f32) -> Sdf {
+        Sdf::Bend(Box::new(a), k)
+    }
+
+    /// Returns the signed distance from `p` to the surface described by
+    /// this tree.
+    pub fn distance(&self, p: &Vector3) -> f32 {
+        match self {
+            Sdf::Sphere(r) => p.len() - r,
+            Sdf::Box(half) => box_distance(p, half),
+            Sdf::RoundBox(half, r) => {
+                let inner = Vector3::new(half.x() - r, half.y() - r, half.z() - r);
+                box_distance(p, &inner) - r
+            }
+            Sdf::Torus(major, minor) => {
+                let qx = (p.x() * p.x() + p.z() * p.z()).sqrt() - major;
+                (qx * qx + p.y() * p.y()).sqrt() - minor
+            }
+            Sdf::Cylinder(r, h) => {
+                let dx = (p.x() * p.x() + p.z() * p.z()).sqrt() - r;
+                let dy = p.y().abs() - h;
+                let outside = (dx.max(0.) * dx.max(0.) + dy.max(0.) * dy.max(0.)).sqrt();
+                outside + dx.max(dy).min(0.)
+            }
+            Sdf::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Sdf::SmoothUnion(a, b, k) => smooth_min(a.distance(p), b.distance(p), *k),
+            Sdf::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            Sdf::Subtraction(a, b) => a.distance(p).max(-b.distance(p)),
+            Sdf::Translate(a, offset) => a.distance(&(p - offset)),
+            Sdf::Repeat(a, period) => {
+                let q = Vector3::new(
+                    repeat_axis(p.x(), period.x()),
+                    repeat_axis(p.y(), period.y()),
+                    repeat_axis(p.z(), period.z()),
+                );
+                a.distance(&q)
+            }
+            Sdf::Twist(a, k) => {
+                let (s, c) = (k * p.y()).sin_cos();
+                let q = Vector3::new(c * p.x() - s * p.z(), p.y(), s * p.x() + c * p.z());
+                a.distance(&q)
+            }
+            Sdf::Bend(a, k) => {
+                let (s, c) = (k * p.x()).sin_cos();
+                let q = Vector3::new(c * p.x() - s * p.y(), s * p.x() + c * p.y(), p.z());
+                a.distance(&q)
+            }
+        }
+    }
+
+    /// Estimate the gradient of the distance field at `p` with central
+    /// differences.
+    pub fn gradient(&self, p: &Vector3) -> Vector3 {
+        let h = NORMAL_DELTA;
+        let dx = Vector3::new(h, 0., 0.);
+        let dy = Vector3::new(0., h, 0.);
+        let dz = Vector3::new(0., 0., h);
+        Vector3::new(
+            self.distance(&(*p + dx)) - self.distance(&(*p - dx)),
+            self.distance(&(*p + dy)) - self.distance(&(*p - dy)),
+            self.distance(&(*p + dz)) - self.distance(&(*p - dz)),
+        )
+        .norm()
+    }
+}
+
+const NORMAL_DELTA: f32 = 0.0005;
+
+fn box_distance(p: &Vector3, half: &Vector3) -> f32 {
+    let qx = p.x().abs() - half.x();
+    let qy = p.y().abs() - half.y();
+    let qz = p.z().abs() - half.z();
+    let outside = Vector3::new(qx.max(0.), qy.max(0.), qz.max(0.)).len();
+    outside + qx.max(qy.max(qz)).min(0.)
+}
+
+/// Polynomial smooth minimum
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0. {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0., 1.);
+    b + (a - b) * h - k * h * (1. - h)
+}
+
+fn repeat_axis(x: f32, period: f32) -> f32 {
+    if period <= 0. {
+        x
+    } else {
+        x - period * (x / period).round()
+    }
+}
+
+/**
+ * A `Renderable` whose surface is the zero set of an `Sdf`.
+ *
+ * Rays are intersected by sphere tracing: the ray is advanced by the
+ * distance to the nearest surface until it is within `epsilon` of it.
+ * Warping operations like `Twist` and `Bend` do not preserve distances,
+ * so for shapes which use them the step size can be scaled down with
+ * `set_step_scale` to keep the tracer from stepping through the surface.
+ */
+pub struct SdfShape {
+    id: i32,
+    name: String,
+    sdf: Sdf,
+    material: Rc<RefCell<dyn Material>>,
+    transform: Matrix,
+    inv_transform: Matrix,
+    visibility: Visibility,
+    max_steps: usize,
+    max_distance: f32,
+    epsilon: f32,
+    step_scale: f32,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Sdf, material: Rc<RefCell<dyn Material>>) -> SdfShape {
+        SdfShape {
+            id: 0,
+            name: String::from("SdfShape"),
+            sdf,
+            material: Rc::clone(&material),
+            transform: Matrix::identity(),
+            inv_transform: Matrix::identity(),
+            visibility: Visibility::all(),
+            max_steps: 256,
+            max_distance: 100.,
+            epsilon: 0.0001,
+            step_scale: 1.,
+        }
+    }
+
+    pub fn new_with_name(name: &str, sdf: Sdf, material: Rc<RefCell<dyn Material>>) -> SdfShape {
+        let mut shape = SdfShape::new(sdf, material);
+        shape.name = String::from(name);
+        shape
+    }
+
+    /// Scale each step of the sphere tracer by `scale` (which should be
+    /// in (0, 1]).
+    pub fn set_step_scale(&mut self, scale: f32) {
+        self.step_scale = scale;
+    }
+
+    pub fn set_max_steps(&mut self, steps: usize) {
+        self.max_steps = steps;
+    }
+
+    /// The furthest distance, in the shape's local space, that a ray will
+    /// be traced before it is considered a miss.
+    pub fn set_max_distance(&mut self, distance: f32) {
+        self.max_distance = distance;
+    }
+
+    fn get_texture_coord(n: &Vector3) -> TextureCoords {
+        use std::f32::consts::PI;
+        let u = (1. + n.z().atan2(n.x()) / PI) * 0.5;
+        let v = n.y().acos() / PI;
+        (u, v)
+    }
+
+    /// March along `ray` and return the distance travelled to the surface
+    /// and whether the ray started outside of the shape.
+    fn march(&self, origin: &Vector3, dir: &Vector3) -> Option<(f32, bool)> {
+        // Work out which side of the surface the ray starts on so that rays
+        // which start inside of the shape (e.g. refracted rays) can find
+        // their way out.  If the origin is on the surface, then the side is
+        // the one that the ray is heading into.
+        let d0 = self.sdf.distance(origin);
+        let outside = if d0.abs() < self.epsilon {
+            self.sdf.gradient(origin).dot(dir) > 0.
+        } else {
+            d0 > 0.
+        };
+        let sign = if outside { 1. } else { -1. };
+
+        // Do not report the surface the ray starts on as a hit
+        let min_distance = 10. * self.epsilon;
+
+        let mut s = 0.;
+        for _ in 0..self.max_steps {
+            let d = sign * self.sdf.distance(&(*origin + s * *dir));
+            if d < self.epsilon && s > min_distance {
+                return Some((s, outside));
+            }
+
+            s += self.step_scale * d.max(self.epsilon);
+            if s > self.max_distance {
+                return None;
+            }
+        }
+
+        None
+    }
+}
+
+impl Renderable for SdfShape {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_id(&mut self, id: i32) {
+        self.id = id;
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        // apply transformation to the ray
+        let transformed_ray = self.inv_transform * ray;
+        let dir_len = transformed_ray.direction().len();
+        let dir = transformed_ray.direction().scalar_div(dir_len);
+        let origin = Vector3::from(transformed_ray.origin());
+
+        let (s, entering) = self.march(&origin, &dir)?;
+
+        // `s` is measured along the normalized local ray, convert it back
+        // to the parameterization of the original ray.
+        let t = s / dir_len;
+        let local_normal = self.sdf.gradient(&(origin + s * dir));
+        let mut normal = (self.inv_transform.transpose() * local_normal).norm();
+        if !entering {
+            normal = -normal;
+        }
+
+        Some(Intersection {
+            id: self.id,
+            t,
+            material: Rc::clone(&self.material),
+            point: t * ray,
+            eye_dir: -ray.direction().norm(),
+            normal,
+            entering,
+            tex_coord: SdfShape::get_texture_coord(&local_normal),
+            local_point: t * transformed_ray,
+            tangent: self.transform * Vector3::new(-local_normal.z(), 0., local_normal.x()),
+            outside_index: 1.,
+            wavelength: None,
+            vertex_color: None,
+        })
+    }
+
+    fn set_transform(&mut self, mat: &Matrix) {
+        self.transform = *mat;
+        self.inv_transform = self.transform.inverse();
+    }
+
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_material_mut(&mut self) -> Option<RefMut<dyn Material>> {
+        Some(self.material.borrow_mut())
+    }
+
+    fn get_material(&self) -> Option<Ref<dyn Material>> {
+        Some(self.material.borrow())
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "SdfShape(Name: {}, Material: {})",
+            self.get_name(),
+            self.material.borrow().to_string()
+        )
+    }
+
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    fn set_visibility(&mut self, visibility: Visibility) {
+        self.visibility = visibility;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Point3;
+    use crate::scene::color::colors::WHITE;
+    use crate::scene::Phong;
+
+    fn assert_near(expected: f32, actual: f32) {
+        assert!(
+            (expected - actual).abs() < 0.001,
+            "expected {} but got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn distances() {
+        let p = Vector3::new(2., 0., 0.);
+        assert_near(1., Sdf::sphere(1.).distance(&p));
+        assert_near(1.5, Sdf::cuboid(Vector3::new(0.5, 0.5, 0.5)).distance(&p));
+        assert_near(0.5, Sdf::torus(1., 0.5).distance(&p));
+
+        let a = Sdf::sphere(1.);
+        let b = Sdf::translate(Sdf::sphere(1.), Vector3::new(1.5, 0., 0.));
+        let p = Vector3::new(0.75, 1., 0.);
+        let hard = Sdf::union(a.clone(), b.clone()).distance(&p);
+        let smooth = Sdf::smooth_union(a, b, 0.5).distance(&p);
+        assert!(smooth < hard);
+
+        let rep = Sdf::repeat(Sdf::sphere(1.), Vector3::new(4., 0., 0.));
+        assert_near(-1., rep.distance(&Vector3::new(8., 0., 0.)));
+    }
+
+    #[test]
+    fn intersection() {
+        let phong = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 0., 0.)));
+        let mut shape = SdfShape::new(Sdf::sphere(1.), phong);
+        shape.set_transform(&Matrix::translate(0., 0., -2.));
+
+        let ray = Ray::new(&Point3::new(0., 0., 2.), &Vector3::new(0., 0., -1.));
+        let i = shape.intersect(&ray).unwrap();
+        assert_near(3., i.t);
+        assert_near(1., i.normal.z());
+        assert_eq!(true, i.entering);
+
+        // Leaving the shape from the inside
+        let inside = Ray::new(&Point3::new(0., 0., -2.), &Vector3::new(0., 0., -1.));
+        let i = shape.intersect(&inside).unwrap();
+        assert_near(1., i.t);
+        assert_near(1., i.normal.z());
+        assert_eq!(false, i.entering);
+
+        let miss = Ray::new(&Point3::new(0., 0., 2.), &Vector3::new(0., 1., 0.));
+        assert_eq!(true, shape.intersect(&miss).is_none());
+    }
+}
@@ -0,0 +1,177 @@
+/// Use a glowing shape as a light source
+use std::f32::consts::PI;
+
+use crate::math::{stratified_samples, Point3, Random, Vector3};
+
+use super::{
+    colors, combine_samples, luminance, transmittance, Color, LightBounds, LightSource, Renderable,
+    Scene,
+};
+
+const DEFAULT_SAMPLES: usize = 16;
+
+// Points picked on a shape to estimate how much energy it gives off
+const POWER_SAMPLES: usize = 16;
+
+/**
+Lights the scene with the emission of the material of a shape in the
+scene, such as a light panel or a neon tube.  Points are picked on the
+surface of the shape and each one casts its own shadow ray, so the shape
+casts soft shadows the size of the shape.
+
+The shape is found by its id in the scene when the light is sampled, so
+changes to the shape's material are picked up by the light.  Shapes which
+cannot be sampled give off no light.
+
+A light made with `for_shape` also has bounds for the light tree, taken
+from the bounding box of the shape and the energy its material gives off
+when the light is made.
+*/
+pub struct ShapeLight {
+    shape_id: i32,
+    samples: usize,
+    bounds: Option<LightBounds>,
+}
+
+impl ShapeLight {
+    pub fn new(shape_id: i32) -> ShapeLight {
+        ShapeLight {
+            shape_id,
+            samples: DEFAULT_SAMPLES,
+            bounds: None,
+        }
+    }
+
+    /// A light for `shape`, which has the id `shape_id` in the scene
+    pub fn for_shape(shape_id: i32, shape: &dyn Renderable) -> ShapeLight {
+        let mut light = ShapeLight::new(shape_id);
+        light.bounds = shape_bounds(shape);
+        light
+    }
+
+    /// Set the number of points sampled on the shape
+    pub fn set_samples(&mut self, samples: usize) {
+        self.samples = samples.max(1);
+    }
+}
+
+impl LightSource for ShapeLight {
+    fn get_energy(&self, scene: &Scene, point: &Point3) -> (Vector3, Color) {
+        combine_samples(&self.get_energy_samples(scene, point))
+    }
+
+    fn get_energy_samples(&self, scene: &Scene, point: &Point3) -> Vec<(Vector3, Color)> {
+        let shape = match scene.shapes().iter().find(|s| s.id() == self.shape_id) {
+            Some(shape) => shape,
+            None => return vec![],
+        };
+        let material = match shape.get_material() {
+            Some(material) => material,
+            None => return vec![],
+        };
+
+        let mut rng = Random::from_point(point, self.shape_id as u64);
+        let n = self.samples as f32;
+        stratified_samples(self.samples, &mut rng)
+            .into_iter()
+            .filter_map(|(s, t)| shape.sample_surface(s, t))
+            .map(|sample| {
+                let to_light = sample.point - point;
+                let dist = to_light.len();
+                let dir = to_light.norm();
+
+                // Only the side of the surface facing the point glows
+                // towards it.
+                let cos_light = -dir.dot(&sample.normal);
+                if cos_light <= 0. || sample.pdf <= 0. {
+                    return (dir, colors::BLACK);
+                }
+
+                // Stop the shadow ray just short of the surface of the light
+                // so that it does not shadow itself.
+                let visible = transmittance(scene, point, &dir, dist - 0.001);
+                let scale = cos_light / (dist * dist * sample.pdf * n * PI);
+                (dir, scale * visible * material.emission(sample.tex_coord))
+            })
+            .collect()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        self.bounds
+    }
+
+    fn to_string(&self) -> String {
+        format!("Shape: {}, Samples: {}", self.shape_id, self.samples)
+    }
+}
+
+/// Bounds around `shape` whose power is the energy it sends to a point one
+/// unit away, estimated from the emission and area of its surface samples
+fn shape_bounds(shape: &dyn Renderable) -> Option<LightBounds> {
+    let bounds = shape.bounding_box()?;
+    let material = shape.get_material()?;
+    let mut rng = Random::new(0);
+    let power: f32 = stratified_samples(POWER_SAMPLES, &mut rng)
+        .into_iter()
+        .filter_map(|(s, t)| shape.sample_surface(s, t))
+        .filter(|sample| sample.pdf > 0.)
+        .map(|sample| luminance(&material.emission(sample.tex_coord)) / sample.pdf)
+        .sum();
+    Some(LightBounds {
+        bounds,
+        direction: Vector3::new(0., 1., 0.),
+        cos_spread: -1.,
+        power: power / (POWER_SAMPLES as f32 * PI),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Matrix;
+    use crate::scene::color::colors::*;
+    use crate::scene::{Emissive, Renderable, Sphere};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn glowing_sphere() {
+        let mut scene = Scene::new();
+        let material = Rc::new(RefCell::new(Emissive::new(WHITE, 16.)));
+        let mut sph = Sphere::new(material);
+        sph.set_transform(&Matrix::translate(0., 4., 0.));
+        scene.add_emissive_shape(Box::new(sph));
+        assert_eq!(1, scene.lights().len());
+
+        let mut light = ShapeLight::new(0);
+        light.set_samples(256);
+
+        // A sphere of radius 1 at a distance of 4 covers a solid angle of
+        // about 0.2, which gives an energy of about 16 * 0.2 / pi
+        let (dir, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert!((dir - Vector3::new(0., 1., 0.)).len() < 0.05);
+        assert!(energy.r > 0.9 && energy.r < 1.15);
+
+        // A shape without an id in the scene gives no light
+        let (_, energy) = ShapeLight::new(7).get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert_eq!(BLACK, energy);
+    }
+
+    #[test]
+    fn bounds() {
+        let material = Rc::new(RefCell::new(Emissive::new(WHITE, 16.)));
+        let mut sph = Sphere::new(material);
+        sph.set_transform(&Matrix::translate(0., 4., 0.));
+
+        // The energy given off by a sphere of area 4 pi
+        let bounds = ShapeLight::for_shape(0, &sph).bounds().unwrap();
+        assert!((bounds.power - 64.).abs() < 0.5);
+        assert!((bounds.bounds.center() - Point3::new(0., 4., 0.)).len() < 0.0001);
+        assert_eq!(Vector3::new(2., 2., 2.), bounds.bounds.extent());
+
+        // Shapes added as emissive shapes are placed in the light tree
+        let mut scene = Scene::new();
+        scene.add_emissive_shape(Box::new(sph));
+        assert!(scene.lights()[0].bounds().is_some());
+    }
+}
@@ -0,0 +1,194 @@
+/// An analytic model of the daylight sky
+use std::f32::consts::PI;
+
+use crate::math::Vector3;
+
+use super::{Background, Color, DirectionalLight};
+
+/// Coefficients of the Perez sky luminance distribution
+#[derive(Debug, Copy, Clone)]
+struct Perez {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+}
+
+impl Perez {
+    /// Relative luminance of the sky at `theta` from the zenith and `gamma`
+    /// from the sun.
+    fn eval(&self, theta: f32, gamma: f32) -> f32 {
+        (1. + self.a * (self.b / theta.cos().max(0.01)).exp())
+            * (1. + self.c * (self.d * gamma).exp() + self.e * gamma.cos() * gamma.cos())
+    }
+}
+
+/**
+The Preetham daylight model ("A Practical Analytic Model for Daylight",
+1999).  The color of the sky in every direction is computed from the
+position of the sun and the turbidity, which measures how hazy the air is:
+2 is a very clear day and 10 is a hazy one.
+
+The sun is placed `elevation` degrees above the horizon and `azimuth`
+degrees around the y axis, starting from +z and turning towards +x.  The
+sky is scaled so that the zenith has a luminance of `intensity`.
+*/
+pub struct PreethamSky {
+    elevation: f32,
+    azimuth: f32,
+    turbidity: f32,
+    intensity: f32,
+    sun_dir: Vector3,
+    theta_sun: f32,
+
+    // Perez coefficients and zenith values for luminance and chromaticity
+    perez_y: Perez,
+    perez_cx: Perez,
+    perez_cy: Perez,
+    zenith: (f32, f32, f32),
+}
+
+impl PreethamSky {
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> PreethamSky {
+        let t = turbidity.max(1.);
+        let (e, a) = (elevation.to_radians(), azimuth.to_radians());
+        let sun_dir = Vector3::new(e.cos() * a.sin(), e.sin(), e.cos() * a.cos());
+        let theta_sun = PI / 2. - e.max(0.);
+
+        let perez_y = Perez {
+            a: 0.1787 * t - 1.4630,
+            b: -0.3554 * t + 0.4275,
+            c: -0.0227 * t + 5.3251,
+            d: 0.1206 * t - 2.5771,
+            e: -0.0670 * t + 0.3703,
+        };
+        let perez_cx = Perez {
+            a: -0.0193 * t - 0.2592,
+            b: -0.0665 * t + 0.0008,
+            c: -0.0004 * t + 0.2125,
+            d: -0.0641 * t - 0.8989,
+            e: -0.0033 * t + 0.0452,
+        };
+        let perez_cy = Perez {
+            a: -0.0167 * t - 0.2608,
+            b: -0.0950 * t + 0.0092,
+            c: -0.0079 * t + 0.2102,
+            d: -0.0441 * t - 1.6537,
+            e: -0.0109 * t + 0.0529,
+        };
+
+        let th = theta_sun;
+        let (th2, th3) = (th * th, th * th * th);
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * th);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_cx = (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th) * t * t
+            + (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394) * t
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_cy = (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th) * t * t
+            + (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516) * t
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        PreethamSky {
+            elevation,
+            azimuth,
+            turbidity: t,
+            intensity: 1.,
+            sun_dir,
+            theta_sun,
+            perez_y,
+            perez_cx,
+            perez_cy,
+            zenith: (zenith_y, zenith_cx, zenith_cy),
+        }
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    /// The direction from the scene towards the sun
+    pub fn sun_direction(&self) -> Vector3 {
+        self.sun_dir
+    }
+
+    /// A directional light shining from the sun, tinted with the color of
+    /// the sky around the sun.  It carries no energy once the sun has set.
+    pub fn sun_light(&self, intensity: f32) -> DirectionalLight {
+        let c = self.radiance(&self.sun_dir);
+        let max = c.r.max(c.g).max(c.b);
+        let color = if self.elevation <= 0. || max <= 0. {
+            Color::new(0., 0., 0.)
+        } else {
+            (intensity / max) * c
+        };
+        DirectionalLight::new(-self.sun_dir, color)
+    }
+
+    fn perez(&self, p: &Perez, zenith: f32, theta: f32, gamma: f32) -> f32 {
+        zenith * p.eval(theta, gamma) / p.eval(0., self.theta_sun)
+    }
+}
+
+impl Background for PreethamSky {
+    fn radiance(&self, dir: &Vector3) -> Color {
+        let dir = dir.norm();
+        // Below the horizon the sky is continued with the color at the
+        // horizon.
+        let theta = dir.y().clamp(0., 1.).acos().min(PI / 2. - 0.001);
+        let gamma = dir.dot(&self.sun_dir).clamp(-1., 1.).acos();
+
+        let (zy, zx, zc) = self.zenith;
+        let lum = self.perez(&self.perez_y, zy, theta, gamma) / zy;
+        let cx = self.perez(&self.perez_cx, zx, theta, gamma);
+        let cy = self.perez(&self.perez_cy, zc, theta, gamma);
+
+        // xyY to XYZ to linear sRGB
+        let y = self.intensity * lum.max(0.);
+        let x = cx / cy * y;
+        let z = (1. - cx - cy) / cy * y;
+        let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+        let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+        let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+        Color::new(r.max(0.), g.max(0.), b.max(0.))
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Preetham Sky(Sun Elevation: {}, Sun Azimuth: {}, Turbidity: {}, Intensity: {})",
+            self.elevation, self.azimuth, self.turbidity, self.intensity
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sky_colors() {
+        let sky = PreethamSky::new(30., 90., 2.5);
+        let sun = sky.sun_direction();
+        assert!((sun.x() - 0.866).abs() < 0.001 && (sun.y() - 0.5).abs() < 0.001);
+
+        // A clear sky is blue overhead
+        let zenith = sky.radiance(&Vector3::new(0., 1., 0.));
+        assert!(zenith.b > zenith.r);
+
+        // and brighter towards the sun than away from it
+        let near = sky.radiance(&Vector3::new(1., 0.6, 0.));
+        let away = sky.radiance(&Vector3::new(-1., 0.6, 0.));
+        assert!(near.r + near.g + near.b > away.r + away.g + away.b);
+    }
+
+    #[test]
+    fn sun_light() {
+        let sky = PreethamSky::new(30., 90., 2.5);
+        let sun = sky.sun_light(1.);
+        assert!((sun.direction.x() + 0.866).abs() < 0.001);
+        assert!(sun.color.r > 0. && sun.color.r <= 1.);
+
+        let night = PreethamSky::new(-10., 0., 2.5);
+        assert_eq!(Color::new(0., 0., 0.), night.sun_light(1.).color);
+    }
+}
@@ -0,0 +1,166 @@
+/// Textures defined throughout the space of a shape rather than on its
+/// surface, so that they don't stretch where the texture coordinates do
+use std::f32::consts::PI;
+
+use super::{Color, Texture, TextureCoords};
+use crate::math::{Perlin, Point3};
+
+// Where only the texture coordinates are known, such as in the gui, they
+// stand in for the point
+fn uv_point(tx: TextureCoords) -> Point3 {
+    Point3::new(tx.0, tx.1, 0.)
+}
+
+fn mix(a: &Color, b: &Color, t: f32) -> Color {
+    let t = t.clamp(0., 1.);
+    (1. - t) * *a + t * *b
+}
+
+/// Fractal noise blending between `a` and `b`, with features about
+/// `1 / scale` across
+#[derive(Clone)]
+pub struct NoiseTexture {
+    perlin: Perlin,
+    pub a: Color,
+    pub b: Color,
+    pub scale: f32,
+    pub octaves: usize,
+}
+
+impl NoiseTexture {
+    pub fn new(seed: u64, a: Color, b: Color, scale: f32) -> NoiseTexture {
+        NoiseTexture {
+            perlin: Perlin::new(seed),
+            a,
+            b,
+            scale,
+            octaves: 4,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn color(&self, tx: TextureCoords) -> Color {
+        self.color_at(tx, &uv_point(tx))
+    }
+
+    fn color_at(&self, _: TextureCoords, p: &Point3) -> Color {
+        let n = self.perlin.fbm(&(self.scale * *p), self.octaves);
+        mix(&self.a, &self.b, 0.5 + 0.5 * n)
+    }
+}
+
+/**
+Thin veins of `vein` running through `base` in planes across the x axis of
+the shape.  `scale` is the number of veins per unit and `turbulence` is how
+far, in veins, they are bent out of their planes.
+*/
+#[derive(Clone)]
+pub struct Marble {
+    perlin: Perlin,
+    pub base: Color,
+    pub vein: Color,
+    pub scale: f32,
+    pub turbulence: f32,
+    pub octaves: usize,
+}
+
+impl Marble {
+    pub fn new(seed: u64, base: Color, vein: Color, scale: f32) -> Marble {
+        Marble {
+            perlin: Perlin::new(seed),
+            base,
+            vein,
+            scale,
+            turbulence: 2.,
+            octaves: 5,
+        }
+    }
+}
+
+impl Texture for Marble {
+    fn color(&self, tx: TextureCoords) -> Color {
+        self.color_at(tx, &uv_point(tx))
+    }
+
+    fn color_at(&self, _: TextureCoords, p: &Point3) -> Color {
+        let q = self.scale * *p;
+        let bend = self.turbulence * self.perlin.turbulence(&q, self.octaves);
+        // The veins are where the sine crosses zero
+        let s = (PI * (q.x() + bend)).sin();
+        mix(&self.base, &self.vein, (1. - s.abs()).powi(4))
+    }
+}
+
+/**
+Growth rings around the y axis of the shape, fading from `light` to `dark`
+across each ring.  `rings` is the number of rings per unit and `distortion`
+is how far, in rings, noise pushes them in and out.
+*/
+#[derive(Clone)]
+pub struct Wood {
+    perlin: Perlin,
+    pub light: Color,
+    pub dark: Color,
+    pub rings: f32,
+    pub distortion: f32,
+}
+
+impl Wood {
+    pub fn new(seed: u64, light: Color, dark: Color, rings: f32) -> Wood {
+        Wood {
+            perlin: Perlin::new(seed),
+            light,
+            dark,
+            rings,
+            distortion: 0.5,
+        }
+    }
+}
+
+impl Texture for Wood {
+    fn color(&self, tx: TextureCoords) -> Color {
+        self.color_at(tx, &uv_point(tx))
+    }
+
+    fn color_at(&self, _: TextureCoords, p: &Point3) -> Color {
+        let r = (p.x() * p.x() + p.z() * p.z()).sqrt() * self.rings;
+        let r = r + self.distortion * self.perlin.fbm(p, 3);
+        mix(&self.light, &self.dark, r.rem_euclid(1.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::color::colors::*;
+
+    #[test]
+    fn solid_textures() {
+        let marble = Marble::new(3, WHITE, BLACK, 2.);
+        let p = Point3::new(0.3, -0.2, 0.8);
+
+        // Only the point matters, not where it is in texture space
+        assert_eq!(marble.color_at((0., 0.), &p), marble.color_at((0.7, 0.2), &p));
+        assert_eq!(
+            marble.color_at((0., 0.), &p),
+            Marble::new(3, WHITE, BLACK, 2.).color_at((0., 0.), &p)
+        );
+        let c = marble.color_at((0., 0.), &p);
+        assert!(c.r >= 0. && c.r <= 1.);
+
+        // Without distortion the rings are circles around the y axis
+        let mut wood = Wood::new(3, WHITE, BLACK, 4.);
+        wood.distortion = 0.;
+        assert_eq!(WHITE, wood.color_at((0., 0.), &Point3::new(0., 5., 0.)));
+        assert_eq!(
+            wood.color_at((0., 0.), &Point3::new(0.1, 0., 0.)),
+            wood.color_at((0., 0.), &Point3::new(0., 3., -0.1))
+        );
+        let half = wood.color_at((0., 0.), &Point3::new(0.125, 0., 0.));
+        assert!((half.r - 0.5).abs() < 0.001);
+
+        let noise = NoiseTexture::new(1, BLACK, WHITE, 4.);
+        assert_eq!(0.5 * WHITE, noise.color_at((0.3, 0.3), &Point3::new(0., 0., 0.)));
+    }
+}
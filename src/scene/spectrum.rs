@@ -0,0 +1,211 @@
+/// Light as a spectrum of wavelengths rather than red, green and blue, for
+/// materials whose index of refraction depends on the wavelength
+use super::Color;
+use crate::math::Random;
+
+/// The shortest and longest wavelengths, in nanometers, which are traced
+pub const MIN_WAVELENGTH: f32 = 380.;
+pub const MAX_WAVELENGTH: f32 = 780.;
+
+/**
+How the index of refraction of a material changes with the wavelength.
+Both models take the wavelength in micrometers, as their coefficients are
+usually given.  Glass has a higher index for blue light than for red, so
+it bends blue more, which is what spreads white light out into a rainbow.
+*/
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Dispersion {
+    /// `n = a + b / λ²`, a good fit for most glasses over visible light
+    Cauchy { a: f32, b: f32 },
+    /// `n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)`, which glass makers publish for their
+    /// glasses
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    /// Borosilicate crown glass, a common glass for lenses
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612, 0.231_792_34, 1.010_469_4],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+
+    /// Dense flint glass, which disperses strongly and makes good prisms
+    pub const SF11: Dispersion = Dispersion::Sellmeier {
+        b: [1.737_597, 0.313_747_35, 1.898_781],
+        c: [0.013_188_707, 0.062_306_814, 155.236_3],
+    };
+
+    /// The index of refraction for light of `wavelength` nanometers
+    pub fn index(&self, wavelength: f32) -> f32 {
+        let l = wavelength / 1000.;
+        let l2 = l * l;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2: f32 = 1. + (0..3).map(|k| b[k] * l2 / (l2 - c[k])).sum::<f32>();
+                n2.max(1.).sqrt()
+            }
+        }
+    }
+}
+
+/**
+Converts wavelengths to linear RGB colors through the CIE 1931 color
+matching functions, using the multi-lobe fit by Wyman, Sloan and Shirley,
+and the XYZ to linear sRGB matrix.
+
+The colors are scaled so that a flat spectrum is white, rather than the
+slightly pink of equal energy under sRGB's D65 white, which keeps the
+colors of a scene the same in spectral and RGB renders.
+*/
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ColorMatching {
+    scale: Color,
+}
+
+impl ColorMatching {
+    pub fn new() -> ColorMatching {
+        // Integrate over the traced range, one nanometer at a time
+        let steps = (MAX_WAVELENGTH - MIN_WAVELENGTH) as usize;
+        let total: Color = (0..steps)
+            .map(|k| linear_rgb(MIN_WAVELENGTH + k as f32 + 0.5))
+            .sum();
+        let mean = (1. / steps as f32) * total;
+        ColorMatching {
+            scale: Color::new(1. / mean.r, 1. / mean.g, 1. / mean.b),
+        }
+    }
+
+    /// The color of a unit of energy at `wavelength`.  The average of this
+    /// over wavelengths picked uniformly from the traced range, each
+    /// weighted by the spectrum there, is the color of the spectrum.
+    /// Channels can be negative for colors which sRGB can't show.
+    pub fn rgb(&self, wavelength: f32) -> Color {
+        self.scale * linear_rgb(wavelength)
+    }
+}
+
+impl Default for ColorMatching {
+    fn default() -> ColorMatching {
+        ColorMatching::new()
+    }
+}
+
+// A gaussian with different widths on either side of its peak
+fn lobe(wavelength: f32, mean: f32, below: f32, above: f32) -> f32 {
+    let t = (wavelength - mean) / if wavelength < mean { below } else { above };
+    (-0.5 * t * t).exp()
+}
+
+fn linear_rgb(wavelength: f32) -> Color {
+    let l = wavelength;
+    let x = 1.056 * lobe(l, 599.8, 37.9, 31.0) + 0.362 * lobe(l, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(l, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(l, 568.8, 46.9, 40.5) + 0.286 * lobe(l, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(l, 437.0, 11.8, 36.0) + 0.681 * lobe(l, 459.0, 26.0, 13.8);
+    Color::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+// How sharply the upsampling basis crosses over between channels
+const BASIS_WIDTH: f32 = 12.;
+
+/// The spectrum of `c` at `wavelength`, from smooth red, green and blue
+/// basis spectra which add up to one everywhere.  White and greys become
+/// flat spectra, and every color keeps its hue through `ColorMatching`.
+pub fn upsample(c: &Color, wavelength: f32) -> f32 {
+    let blue = 1. / (1. + ((wavelength - 490.) / BASIS_WIDTH).exp());
+    let red = 1. / (1. + ((590. - wavelength) / BASIS_WIDTH).exp());
+    let green = 1. - blue - red;
+    c.r * red + c.g * green + c.b * blue
+}
+
+/// Pick `n` evenly spaced wavelengths over the traced range, all shifted
+/// by the same random offset as with hero wavelength sampling.  The color
+/// matching functions are smooth, so this is far less noisy than jittering
+/// each wavelength on its own.
+pub fn sample_wavelengths(n: usize, rng: &mut Random) -> Vec<f32> {
+    let width = (MAX_WAVELENGTH - MIN_WAVELENGTH) / n as f32;
+    let offset = rng.next_f32();
+    (0..n)
+        .map(|k| MIN_WAVELENGTH + (k as f32 + offset) * width)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::colors::*;
+
+    #[test]
+    fn dispersion() {
+        // BK7 at the yellow helium line
+        assert!((Dispersion::BK7.index(587.6) - 1.5168).abs() < 0.0005);
+
+        for glass in &[
+            Dispersion::BK7,
+            Dispersion::SF11,
+            Dispersion::Cauchy {
+                a: 1.5046,
+                b: 0.0042,
+            },
+        ] {
+            assert!(glass.index(450.) > glass.index(550.));
+            assert!(glass.index(550.) > glass.index(650.));
+        }
+        assert!(Dispersion::SF11.index(550.) > Dispersion::BK7.index(550.));
+    }
+
+    #[test]
+    fn color_matching() {
+        let cmf = ColorMatching::new();
+        let mut rng = Random::new(1);
+
+        // A flat spectrum is white
+        let samples = sample_wavelengths(64, &mut rng);
+        let white: Color = samples.iter().map(|&l| cmf.rgb(l)).sum();
+        let white = (1. / 64.) * white;
+        for c in &[white.r, white.g, white.b] {
+            assert!((c - 1.).abs() < 0.02);
+        }
+
+        // Single wavelengths have the colors of the rainbow
+        let blue = cmf.rgb(450.);
+        assert!(blue.b > blue.r && blue.b > blue.g);
+        let green = cmf.rgb(530.);
+        assert!(green.g > green.r && green.g > green.b);
+        let red = cmf.rgb(640.);
+        assert!(red.r > red.g && red.r > red.b);
+        assert!(cmf.rgb(MAX_WAVELENGTH).r < 0.01 * red.r);
+
+        for l in &samples {
+            assert!(*l >= MIN_WAVELENGTH && *l < MAX_WAVELENGTH);
+        }
+    }
+
+    #[test]
+    fn upsampling() {
+        let cmf = ColorMatching::new();
+        let samples: Vec<f32> = (0..400).map(|k| MIN_WAVELENGTH + k as f32 + 0.5).collect();
+        let round_trip = |c: &Color| -> Color {
+            let sum: Color = samples.iter().map(|&l| upsample(c, l) * cmf.rgb(l)).sum();
+            (1. / samples.len() as f32) * sum
+        };
+
+        assert!((upsample(&WHITE, 420.) - 1.).abs() < 0.0001);
+        assert!((upsample(&(0.5 * WHITE), 700.) - 0.5).abs() < 0.0001);
+        let grey = round_trip(&(0.5 * WHITE));
+        assert!((grey.r - 0.5).abs() < 0.001 && (grey.b - 0.5).abs() < 0.001);
+
+        // Primaries keep their hue
+        let red = round_trip(&RED);
+        assert!(red.r > 2. * red.g && red.r > 2. * red.b);
+        let green = round_trip(&GREEN);
+        assert!(green.g > 2. * green.r && green.g > 2. * green.b);
+        let blue = round_trip(&BLUE);
+        assert!(blue.b > 2. * blue.r && blue.b > 2. * blue.g);
+    }
+}
@@ -1,17 +1,21 @@
-use crate::math::{Matrix, Point3, Ray, Vector3};
+use crate::math::{BoundingBox, Matrix, Point3, Ray, Vector3};
 
 use std::cell::*;
 use std::rc::Rc;
 
+use super::area_scale;
 use super::Intersection;
 use super::Material;
 use super::Renderable;
+use super::SurfaceSample;
 use super::TextureCoords;
+use super::Visibility;
 
 pub struct Sphere {
     id: i32,
     transform: Matrix,
     inv_transform: Matrix,
+    visibility: Visibility,
     material: Rc<RefCell<dyn Material>>,
     name: String,
 }
@@ -22,6 +26,7 @@ impl Sphere {
             id: 0,
             transform: Matrix::identity(),
             inv_transform: Matrix::identity(),
+            visibility: Visibility::all(),
             material: Rc::clone(&material),
             name: String::from("Sphere"),
         }
@@ -32,6 +37,7 @@ impl Sphere {
             id: 0,
             transform: Matrix::identity(),
             inv_transform: Matrix::identity(),
+            visibility: Visibility::all(),
             material: Rc::clone(&material),
             name: String::from(name),
         }
@@ -77,8 +83,8 @@ impl Renderable for Sphere {
                 let t = if t0 < 0. { t1 } else { t0 };
                 let entering = t0 > 0.;
                 let point = t * ray;
-                let normal = t * transformed_ray;
-                let mut normal = (self.inv_transform.transpose() * Vector3::from(normal)).norm();
+                let local_point = t * transformed_ray;
+                let mut normal = (self.inv_transform.transpose() * Vector3::from(local_point)).norm();
                 if !entering {
                     normal = -normal;
                 }
@@ -92,6 +98,11 @@ impl Renderable for Sphere {
                     normal,
                     entering,
                     tex_coord: Sphere::get_texture_coord(&normal),
+                    local_point,
+                    tangent: Vector3::new(-normal.z(), 0., normal.x()),
+                    outside_index: 1.,
+                    wavelength: None,
+                    vertex_color: None,
                 })
             }
         }
@@ -121,6 +132,37 @@ impl Renderable for Sphere {
     fn size(&self) -> usize {
         1
     }
+
+    fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    fn set_visibility(&mut self, visibility: Visibility) {
+        self.visibility = visibility;
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let unit = BoundingBox::new(&Point3::new(-1., -1., -1.), &Point3::new(1., 1., 1.));
+        Some(unit.transform(&self.transform))
+    }
+
+    fn sample_surface(&self, s: f32, t: f32) -> Option<SurfaceSample> {
+        use std::f32::consts::PI;
+
+        // Pick a point evenly over the unit sphere
+        let z = 1. - 2. * s;
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * PI * t;
+        let n = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+
+        let normal = (self.inv_transform.transpose() * n).norm();
+        Some(SurfaceSample {
+            point: self.transform * Point3::new(n.x(), n.y(), n.z()),
+            normal,
+            tex_coord: Sphere::get_texture_coord(&normal),
+            pdf: 1. / (4. * PI * area_scale(&self.transform, &n)),
+        })
+    }
 }
 
 fn solve_quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
@@ -174,6 +216,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn intersection_local_point() {
+        // The local point is on the unit sphere whatever the transform, so
+        // solid textures stick to the shape
+        let phong = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 1., 0.)));
+        let mut sph = Sphere::new(phong);
+        sph.set_transform(&(Matrix::translate(5., 0., 0.) * Matrix::scale(2., 2., 2.)));
+
+        let ray = Ray::new(&Point3::new(5., 0., -10.), &Vector3::new(0., 0., 1.));
+        let i = sph.intersect(&ray).unwrap();
+        assert!((i.point.z() + 2.).abs() < 0.001);
+        assert!((i.local_point.z() + 1.).abs() < 0.001);
+        assert!(i.local_point.x().abs() < 0.001);
+    }
+
     #[test]
     fn intersection_no_transform() {
         let phong = Rc::new(RefCell::new(Phong::new(WHITE, WHITE, WHITE, 60., 1., 0.)));
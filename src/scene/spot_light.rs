@@ -0,0 +1,273 @@
+/// A light which shines in a cone
+use std::rc::Rc;
+
+use crate::math::{BoundingBox, Point3, Vector3};
+
+use super::{
+    colors, luminance, shadow_transmittance, Attenuation, Color, ImageTexture, LightBounds,
+    LightSource, Scene, Texture, WrapMode,
+};
+
+/// A pattern projected by a `SpotLight`.  The pattern is stretched so that
+/// texture coordinates (0, 0) to (1, 1) cover the outer cone of the light.
+#[derive(Clone)]
+pub struct Gobo {
+    texture: Rc<dyn Texture>,
+}
+
+impl Gobo {
+    pub fn new(texture: Rc<dyn Texture>) -> Gobo {
+        Gobo { texture }
+    }
+
+    /// Load an sRGB image to use as a gobo.  It is clamped at its edges
+    /// rather than repeated.
+    pub fn open(path: &str) -> image::ImageResult<Gobo> {
+        let mut texture = ImageTexture::open(path)?;
+        texture.set_wrap(WrapMode::Clamp);
+        Ok(Gobo::new(Rc::new(texture)))
+    }
+
+    fn color(&self, u: f32, v: f32) -> Color {
+        self.texture.color((u, v))
+    }
+}
+
+// The gobo is projected onto a plane in front of the light, which the cone
+// only meets while it is narrower than a half space
+const MAX_OUTER: f32 = 89.;
+
+/**
+A spot light radiates energy with the intensity of `Color` from a point
+in a cone around `direction`.
+
+Points within `inner` degrees of the direction of the light receive the
+full energy of the light, points beyond `outer` degrees receive nothing
+and in between the energy falls off smoothly.  An `outer` angle wider
+than 89 degrees is clamped to 89, since the gobo could not be projected
+onto a wider cone, and `inner` is clamped to `outer`.
+
+An optional `Gobo` will tint the light with a pattern.  Like a `PointLight`
+the energy is scaled by an intensity and, optionally, falls off with
+distance.
+*/
+pub struct SpotLight {
+    pos: Point3,
+    direction: Vector3,
+    color: Color,
+    intensity: f32,
+    attenuation: Attenuation,
+    inner: f32,
+    outer: f32,
+    cos_inner: f32,
+    cos_outer: f32,
+    gobo: Option<Gobo>,
+
+    // axes of the gobo
+    u: Vector3,
+    v: Vector3,
+}
+
+impl SpotLight {
+    pub fn new(pos: Point3, direction: Vector3, color: Color, inner: f32, outer: f32) -> SpotLight {
+        let direction = direction.norm();
+        let outer = outer.min(MAX_OUTER);
+        let inner = inner.min(outer);
+
+        let w = if direction.cross(&Vector3::new(0., 1., 0.)).len() <= std::f32::EPSILON {
+            Vector3::new(1., 0., 0.)
+        } else {
+            Vector3::new(0., 1., 0.)
+        };
+        let u = w.cross(&direction).norm();
+        let v = direction.cross(&u).norm();
+
+        SpotLight {
+            pos,
+            direction,
+            color,
+            intensity: 1.,
+            attenuation: Attenuation::None,
+            inner,
+            outer,
+            cos_inner: inner.to_radians().cos(),
+            cos_outer: outer.to_radians().cos(),
+            gobo: None,
+            u,
+            v,
+        }
+    }
+
+    pub fn set_gobo(&mut self, gobo: Gobo) {
+        self.gobo = Some(gobo);
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    pub fn set_attenuation(&mut self, attenuation: Attenuation) {
+        self.attenuation = attenuation;
+    }
+
+    /// Fraction of the light's energy which reaches a point in direction
+    /// `to_point` from the light.
+    fn falloff(&self, to_point: &Vector3) -> f32 {
+        let cos_theta = to_point.dot(&self.direction);
+        if cos_theta >= self.cos_inner {
+            1.
+        } else if cos_theta <= self.cos_outer {
+            0.
+        } else {
+            let x = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+            x * x * (3. - 2. * x)
+        }
+    }
+
+    fn gobo_color(&self, to_point: &Vector3) -> Color {
+        match self.gobo {
+            None => colors::WHITE,
+            Some(ref gobo) => {
+                // Project onto a plane one unit in front of the light
+                let d = to_point.dot(&self.direction);
+                let tan_outer = self.outer.to_radians().tan();
+                let x = to_point.dot(&self.u) / (d * tan_outer);
+                let y = to_point.dot(&self.v) / (d * tan_outer);
+                gobo.color(0.5 + 0.5 * x, 0.5 - 0.5 * y)
+            }
+        }
+    }
+}
+
+impl LightSource for SpotLight {
+    fn get_energy(&self, scene: &Scene, point: &Point3) -> (Vector3, Color) {
+        let to_light = self.pos - point;
+        let dir_to_light = to_light.norm();
+        let to_point = -dir_to_light;
+
+        let falloff = self.falloff(&to_point);
+        let total_energy = if falloff <= 0. {
+            colors::BLACK
+        } else {
+            (falloff * self.intensity_at(to_light.len()))
+                * shadow_transmittance(scene, point, &self.pos)
+                * self.gobo_color(&to_point)
+                * self.color
+        };
+        (dir_to_light, total_energy)
+    }
+
+    fn intensity_at(&self, distance: f32) -> f32 {
+        self.intensity * self.attenuation.factor(distance)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: BoundingBox::new(&self.pos, &self.pos),
+            direction: self.direction,
+            cos_spread: self.cos_outer,
+            power: self.intensity * luminance(&self.color),
+        })
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "Position: ({}, {}, {}), Direction: ({}, {}, {}), Cone: ({}, {}), \
+             Color: ({}, {}, {}), Intensity: {}, Attenuation: {}",
+            self.pos.x(),
+            self.pos.y(),
+            self.pos.z(),
+            self.direction.x(),
+            self.direction.y(),
+            self.direction.z(),
+            self.inner,
+            self.outer,
+            self.color.r,
+            self.color.g,
+            self.color.b,
+            self.intensity,
+            self.attenuation
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::color::colors::*;
+    use crate::scene::TextureCoords;
+
+    fn left_red(tx: TextureCoords) -> Color {
+        if tx.0 < 0.5 {
+            RED
+        } else {
+            WHITE
+        }
+    }
+
+    #[test]
+    fn cone() {
+        let scene = Scene::new();
+        let light = SpotLight::new(
+            Point3::new(0., 4., 0.),
+            Vector3::new(0., -1., 0.),
+            WHITE,
+            10.,
+            20.,
+        );
+
+        let (dir, energy) = light.get_energy(&scene, &Point3::new(0., 0., 0.));
+        assert_eq!(Vector3::new(0., 1., 0.), dir);
+        assert_eq!(WHITE, energy);
+
+        // tan(15 degrees) * 4 puts the point halfway through the penumbra
+        let (_, energy) = light.get_energy(&scene, &Point3::new(1.0718, 0., 0.));
+        assert!(energy.r > 0. && energy.r < 1.);
+
+        let (_, energy) = light.get_energy(&scene, &Point3::new(4., 0., 0.));
+        assert_eq!(BLACK, energy);
+    }
+
+    #[test]
+    fn gobo() {
+        let scene = Scene::new();
+        let mut light = SpotLight::new(
+            Point3::new(0., 4., 0.),
+            Vector3::new(0., -1., 0.),
+            WHITE,
+            30.,
+            30.,
+        );
+        light.set_gobo(Gobo::new(Rc::new(left_red)));
+
+        let left = light.get_energy(&scene, &Point3::new(0., 0., -1.)).1;
+        let right = light.get_energy(&scene, &Point3::new(0., 0., 1.)).1;
+        assert_ne!(left, right);
+        assert_eq!(true, left == RED || right == RED);
+    }
+
+    #[test]
+    fn wide_gobo() {
+        let scene = Scene::new();
+        let mut light = SpotLight::new(
+            Point3::new(0., 4., 0.),
+            Vector3::new(0., -1., 0.),
+            WHITE,
+            60.,
+            120.,
+        );
+        light.set_gobo(Gobo::new(Rc::new(left_red)));
+
+        // Well past 45 degrees the pattern is still projected the right way
+        // around rather than blowing up
+        let left = light.get_energy(&scene, &Point3::new(0., 0., -6.)).1;
+        let right = light.get_energy(&scene, &Point3::new(0., 0., 6.)).1;
+        assert!(left.r.is_finite() && right.r.is_finite());
+        assert_ne!(left, right);
+        assert_eq!(BLACK, light.get_energy(&scene, &Point3::new(0., 5., 0.)).1);
+
+        // The outer cone is clamped to 89 degrees, so a point 95 degrees
+        // from the direction of the light is not lit
+        assert_eq!(BLACK, light.get_energy(&scene, &Point3::new(6., 4.5, 0.)).1);
+    }
+}
@@ -0,0 +1,528 @@
+/// Textures which give a color for each point of a surface
+use std::rc::Rc;
+
+use super::{luminance, Color, TextureCoords};
+use crate::math::Point3;
+
+/**
+A color which varies over a surface with the texture coordinates.  Textures
+are shared as `Rc<dyn Texture>` and built from one another, for example to
+scale a checkerboard or to blend two textures through a mask.
+
+A `Color` is a texture of a single color, and any function or closure of
+the texture coordinates is a texture as well.
+
+Solid textures, such as `Marble`, are defined in the space of the shape
+rather than on its surface and override `color_at`, which renderers call
+with the hit point before the shape's transform.
+*/
+pub trait Texture {
+    fn color(&self, tx: TextureCoords) -> Color;
+
+    /// The color at a hit with texture coordinates `tx` and the point `p` in
+    /// the space of the shape
+    fn color_at(&self, tx: TextureCoords, _p: &Point3) -> Color {
+        self.color(tx)
+    }
+}
+
+impl Texture for Color {
+    fn color(&self, _: TextureCoords) -> Color {
+        *self
+    }
+}
+
+impl<F: Fn(TextureCoords) -> Color> Texture for F {
+    fn color(&self, tx: TextureCoords) -> Color {
+        self(tx)
+    }
+}
+
+/// How texture coordinates outside of [0, 1] are mapped onto an image
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WrapMode {
+    /// Tile the image
+    Repeat,
+    /// Stretch the pixels on the edge of the image
+    Clamp,
+    /// Tile the image, flipping every other copy so that the edges meet
+    Mirror,
+}
+
+impl WrapMode {
+    fn wrap(&self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i >= size {
+                    2 * size - 1 - i
+                } else {
+                    i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+/// One level of the mipmap of an `ImageTexture`
+#[derive(Debug, Clone)]
+struct Level {
+    width: usize,
+    height: usize,
+    texels: Vec<Color>,
+}
+
+impl Level {
+    /// Half the size of `self`, each texel the average of the texels it
+    /// covers.
+    fn downsample(&self) -> Level {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = (2 * x, 2 * y);
+                let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+                let sum = self.get(x0, y0) + self.get(x1, y0) + self.get(x0, y1) + self.get(x1, y1);
+                texels.push(0.25 * sum);
+            }
+        }
+        Level {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> Color {
+        self.texels[y * self.width + x]
+    }
+
+    /// Blend the four texels around `tx`
+    fn bilinear(&self, tx: TextureCoords, wrap: WrapMode) -> Color {
+        let x = tx.0 * self.width as f32 - 0.5;
+        let y = tx.1 * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let xs = (
+            wrap.wrap(x0 as i64, self.width),
+            wrap.wrap(x0 as i64 + 1, self.width),
+        );
+        let ys = (
+            wrap.wrap(y0 as i64, self.height),
+            wrap.wrap(y0 as i64 + 1, self.height),
+        );
+        let top = (1. - fx) * self.get(xs.0, ys.0) + fx * self.get(xs.1, ys.0);
+        let bottom = (1. - fx) * self.get(xs.0, ys.1) + fx * self.get(xs.1, ys.1);
+        (1. - fy) * top + fy * bottom
+    }
+}
+
+/**
+A texture read from an image.  Texture coordinates (0, 0) are the top left
+corner of the image and (1, 1) the bottom right corner, and the colors are
+blended between the nearest pixels.
+
+To avoid aliasing where the image is shrunk, the texture keeps a mipmap of
+smaller and smaller copies of the image.  Lookups average the area of the
+image given by `set_footprint`, which should be about the size of a pixel
+of the render in texture coordinates.  The default of zero always uses the
+full size image.
+*/
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    levels: Vec<Level>,
+    wrap: WrapMode,
+    footprint: f32,
+}
+
+impl ImageTexture {
+    /// Load a color image, whose pixels are stored in sRGB
+    pub fn open(path: &str) -> image::ImageResult<ImageTexture> {
+        Ok(ImageTexture::from_image(&image::open(path)?.to_rgb(), true))
+    }
+
+    /// Load an image which stores data, such as roughness or normals, which
+    /// must be used as is rather than decoded from sRGB
+    pub fn open_linear(path: &str) -> image::ImageResult<ImageTexture> {
+        Ok(ImageTexture::from_image(
+            &image::open(path)?.to_rgb(),
+            false,
+        ))
+    }
+
+    pub fn from_image(img: &image::RgbImage, srgb: bool) -> ImageTexture {
+        let (width, height) = img.dimensions();
+        let decode = |v: u8| {
+            let v = v as f32 / 255.;
+            if srgb {
+                srgb_to_linear(v)
+            } else {
+                v
+            }
+        };
+        let texels = img
+            .pixels()
+            .map(|p| Color::new(decode(p[0]), decode(p[1]), decode(p[2])))
+            .collect();
+
+        let mut levels = vec![Level {
+            width: width as usize,
+            height: height as usize,
+            texels,
+        }];
+        loop {
+            let last = &levels[levels.len() - 1];
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+
+        ImageTexture {
+            levels,
+            wrap: WrapMode::Repeat,
+            footprint: 0.,
+        }
+    }
+
+    pub fn set_wrap(&mut self, wrap: WrapMode) {
+        self.wrap = wrap;
+    }
+
+    /// Set the width, in texture coordinates, of the area averaged by each
+    /// lookup
+    pub fn set_footprint(&mut self, footprint: f32) {
+        self.footprint = footprint.max(0.);
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    /// The average color of the area of the image `footprint` wide around
+    /// `tx`, blending between the two nearest levels of the mipmap.
+    pub fn sample(&self, tx: TextureCoords, footprint: f32) -> Color {
+        let size = self.width().max(self.height()) as f32;
+        let max_level = (self.levels.len() - 1) as f32;
+        let level = (footprint * size).max(1.).log2().min(max_level);
+
+        let lower = level.floor();
+        let color = self.levels[lower as usize].bilinear(tx, self.wrap);
+        let t = level - lower;
+        if t <= 0. {
+            return color;
+        }
+        let upper = self.levels[lower as usize + 1].bilinear(tx, self.wrap);
+        (1. - t) * color + t * upper
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn color(&self, tx: TextureCoords) -> Color {
+        self.sample(tx, self.footprint)
+    }
+}
+
+/// Squares of side 1 alternating between the colors `a` and `b`
+pub struct Checkerboard {
+    pub a: Color,
+    pub b: Color,
+}
+
+impl Checkerboard {
+    pub fn new(a: Color, b: Color) -> Checkerboard {
+        Checkerboard { a, b }
+    }
+}
+
+impl Texture for Checkerboard {
+    fn color(&self, tx: TextureCoords) -> Color {
+        if (tx.0.floor() as i64 + tx.1.floor() as i64) % 2 == 0 {
+            self.a
+        } else {
+            self.b
+        }
+    }
+}
+
+/// Stripes across the u axis, each 1/2 wide, alternating between `a` and
+/// `b`
+pub struct Stripes {
+    pub a: Color,
+    pub b: Color,
+}
+
+impl Stripes {
+    pub fn new(a: Color, b: Color) -> Stripes {
+        Stripes { a, b }
+    }
+}
+
+impl Texture for Stripes {
+    fn color(&self, tx: TextureCoords) -> Color {
+        if tx.0.rem_euclid(1.) < 0.5 {
+            self.a
+        } else {
+            self.b
+        }
+    }
+}
+
+/// Lines `width` wide of the color `line` on every whole texture
+/// coordinate, with the squares between them filled with `fill`
+pub struct Grid {
+    pub line: Color,
+    pub fill: Color,
+    pub width: f32,
+}
+
+impl Grid {
+    pub fn new(line: Color, fill: Color, width: f32) -> Grid {
+        Grid { line, fill, width }
+    }
+}
+
+impl Texture for Grid {
+    fn color(&self, tx: TextureCoords) -> Color {
+        let near = |x: f32| {
+            let d = x - x.round();
+            d.abs() < 0.5 * self.width
+        };
+        if near(tx.0) || near(tx.1) {
+            self.line
+        } else {
+            self.fill
+        }
+    }
+}
+
+/// Blends from `a` at u = 0 to `b` at u = 1
+pub struct Gradient {
+    pub a: Color,
+    pub b: Color,
+}
+
+impl Gradient {
+    pub fn new(a: Color, b: Color) -> Gradient {
+        Gradient { a, b }
+    }
+}
+
+impl Texture for Gradient {
+    fn color(&self, tx: TextureCoords) -> Color {
+        let t = tx.0.clamp(0., 1.);
+        (1. - t) * self.a + t * self.b
+    }
+}
+
+/**
+Moves `texture` around on the surface: the texture is scaled by `scale`,
+then rotated by `rotation` degrees and then moved by `offset`.  Scaling a
+checkerboard by (4, 4), for example, gives squares four times as large.
+*/
+pub struct UvTransform {
+    texture: Rc<dyn Texture>,
+    // Maps texture coordinates of the surface to those of `texture`, as the
+    // rows of a 2x3 matrix
+    to_texture: [[f32; 3]; 2],
+}
+
+impl UvTransform {
+    pub fn new(
+        texture: Rc<dyn Texture>,
+        scale: (f32, f32),
+        rotation: f32,
+        offset: (f32, f32),
+    ) -> UvTransform {
+        // Undo the offset, then the rotation, then the scale
+        let (sin, cos) = (-rotation.to_radians()).sin_cos();
+        let (su, sv) = (1. / scale.0, 1. / scale.1);
+        let (du, dv) = (-offset.0, -offset.1);
+        UvTransform {
+            texture,
+            to_texture: [
+                [su * cos, -su * sin, su * (cos * du - sin * dv)],
+                [sv * sin, sv * cos, sv * (sin * du + cos * dv)],
+            ],
+        }
+    }
+}
+
+impl UvTransform {
+    fn transform(&self, tx: TextureCoords) -> TextureCoords {
+        let m = &self.to_texture;
+        let u = m[0][0] * tx.0 + m[0][1] * tx.1 + m[0][2];
+        let v = m[1][0] * tx.0 + m[1][1] * tx.1 + m[1][2];
+        (u, v)
+    }
+}
+
+impl Texture for UvTransform {
+    fn color(&self, tx: TextureCoords) -> Color {
+        self.texture.color(self.transform(tx))
+    }
+
+    fn color_at(&self, tx: TextureCoords, p: &Point3) -> Color {
+        self.texture.color_at(self.transform(tx), p)
+    }
+}
+
+/// Blends from `a` where `mask` is black to `b` where it is white
+pub struct Mix {
+    pub a: Rc<dyn Texture>,
+    pub b: Rc<dyn Texture>,
+    pub mask: Rc<dyn Texture>,
+}
+
+impl Mix {
+    pub fn new(a: Rc<dyn Texture>, b: Rc<dyn Texture>, mask: Rc<dyn Texture>) -> Mix {
+        Mix { a, b, mask }
+    }
+}
+
+impl Texture for Mix {
+    fn color(&self, tx: TextureCoords) -> Color {
+        let t = luminance(&self.mask.color(tx)).clamp(0., 1.);
+        (1. - t) * self.a.color(tx) + t * self.b.color(tx)
+    }
+
+    fn color_at(&self, tx: TextureCoords, p: &Point3) -> Color {
+        let t = luminance(&self.mask.color_at(tx, p)).clamp(0., 1.);
+        (1. - t) * self.a.color_at(tx, p) + t * self.b.color_at(tx, p)
+    }
+}
+
+/// The product of two textures, such as a pattern darkened by dirt
+pub struct Multiply {
+    pub a: Rc<dyn Texture>,
+    pub b: Rc<dyn Texture>,
+}
+
+impl Multiply {
+    pub fn new(a: Rc<dyn Texture>, b: Rc<dyn Texture>) -> Multiply {
+        Multiply { a, b }
+    }
+}
+
+impl Texture for Multiply {
+    fn color(&self, tx: TextureCoords) -> Color {
+        self.a.color(tx) * self.b.color(tx)
+    }
+
+    fn color_at(&self, tx: TextureCoords, p: &Point3) -> Color {
+        self.a.color_at(tx, p) * self.b.color_at(tx, p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::color::colors::*;
+
+    // A 2x2 image: black and white on the top row, red and blue below
+    fn image() -> image::RgbImage {
+        image::RgbImage::from_raw(2, 2, vec![0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 255]).unwrap()
+    }
+
+    #[test]
+    fn lookup() {
+        let mut tex = ImageTexture::from_image(&image(), false);
+        assert_eq!(WHITE, tex.color((0.75, 0.25)));
+        assert_eq!(RED, tex.color((0.25, 0.75)));
+
+        // Halfway between the black and white pixels
+        assert_eq!(0.5 * WHITE, tex.color((0.5, 0.25)));
+
+        // Outside of the image
+        assert_eq!(BLUE, tex.color((1.75, 0.75)));
+        tex.set_wrap(WrapMode::Mirror);
+        assert_eq!(RED, tex.color((1.75, 0.75)));
+        tex.set_wrap(WrapMode::Clamp);
+        assert_eq!(BLUE, tex.color((5., 0.75)));
+    }
+
+    #[test]
+    fn mipmap() {
+        let mut tex = ImageTexture::from_image(&image(), false);
+        assert_eq!(2, tex.levels.len());
+
+        // A footprint the size of the image averages all of it
+        tex.set_footprint(1.);
+        assert_eq!(Color::new(0.5, 0.25, 0.5), tex.color((0.1, 0.1)));
+    }
+
+    #[test]
+    fn patterns() {
+        let checker = Checkerboard::new(WHITE, BLACK);
+        assert_eq!(WHITE, checker.color((0.5, 0.5)));
+        assert_eq!(BLACK, checker.color((1.5, 0.5)));
+        assert_eq!(WHITE, checker.color((-0.5, -0.5)));
+
+        let stripes = Stripes::new(RED, BLUE);
+        assert_eq!(RED, stripes.color((0.25, 7.)));
+        assert_eq!(BLUE, stripes.color((-0.25, 7.)));
+
+        let grid = Grid::new(BLACK, WHITE, 0.1);
+        assert_eq!(BLACK, grid.color((2.02, 0.5)));
+        assert_eq!(WHITE, grid.color((2.5, 0.5)));
+
+        assert_eq!(0.5 * WHITE, Gradient::new(BLACK, WHITE).color((0.5, 0.)));
+    }
+
+    #[test]
+    fn combinators() {
+        let checker: Rc<dyn Texture> = Rc::new(Checkerboard::new(WHITE, BLACK));
+
+        // Squares twice as large, moved by half a square
+        let big = UvTransform::new(checker.clone(), (2., 2.), 0., (1., 0.));
+        assert_eq!(WHITE, big.color((2.9, 0.5)));
+        assert_eq!(BLACK, big.color((3.1, 0.5)));
+
+        // A quarter turn swaps u and v
+        let stripes = Rc::new(Stripes::new(RED, BLUE));
+        let turned = UvTransform::new(stripes, (1., 1.), 90., (0., 0.));
+        assert_eq!(RED, turned.color((0.75, 0.25)));
+        assert_eq!(BLUE, turned.color((0.25, 0.75)));
+
+        let mix = Mix::new(Rc::new(RED), Rc::new(BLUE), checker.clone());
+        assert_eq!(BLUE, mix.color((0.5, 0.5)));
+        assert_eq!(RED, mix.color((1.5, 0.5)));
+
+        let tinted = Multiply::new(
+            checker,
+            Rc::new(|tx: TextureCoords| Color::new(tx.0, 1., 1.)),
+        );
+        assert_eq!(Color::new(0.5, 1., 1.), tinted.color((0.5, 0.5)));
+    }
+
+    #[test]
+    fn srgb() {
+        let tex = ImageTexture::from_image(
+            &image::RgbImage::from_raw(1, 1, vec![128, 0, 255]).unwrap(),
+            true,
+        );
+        let c = tex.color((0.5, 0.5));
+        assert!((c.r - 0.2158).abs() < 0.001);
+        assert_eq!(0., c.g);
+        assert_eq!(1., c.b);
+    }
+}
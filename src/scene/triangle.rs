@@ -1,8 +1,8 @@
 use std::cell::*;
 use std::rc::Rc;
 
-use super::{Intersection, Material, Renderable};
-use crate::math::{Matrix, Point3, Ray, Vector3};
+use super::{Intersection, Material, Renderable, SurfaceSample, Visibility};
+use crate::math::{BoundingBox, Matrix, Point3, Ray, Vector3};
 
 pub struct Triangle {
     id: i32,
@@ -10,6 +10,7 @@ pub struct Triangle {
     normal: Vector3,
     transform: Matrix,
     inv_transform: Matrix,
+    visibility: Visibility,
     material: Rc<RefCell<dyn Material>>,
 }
 
@@ -34,6 +35,7 @@ impl Triangle {
             normal,
             transform: Matrix::identity(),
             inv_transform: Matrix::identity(),
+            visibility: Visibility::all(),
             material: Rc::clone(&material),
         }
     }
@@ -90,6 +92,11 @@ impl Renderable for Triangle {
             normal,
             entering: det > 0.,
             tex_coord: (u, v),
+            local_point: t * ray,
+            tangent: v0v1,
+            outside_index: 1.,
+            wavelength: None,
+            vertex_color: None,
         })
     }
 
@@ -117,6 +124,38 @@ impl Renderable for Triangle {
     fn size(&self) -> usize {
         1
     }
+
+    fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    fn set_visibility(&mut self, visibility: Visibility) {
+        self.visibility = visibility;
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let (v0, v1, v2) = (&self.verts[0], &self.verts[1], &self.verts[2]);
+        Some(BoundingBox::new(v0, v0).grow(v1).grow(v2))
+    }
+
+    fn sample_surface(&self, s: f32, t: f32) -> Option<SurfaceSample> {
+        // Fold the unit square onto the triangle so that points are spread
+        // evenly over its area
+        let (u, v) = if s + t > 1. { (1. - s, 1. - t) } else { (s, t) };
+        let v0v1 = self.verts[1] - self.verts[0];
+        let v0v2 = self.verts[2] - self.verts[0];
+        let area = 0.5 * v0v1.cross(&v0v2).len();
+        if area <= std::f32::EPSILON {
+            return None;
+        }
+
+        Some(SurfaceSample {
+            point: self.verts[0] + (u * v0v1 + v * v0v2),
+            normal: self.normal,
+            tex_coord: (u, v),
+            pdf: 1. / area,
+        })
+    }
 }
 
 #[cfg(test)]
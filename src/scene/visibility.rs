@@ -0,0 +1,86 @@
+//! Control which rays can see a shape and which shapes a light reaches
+
+/// The reason a ray is being traced through the scene
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RayKind {
+    Camera,
+    Shadow,
+    Reflection,
+    Refraction,
+}
+
+/**
+Which kinds of rays a shape can be hit by.  A shape which is invisible to
+the camera can still show up in mirrors, and a shape which does not block
+shadow rays casts no shadow.
+*/
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Visibility {
+    pub camera: bool,
+    pub shadow: bool,
+    pub reflection: bool,
+    pub refraction: bool,
+}
+
+impl Visibility {
+    /// Visible to every kind of ray
+    pub fn all() -> Visibility {
+        Visibility {
+            camera: true,
+            shadow: true,
+            reflection: true,
+            refraction: true,
+        }
+    }
+
+    pub fn sees(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Reflection => self.reflection,
+            RayKind::Refraction => self.refraction,
+        }
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Visibility {
+        Visibility::all()
+    }
+}
+
+/// Which shapes, by id, a light illuminates
+#[derive(Debug, Clone, PartialEq)]
+pub enum LightLink {
+    All,
+    Only(Vec<i32>),
+    Except(Vec<i32>),
+}
+
+impl LightLink {
+    pub fn illuminates(&self, shape_id: i32) -> bool {
+        match self {
+            LightLink::All => true,
+            LightLink::Only(ids) => ids.contains(&shape_id),
+            LightLink::Except(ids) => !ids.contains(&shape_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        let mut v = Visibility::default();
+        assert_eq!(true, v.sees(RayKind::Camera));
+        v.camera = false;
+        assert_eq!(false, v.sees(RayKind::Camera));
+        assert_eq!(true, v.sees(RayKind::Reflection));
+
+        assert_eq!(true, LightLink::All.illuminates(3));
+        assert_eq!(false, LightLink::Only(vec![1, 2]).illuminates(3));
+        assert_eq!(false, LightLink::Except(vec![3]).illuminates(3));
+    }
+}